@@ -0,0 +1,119 @@
+//! Markdown Extra style abbreviations: a `*[HTML]: HyperText Markup Language`
+//! line makes every `HTML` in the document an `<abbr>` with that title.
+
+use crate::sourcemap::SourceMap;
+use pulldown_cmark::{CowStr, Event, Tag, TagEnd, TextMergeStream};
+use pulldown_cmark_escape::escape_html;
+use std::borrow::Cow;
+
+/// Abbreviations and what they stand for.
+pub type Abbreviations = Vec<(String, String)>;
+
+/// Parse a `*[HTML]: HyperText Markup Language` line.
+fn definition(line: &str) -> Option<(&str, &str)> {
+    let (abbr, title) = line.trim().strip_prefix("*[")?.split_once("]:")?;
+    (!abbr.trim().is_empty()).then(|| (abbr.trim(), title.trim()))
+}
+
+/// Remove abbreviation definitions from `md`, leaving code blocks alone.
+/// The map leads from the result back to `md`.
+pub fn definitions(md: &str) -> (Cow<'_, str>, Abbreviations, SourceMap) {
+    let mut map = SourceMap::default();
+    if !md.contains("*[") {
+        return (Cow::Borrowed(md), Vec::new(), map);
+    }
+    let mut out = String::with_capacity(md.len());
+    let mut abbreviations = Abbreviations::new();
+    let mut fence: Option<&str> = None;
+    let mut end = 0;
+    for line in md.split_inclusive('\n') {
+        end += line.len();
+        let trimmed = line.trim();
+        match fence {
+            Some(open) if trimmed.starts_with(open) => fence = None,
+            Some(_) => {}
+            None if trimmed.starts_with("```") || trimmed.starts_with("~~~") => {
+                fence = Some(&trimmed[..3]);
+            }
+            None => {
+                if let Some((abbr, title)) = definition(line) {
+                    abbreviations.push((abbr.to_string(), title.to_string()));
+                    continue;
+                }
+            }
+        }
+        map.copied(out.len(), end - line.len());
+        out.push_str(line);
+    }
+    // Longer abbreviations first, so `HTML5` is not matched as `HTML`.
+    abbreviations.sort_by_key(|(abbr, _)| std::cmp::Reverse(abbr.len()));
+    (Cow::Owned(out), abbreviations, map)
+}
+
+/// Wrap abbreviations in text events in `<abbr>` elements.
+pub fn expand<'a>(
+    events: impl Iterator<Item = Event<'a>>,
+    abbreviations: &Abbreviations,
+) -> Vec<Event<'a>> {
+    let mut in_metadata = false;
+    let mut out = Vec::new();
+    for event in TextMergeStream::new(events) {
+        match event {
+            Event::Start(Tag::MetadataBlock(_)) => in_metadata = true,
+            Event::End(TagEnd::MetadataBlock(_)) => in_metadata = false,
+            Event::Text(text) if !in_metadata => {
+                push_abbreviations(&mut out, &text, abbreviations);
+                continue;
+            }
+            _ => {}
+        }
+        out.push(event);
+    }
+    out
+}
+
+fn push_abbreviations<'a>(out: &mut Vec<Event<'a>>, text: &str, abbreviations: &Abbreviations) {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let mut plain = 0;
+    let mut i = 0;
+    while i < text.len() {
+        let at_word_start = !text[..i].ends_with(is_word);
+        let found = abbreviations.iter().find(|(abbr, _)| {
+            at_word_start
+                && text[i..].starts_with(abbr.as_str())
+                && !text[i + abbr.len()..].starts_with(is_word)
+        });
+        let Some((abbr, title)) = found else {
+            i += text[i..].chars().next().map_or(1, char::len_utf8);
+            continue;
+        };
+        if plain < i {
+            out.push(Event::Text(CowStr::from(text[plain..i].to_string())));
+        }
+        let mut html = String::from("<abbr title=\"");
+        _ = escape_html(&mut html, title);
+        html.push_str("\">");
+        _ = escape_html(&mut html, abbr);
+        html.push_str("</abbr>");
+        out.push(Event::InlineHtml(html.into()));
+        i += abbr.len();
+        plain = i;
+    }
+    if plain < text.len() {
+        out.push(Event::Text(CowStr::from(text[plain..].to_string())));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::markdown::{to_html, Options};
+
+    #[test]
+    fn abbreviations_are_wrapped() {
+        let md = "The HTML and HTML5 specs, not HTMLish.\n\n`HTML`\n\n```\n*[kept]: in code\n```\n\n*[HTML]: HyperText Markup Language\n*[HTML5]: HTML \"5\"\n";
+        assert_eq!(
+            to_html(md, &Options::default()),
+            "<p>The <abbr title=\"HyperText Markup Language\">HTML</abbr> and <abbr title=\"HTML &quot;5&quot;\">HTML5</abbr> specs, not HTMLish.</p>\n<p><code>HTML</code></p>\n<div class=\"code-block\"><button class=\"copy-code\" type=\"button\">Copy</button><pre><code>*[kept]: in code\n</code></pre></div>\n"
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! Rendering of terminal output with ANSI color escapes as styled HTML.
+
+use pulldown_cmark_escape::escape_html;
+use std::borrow::Cow;
+
+/// How escapes are written when terminal output is pasted as text.
+const TEXT_ESCAPES: &[&str] = &["\\x1b[", "\\x1B[", "\\033[", "\\e[", "\\u001b[", "^[["];
+
+const BASIC_NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Color {
+    /// One of the 16 colors of the terminal theme, styled by class.
+    Basic(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    /// Color `n` of the 256 color palette.
+    fn indexed(n: u8) -> Self {
+        match n {
+            0..=15 => Color::Basic(n),
+            16..=231 => {
+                let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+                let n = n - 16;
+                Color::Rgb(level(n / 36), level(n / 6 % 6), level(n % 6))
+            }
+            _ => {
+                let gray = 8 + (n - 232) * 10;
+                Color::Rgb(gray, gray, gray)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Style {
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+    strike: bool,
+    foreground: Option<Color>,
+    background: Option<Color>,
+}
+
+impl Style {
+    /// Apply the parameters of a Select Graphic Rendition sequence.
+    fn apply(&mut self, params: &str) {
+        let mut codes = params
+            .split([';', ':'])
+            .map(|p| p.parse::<u8>().unwrap_or(0));
+        while let Some(code) = codes.next() {
+            match code {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                9 => self.strike = true,
+                22 => (self.bold, self.dim) = (false, false),
+                23 => self.italic = false,
+                24 => self.underline = false,
+                29 => self.strike = false,
+                30..=37 => self.foreground = Some(Color::Basic(code - 30)),
+                39 => self.foreground = None,
+                40..=47 => self.background = Some(Color::Basic(code - 40)),
+                49 => self.background = None,
+                90..=97 => self.foreground = Some(Color::Basic(code - 90 + 8)),
+                100..=107 => self.background = Some(Color::Basic(code - 100 + 8)),
+                38 | 48 => {
+                    let color = match codes.next() {
+                        Some(5) => codes.next().map(Color::indexed),
+                        Some(2) => match (codes.next(), codes.next(), codes.next()) {
+                            (Some(r), Some(g), Some(b)) => Some(Color::Rgb(r, g, b)),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    if code == 38 {
+                        self.foreground = color;
+                    } else {
+                        self.background = color;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn open_tag(&self) -> Option<String> {
+        let mut classes = Vec::new();
+        let mut style = String::new();
+        for (on, class) in [
+            (self.bold, "ansi-bold"),
+            (self.dim, "ansi-dim"),
+            (self.italic, "ansi-italic"),
+            (self.underline, "ansi-underline"),
+            (self.strike, "ansi-strike"),
+        ] {
+            if on {
+                classes.push(class.to_string());
+            }
+        }
+        for (color, prefix, property) in [
+            (self.foreground, "ansi-", "color"),
+            (self.background, "ansi-bg-", "background-color"),
+        ] {
+            match color {
+                Some(Color::Basic(n)) => classes.push(format!(
+                    "{}{}{}",
+                    prefix,
+                    if n >= 8 { "bright-" } else { "" },
+                    BASIC_NAMES[n as usize % 8]
+                )),
+                Some(Color::Rgb(r, g, b)) => {
+                    style.push_str(&format!("{}: #{:02x}{:02x}{:02x};", property, r, g, b))
+                }
+                None => {}
+            }
+        }
+        if classes.is_empty() && style.is_empty() {
+            return None;
+        }
+        let mut tag = String::from("<span");
+        if !classes.is_empty() {
+            tag.push_str(&format!(" class=\"{}\"", classes.join(" ")));
+        }
+        if !style.is_empty() {
+            tag.push_str(&format!(" style=\"{}\"", style));
+        }
+        tag.push('>');
+        Some(tag)
+    }
+}
+
+/// Replace escapes written out as text, like `\e[31m`, with real ones.
+fn normalize(source: &str) -> Cow<'_, str> {
+    let mut source = Cow::Borrowed(source);
+    for escape in TEXT_ESCAPES {
+        if source.contains(escape) {
+            source = Cow::Owned(source.replace(escape, "\x1b["));
+        }
+    }
+    source
+}
+
+/// Render terminal output, styling text by its color escapes and dropping
+/// other control sequences.
+pub fn to_html(source: &str) -> String {
+    let source = normalize(source);
+    let mut html = String::with_capacity(source.len());
+    let mut style = Style::default();
+    let mut open = false;
+    let mut rest = source.as_ref();
+    while let Some(start) = rest.find('\x1b') {
+        _ = escape_html(&mut html, &rest[..start]);
+        rest = &rest[start + 1..];
+        if let Some(csi) = rest.strip_prefix('[') {
+            // Parameters, then a final byte from `@` to `~`.
+            let Some(end) = csi.find(|c: char| ('@'..='~').contains(&c)) else {
+                rest = "";
+                break;
+            };
+            rest = &csi[end + 1..];
+            if !csi[end..].starts_with('m') {
+                continue;
+            }
+            let mut next = style.clone();
+            next.apply(&csi[..end]);
+            if next != style {
+                if open {
+                    html.push_str("</span>");
+                }
+                let tag = next.open_tag();
+                open = tag.is_some();
+                html.push_str(&tag.unwrap_or_default());
+                style = next;
+            }
+        } else if let Some(osc) = rest.strip_prefix(']') {
+            // Titles and hyperlinks, ended by BEL or ST.
+            let end = osc.find(['\x07', '\x1b']).unwrap_or(osc.len());
+            rest = &osc[end..];
+            rest = rest
+                .strip_prefix('\x07')
+                .or(rest.strip_prefix("\x1b\\"))
+                .unwrap_or(rest);
+        } else {
+            // Two character escapes like `ESC =`.
+            rest = rest.get(1..).unwrap_or_default();
+        }
+    }
+    _ = escape_html(&mut html, rest);
+    if open {
+        html.push_str("</span>");
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_become_spans() {
+        assert_eq!(
+            to_html("\x1b[1;31merror\x1b[0m: <x>\x1b[K\n"),
+            "<span class=\"ansi-bold ansi-red\">error</span>: &lt;x&gt;\n"
+        );
+        assert_eq!(
+            to_html("\\e[92mok\\e[39m \x1b[38;5;196mhot\x1b[48;2;0;0;255m!\x1b[m"),
+            "<span class=\"ansi-bright-green\">ok</span> <span style=\"color: #ff0000;\">hot</span><span style=\"color: #ff0000;background-color: #0000ff;\">!</span>"
+        );
+        assert_eq!(to_html("\x1b]8;;https://a.b\x07link\x1b]8;;\x07"), "link");
+        assert_eq!(
+            to_html("^[[1mbold"),
+            "<span class=\"ansi-bold\">bold</span>"
+        );
+    }
+}
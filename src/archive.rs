@@ -0,0 +1,451 @@
+//! Reading entries of zip and tar archives without extracting them.
+
+use crate::inflate;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Largest file read from a zip archive.
+const MAX_ENTRY: u64 = 256 * 1024 * 1024;
+
+/// Largest `.tar.gz` archive, which is decompressed whole on every request.
+const MAX_TAR_GZ: usize = 64 * 1024 * 1024;
+
+/// File or directory stored in an archive.
+pub struct Entry {
+    /// Path inside the archive, without leading or trailing slashes.
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    /// Modification time as a Unix timestamp.
+    pub mtime: Option<u64>,
+}
+
+enum Format {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+fn format(path: &Path) -> Option<Format> {
+    let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+    if name.ends_with(".zip") {
+        Some(Format::Zip)
+    } else if name.ends_with(".tar") {
+        Some(Format::Tar)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(Format::TarGz)
+    } else {
+        None
+    }
+}
+
+/// Returns true if the file is an archive that can be browsed.
+pub fn is_archive(path: &Path) -> bool {
+    format(path).is_some()
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn trim_name(name: &str) -> String {
+    name.trim_start_matches("./").trim_matches('/').to_string()
+}
+
+/// List all entries of the archive.
+pub fn list(path: &Path) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    match format(path) {
+        Some(Format::Zip) => zip_entries(path, |entry, _| {
+            entries.push(entry);
+            Ok(false)
+        })?,
+        Some(Format::Tar) => tar_entries(File::open(path)?, |entry, _| {
+            entries.push(entry);
+            Ok(false)
+        })?,
+        Some(Format::TarGz) => {
+            let data = inflate::gunzip(&fs::read(path)?, MAX_TAR_GZ)?;
+            tar_entries(data.as_slice(), |entry, _| {
+                entries.push(entry);
+                Ok(false)
+            })?
+        }
+        None => return Err(invalid("not an archive")),
+    }
+    Ok(entries)
+}
+
+/// Read contents of the file `name` in the archive.
+pub fn read(path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+    let mut found = None;
+    let visit = |entry: Entry, data: &mut dyn FnMut() -> io::Result<Vec<u8>>| {
+        if entry.is_dir || entry.name != name {
+            return Ok(false);
+        }
+        found = Some(data()?);
+        Ok(true)
+    };
+    match format(path) {
+        Some(Format::Zip) => zip_entries(path, visit)?,
+        Some(Format::Tar) => tar_entries(File::open(path)?, visit)?,
+        Some(Format::TarGz) => {
+            let data = inflate::gunzip(&fs::read(path)?, MAX_TAR_GZ)?;
+            tar_entries(data.as_slice(), visit)?
+        }
+        None => return Err(invalid("not an archive")),
+    }
+    Ok(found)
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Call `visit` with every entry of a zip file and a function reading its data,
+/// until it returns true.
+fn zip_entries(
+    path: &Path,
+    mut visit: impl FnMut(Entry, &mut dyn FnMut() -> io::Result<Vec<u8>>) -> io::Result<bool>,
+) -> io::Result<()> {
+    const END_SIGNATURE: u32 = 0x06054b50;
+    const CENTRAL_SIGNATURE: u32 = 0x02014b50;
+    const LOCAL_SIGNATURE: u32 = 0x04034b50;
+
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+
+    // The end of central directory record is followed by a comment of up to 64 KiB.
+    let tail_len = len.min(22 + 65535);
+    let mut tail = vec![0; tail_len as usize];
+    file.seek(SeekFrom::Start(len - tail_len))?;
+    file.read_exact(&mut tail)?;
+    let end = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&i| u32_at(&tail, i) == END_SIGNATURE)
+        .ok_or_else(|| invalid("zip end of central directory not found"))?;
+    let count = u16_at(&tail, end + 10);
+    let size = u32_at(&tail, end + 12);
+    let offset = u32_at(&tail, end + 16);
+    if count == 0xffff || offset == 0xffffffff {
+        return Err(invalid("zip64 archives are not supported"));
+    }
+
+    if offset as u64 + size as u64 > len {
+        return Err(invalid("corrupt zip central directory"));
+    }
+    let mut central = vec![0; size as usize];
+    file.seek(SeekFrom::Start(offset as u64))?;
+    file.read_exact(&mut central)?;
+
+    let mut pos = 0;
+    for _ in 0..count {
+        if central.len() < pos + 46 || u32_at(&central, pos) != CENTRAL_SIGNATURE {
+            return Err(invalid("corrupt zip central directory"));
+        }
+        let method = u16_at(&central, pos + 10);
+        let compressed_size = u32_at(&central, pos + 20);
+        let size = u32_at(&central, pos + 24);
+        let name_len = u16_at(&central, pos + 28) as usize;
+        let extra_len = u16_at(&central, pos + 30) as usize;
+        let comment_len = u16_at(&central, pos + 32) as usize;
+        let local_offset = u32_at(&central, pos + 42);
+        let raw_name = central
+            .get(pos + 46..pos + 46 + name_len)
+            .ok_or_else(|| invalid("corrupt zip central directory"))?;
+        let name = String::from_utf8_lossy(raw_name);
+        pos += 46 + name_len + extra_len + comment_len;
+
+        let entry = Entry {
+            is_dir: name.ends_with('/'),
+            name: trim_name(&name),
+            size: size as u64,
+            mtime: None,
+        };
+        let mut data = || {
+            let mut header = [0; 30];
+            file.seek(SeekFrom::Start(local_offset as u64))?;
+            file.read_exact(&mut header)?;
+            if u32_at(&header, 0) != LOCAL_SIGNATURE {
+                return Err(invalid("corrupt zip local header"));
+            }
+            let skip = u16_at(&header, 26) as u64 + u16_at(&header, 28) as u64;
+            // Sizes come from the archive, check them before allocating.
+            let start = local_offset as u64 + 30 + skip;
+            if start + compressed_size as u64 > len {
+                return Err(invalid("corrupt zip entry size"));
+            }
+            if size as u64 > MAX_ENTRY || method == 0 && compressed_size as u64 > MAX_ENTRY {
+                return Err(invalid("zip entry is too large"));
+            }
+            file.seek(SeekFrom::Start(start))?;
+            let mut compressed = vec![0; compressed_size as usize];
+            file.read_exact(&mut compressed)?;
+            match method {
+                0 => Ok(compressed),
+                8 => inflate::inflate(&compressed, size as usize),
+                _ => Err(invalid("unsupported zip compression method")),
+            }
+        };
+        if visit(entry, &mut data)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Parse a NUL or space terminated octal number of a tar header.
+fn octal(field: &[u8]) -> u64 {
+    field
+        .iter()
+        .skip_while(|&&b| b == b' ')
+        .take_while(|b| b.is_ascii_digit())
+        .fold(0, |n, b| n * 8 + (b - b'0') as u64)
+}
+
+fn c_string(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).to_string()
+}
+
+/// Read `size` bytes of data padded to `padded` bytes, growing the buffer as
+/// data arrives rather than trusting the size in the header.
+fn read_padded(reader: &mut impl Read, size: u64, padded: u64) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(padded).read_to_end(&mut data)?;
+    if (data.len() as u64) < padded {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "truncated tar entry",
+        ));
+    }
+    data.truncate(size as usize);
+    Ok(data)
+}
+
+/// Get the `path` record of a pax extended header.
+fn pax_path(data: &[u8]) -> Option<String> {
+    let mut rest = data;
+    while !rest.is_empty() {
+        let space = rest.iter().position(|&b| b == b' ')?;
+        let len: usize = std::str::from_utf8(&rest[..space]).ok()?.parse().ok()?;
+        let record = rest.get(space + 1..len)?;
+        if let Some(path) = record.strip_prefix(b"path=") {
+            return Some(
+                String::from_utf8_lossy(path)
+                    .trim_end_matches('\n')
+                    .to_string(),
+            );
+        }
+        rest = &rest[len..];
+    }
+    None
+}
+
+/// Call `visit` with every entry of a tar stream and a function reading its data,
+/// until it returns true.
+fn tar_entries(
+    mut reader: impl Read,
+    mut visit: impl FnMut(Entry, &mut dyn FnMut() -> io::Result<Vec<u8>>) -> io::Result<bool>,
+) -> io::Result<()> {
+    let mut long_name = None;
+    let mut header = [0u8; 512];
+    loop {
+        if let Err(e) = reader.read_exact(&mut header) {
+            // Some writers omit the two zero blocks at the end.
+            return match e.kind() {
+                io::ErrorKind::UnexpectedEof => Ok(()),
+                _ => Err(e),
+            };
+        }
+        if header.iter().all(|&b| b == 0) {
+            return Ok(());
+        }
+
+        let size = octal(&header[124..136]);
+        let padded = size.div_ceil(512) * 512;
+        let kind = header[156];
+
+        // Long names are stored in a pseudo-entry before the entry itself.
+        if kind == b'L' || kind == b'x' {
+            let data = read_padded(&mut reader, size, padded)?;
+            long_name = match kind {
+                b'L' => Some(c_string(&data)),
+                _ => pax_path(&data),
+            };
+            continue;
+        }
+
+        let name = match long_name.take() {
+            Some(name) => name,
+            None if &header[257..262] == b"ustar" && header[345] != 0 => {
+                format!(
+                    "{}/{}",
+                    c_string(&header[345..500]),
+                    c_string(&header[..100])
+                )
+            }
+            None => c_string(&header[..100]),
+        };
+        let is_file = matches!(kind, b'0' | 0 | b'7');
+        let is_dir = kind == b'5';
+
+        let mut consumed = false;
+        if is_file || is_dir {
+            let entry = Entry {
+                is_dir: is_dir || name.ends_with('/'),
+                name: trim_name(&name),
+                size,
+                mtime: Some(octal(&header[136..148])),
+            };
+            let mut data = || {
+                consumed = true;
+                read_padded(&mut reader, size, padded)
+            };
+            if visit(entry, &mut data)? {
+                return Ok(());
+            }
+        }
+        if !consumed {
+            io::copy(&mut (&mut reader).take(padded), &mut io::sink())?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// Tar header of an entry, with `name` split at `/` into the ustar prefix
+    /// when `prefix` is set.
+    fn tar_header(name: &str, size: usize, kind: u8, prefix: bool) -> [u8; 512] {
+        let mut header = [0; 512];
+        let (prefix, name) = match name.rsplit_once('/') {
+            Some((dir, file)) if prefix => (dir, file),
+            _ => ("", name),
+        };
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[124..135].copy_from_slice(format!("{:011o}", size).as_bytes());
+        header[136..147].copy_from_slice(format!("{:011o}", 1_700_000_000).as_bytes());
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+        header
+    }
+
+    fn tar_entry(tar: &mut Vec<u8>, header: [u8; 512], data: &[u8]) {
+        tar.extend_from_slice(&header);
+        tar.extend_from_slice(data);
+        tar.resize(tar.len().div_ceil(512) * 512, 0);
+    }
+
+    #[test]
+    fn tar_entries_and_long_names() {
+        let long = format!("docs/{}.md", "x".repeat(120));
+        let pax_record = format!("path={}\n", long.replace("docs", "pax"));
+        let pax = format!("{} {}", pax_record.len() + 4, pax_record);
+        let mut tar = Vec::new();
+        tar_entry(&mut tar, tar_header("./docs/", 0, b'5', false), b"");
+        tar_entry(
+            &mut tar,
+            tar_header("docs/deep/a.md", 3, b'0', true),
+            b"# A",
+        );
+        tar_entry(
+            &mut tar,
+            tar_header("././@LongLink", long.len() + 1, b'L', false),
+            format!("{}\0", long).as_bytes(),
+        );
+        tar_entry(&mut tar, tar_header("docs/xxxx", 4, b'0', false), b"long");
+        tar_entry(
+            &mut tar,
+            tar_header("PaxHeader", pax.len(), b'x', false),
+            pax.as_bytes(),
+        );
+        tar_entry(&mut tar, tar_header("pax/xxxx", 3, b'0', false), b"pax");
+        tar_entry(&mut tar, tar_header("link", 0, b'2', false), b"");
+        tar.extend_from_slice(&[0; 1024]);
+
+        let dir = env::temp_dir().join("mdopen-test-archive-tar");
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("docs.tar");
+        fs::write(&path, &tar).unwrap();
+
+        let entries = list(&path).unwrap();
+        let names: Vec<(&str, bool, u64)> = entries
+            .iter()
+            .map(|e| (e.name.as_str(), e.is_dir, e.size))
+            .collect();
+        let pax_name = long.replace("docs", "pax");
+        assert_eq!(
+            names,
+            [
+                ("docs", true, 0),
+                ("docs/deep/a.md", false, 3),
+                (long.as_str(), false, 4),
+                (pax_name.as_str(), false, 3),
+            ]
+        );
+        assert_eq!(entries[1].mtime, Some(1_700_000_000));
+        assert_eq!(read(&path, "docs/deep/a.md").unwrap().unwrap(), b"# A");
+        assert_eq!(read(&path, &long).unwrap().unwrap(), b"long");
+        assert_eq!(read(&path, &pax_name).unwrap().unwrap(), b"pax");
+        assert!(read(&path, "docs").unwrap().is_none());
+
+        // A size larger than the archive is an error, not an allocation.
+        let mut lying = tar[..1536].to_vec();
+        lying[512 + 124..512 + 135].copy_from_slice(b"77777777777");
+        fs::write(&path, &lying).unwrap();
+        assert!(read(&path, "docs/deep/a.md").is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn zip_central_directory() {
+        // Made with Python's zipfile: a directory and a deflated file, with an
+        // archive comment after the central directory.
+        let zip = hex("504b03041400000000000000210000000000000000000000000005000000646f63732f504b030414000000080068524f5df100d40a12000000d100000009000000646f63732f612e6d64535608c92cc949e5e22a49ad285118060400504b0102140314000000000000002100000000000000000000000000050000000000000000000000800100000000646f63732f504b0102140314000000080068524f5df100d40a12000000d1000000090000000000000000000000800123000000646f63732f612e6d64504b050600000000020002006a0000005c00000009006120636f6d6d656e74");
+        let dir = env::temp_dir().join("mdopen-test-archive-zip");
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("docs.zip");
+        fs::write(&path, &zip).unwrap();
+
+        let entries = list(&path).unwrap();
+        let names: Vec<(&str, bool, u64)> = entries
+            .iter()
+            .map(|e| (e.name.as_str(), e.is_dir, e.size))
+            .collect();
+        assert_eq!(names, [("docs", true, 0), ("docs/a.md", false, 209)]);
+        let text = format!("# Title\n\n{}", "text ".repeat(40));
+        assert_eq!(read(&path, "docs/a.md").unwrap().unwrap(), text.as_bytes());
+        assert!(read(&path, "a.md").unwrap().is_none());
+
+        // Compressed sizes past the end of the file are refused.
+        let mut lying = zip.clone();
+        let central = 0x5c;
+        lying[central + 46 + 5 + 20..central + 46 + 5 + 24]
+            .copy_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&path, &lying).unwrap();
+        assert!(read(&path, "docs/a.md").is_err());
+        fs::write(&path, &zip[..zip.len() - 30]).unwrap();
+        assert!(list(&path).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
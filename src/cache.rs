@@ -0,0 +1,94 @@
+//! Rendered pages of markdown files, kept while `--reload` watches for
+//! changes, so tabs open on the same file and pages reconnecting after
+//! sleep don't render it again each time.
+//!
+//! Entries are for a modification time of the file. Any change seen by the
+//! watcher drops them all, as pages also depend on the files they include and
+//! on which files exist for their links.
+
+use crate::metrics;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Pages by file and variant, with the modification time they were rendered
+/// for.
+type Pages = HashMap<(PathBuf, String), (SystemTime, String)>;
+
+static PAGES: Mutex<Option<Pages>> = Mutex::new(None);
+
+/// Start caching pages, once something clears the cache on changes.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Forget every page.
+pub fn clear() {
+    *PAGES.lock().unwrap() = None;
+}
+
+/// Page of the file at `path`, rendered by `render` unless it is cached.
+///
+/// `variant` tells apart pages rendered differently from the same file.
+pub fn page(
+    path: &Path,
+    variant: &str,
+    render: impl FnOnce() -> io::Result<String>,
+) -> io::Result<String> {
+    let modified = fs::metadata(path).and_then(|m| m.modified());
+    let (true, Ok(modified)) = (ENABLED.load(Ordering::Relaxed), modified) else {
+        return render();
+    };
+    let key = (path.to_path_buf(), variant.to_string());
+    let mut pages = PAGES.lock().unwrap();
+    if let Some((_, html)) = pages
+        .get_or_insert_with(HashMap::new)
+        .get(&key)
+        .filter(|(time, _)| *time == modified)
+    {
+        metrics::record_cache_hit();
+        return Ok(html.clone());
+    }
+    drop(pages);
+    let html = render()?;
+    PAGES
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(key, (modified, html.clone()));
+    Ok(html)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn pages_are_rendered_again_when_cleared() {
+        let dir = env::temp_dir().join("mdopen-test-cache");
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.md");
+        fs::write(&path, "# A").unwrap();
+        enable();
+
+        let render = |html: &str| page(&path, "", || Ok(html.to_string())).unwrap();
+        assert_eq!(render("first"), "first");
+        assert_eq!(render("second"), "first");
+        assert_eq!(
+            page(&path, "index", || Ok("index".into())).unwrap(),
+            "index"
+        );
+        clear();
+        assert_eq!(render("third"), "third");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
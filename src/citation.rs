@@ -0,0 +1,654 @@
+//! Pandoc style `[@key]` citations, resolved against a BibTeX or CSL JSON
+//! bibliography and rendered author-date, with a list of references at the
+//! end of the document.
+
+use crate::{json, links};
+use log::warn;
+use pulldown_cmark::{CowStr, Event, Tag, TagEnd, TextMergeStream};
+use pulldown_cmark_escape::{escape_href, escape_html};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Default, PartialEq)]
+struct Name {
+    family: String,
+    given: String,
+}
+
+/// A bibliography entry, with only the fields shown in references.
+#[derive(Debug, Default)]
+struct Entry {
+    authors: Vec<Name>,
+    year: Option<String>,
+    title: Option<String>,
+    /// Journal or book the work appeared in.
+    container: Option<String>,
+    publisher: Option<String>,
+    url: Option<String>,
+}
+
+impl Entry {
+    /// `Doe`, `Doe and Roe` or `Doe et al.`
+    fn author_label(&self) -> String {
+        match self.authors.as_slice() {
+            [] => self.title.clone().unwrap_or_default(),
+            [a] => a.family.clone(),
+            [a, b] => format!("{} and {}", a.family, b.family),
+            [a, ..] => format!("{} et al.", a.family),
+        }
+    }
+
+    fn year(&self) -> &str {
+        self.year.as_deref().unwrap_or("n.d.")
+    }
+
+    /// Write the entry in Chicago author-date style.
+    fn write_reference(&self, html: &mut String) {
+        for (i, name) in self.authors.iter().enumerate() {
+            let name = match (i, name.given.is_empty()) {
+                (_, true) => name.family.clone(),
+                (0, false) => format!("{}, {}", name.family, name.given),
+                (_, false) => format!("{} {}", name.given, name.family),
+            };
+            if i > 0 {
+                html.push_str(if self.authors.len() == 2 {
+                    " and "
+                } else {
+                    ", "
+                });
+                if self.authors.len() > 2 && i == self.authors.len() - 1 {
+                    html.push_str("and ");
+                }
+            }
+            _ = escape_html(&mut *html, &name);
+        }
+        if !self.authors.is_empty() {
+            // Initials already end with a period.
+            if !html.ends_with('.') {
+                html.push('.');
+            }
+            html.push(' ');
+        }
+        html.push_str(self.year());
+        html.push_str(". ");
+        match (&self.title, &self.container) {
+            (Some(title), Some(container)) => {
+                html.push('“');
+                _ = escape_html(&mut *html, title.trim_end_matches('.'));
+                html.push_str(".” <em>");
+                _ = escape_html(&mut *html, container);
+                html.push_str("</em>.");
+            }
+            (Some(title), None) => {
+                html.push_str("<em>");
+                _ = escape_html(&mut *html, title.trim_end_matches('.'));
+                html.push_str("</em>.");
+            }
+            (None, _) => {}
+        }
+        if let Some(publisher) = &self.publisher {
+            html.push(' ');
+            _ = escape_html(&mut *html, publisher.trim_end_matches('.'));
+            html.push('.');
+        }
+        if let Some(url) = &self.url {
+            html.push_str(" <a href=\"");
+            _ = escape_href(&mut *html, url);
+            html.push_str("\">");
+            _ = escape_html(&mut *html, url);
+            html.push_str("</a>.");
+        }
+    }
+}
+
+/// Entries of a bibliography file, by citation key.
+#[derive(Debug, Default)]
+pub struct Bibliography {
+    entries: HashMap<String, Entry>,
+}
+
+impl Bibliography {
+    /// Read a `.json` CSL file, or a BibTeX file otherwise.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        if path.extension().is_some_and(|ext| ext == "json") {
+            parse_csl_json(&source)
+        } else {
+            Ok(parse_bibtex(&source))
+        }
+    }
+}
+
+/// Find the bibliography of a document: the `bibliography` field of its
+/// frontmatter, relative to the file, or else `default`.
+pub fn find(
+    metadata: &[(String, String)],
+    default: Option<&Path>,
+    base: Option<&links::Base>,
+) -> Option<Bibliography> {
+    let field = metadata
+        .iter()
+        .find(|(key, _)| key == "bibliography")
+        .map(|(_, value)| value.as_str())
+        .filter(|value| !value.is_empty());
+    let path = match (field, base) {
+        (Some(field), Some(base)) => {
+            let path = base.dir.join(field);
+            // Like includes, bibliographies must be under the served directory.
+            let inside = path
+                .canonicalize()
+                .ok()
+                .zip(base.root.canonicalize().ok())
+                .is_some_and(|(path, root)| path.starts_with(root));
+            if !inside {
+                warn!("cannot read bibliography {}: not found", field);
+                return None;
+            }
+            path
+        }
+        _ => default?.to_path_buf(),
+    };
+    Bibliography::load(&path)
+        .map_err(|e| warn!("cannot read bibliography {}: {}", path.display(), e))
+        .ok()
+}
+
+/// Remove TeX grouping and common escapes from a BibTeX value.
+fn clean_tex(value: &str) -> String {
+    let value = value
+        .replace(['{', '}'], "")
+        .replace("\\&", "&")
+        .replace("\\%", "%")
+        .replace("\\_", "_")
+        .replace("---", "—")
+        .replace("--", "–")
+        .replace('~', "\u{a0}");
+    value.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Split `value` on ` and ` outside braces, as BibTeX name lists are.
+fn split_names(value: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in value.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ if depth == 0 && value[i..].starts_with(" and ") => {
+                names.push(&value[start..i]);
+                start = i + 5;
+            }
+            _ => {}
+        }
+    }
+    names.push(&value[start..]);
+    names
+}
+
+/// Parse `Doe, Jane` or `Jane Doe`; a braced `{World Health Organization}`
+/// is kept whole as a family name.
+fn parse_name(name: &str) -> Name {
+    let name = name.trim();
+    if name.starts_with('{') && name.ends_with('}') {
+        return Name {
+            family: clean_tex(name),
+            given: String::new(),
+        };
+    }
+    let name = clean_tex(name);
+    match name.split_once(',') {
+        Some((family, given)) => Name {
+            family: family.trim().to_string(),
+            given: given.trim().to_string(),
+        },
+        None => match name.rsplit_once(' ') {
+            Some((given, family)) => Name {
+                family: family.to_string(),
+                given: given.to_string(),
+            },
+            None => Name {
+                family: name,
+                given: String::new(),
+            },
+        },
+    }
+}
+
+/// Parse the entries of a BibTeX file, skipping `@string` and `@comment`.
+fn parse_bibtex(source: &str) -> Bibliography {
+    let mut bibliography = Bibliography::default();
+    let mut rest = source;
+    while let Some(at) = rest.find('@') {
+        rest = &rest[at + 1..];
+        let Some(open) = rest.find(['{', '(']) else {
+            break;
+        };
+        let kind = rest[..open].trim().to_lowercase();
+        rest = &rest[open + 1..];
+        if matches!(kind.as_str(), "string" | "comment" | "preamble") {
+            continue;
+        }
+        let Some(comma) = rest.find(',') else {
+            break;
+        };
+        let key = rest[..comma].trim().to_string();
+        rest = &rest[comma + 1..];
+
+        let mut fields = HashMap::new();
+        loop {
+            rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+            if rest.is_empty() || rest.starts_with(['}', ')']) {
+                rest = rest.get(1..).unwrap_or_default();
+                break;
+            }
+            let Some(eq) = rest.find('=') else {
+                rest = "";
+                break;
+            };
+            let name = rest[..eq].trim().to_lowercase();
+            rest = rest[eq + 1..].trim_start();
+            let (value, len) = bibtex_value(rest);
+            fields.insert(name, value);
+            rest = &rest[len..];
+        }
+
+        let field = |name: &str| fields.get(name).map(|value| clean_tex(value));
+        let names = fields.get("author").or(fields.get("editor"));
+        let entry = Entry {
+            authors: names
+                .map(|names| split_names(names).into_iter().map(parse_name).collect())
+                .unwrap_or_default(),
+            year: field("year")
+                .or_else(|| field("date").map(|date| date.chars().take(4).collect())),
+            title: field("title"),
+            container: field("journal")
+                .or_else(|| field("journaltitle"))
+                .or_else(|| field("booktitle")),
+            publisher: field("publisher"),
+            url: field("doi")
+                .map(|doi| format!("https://doi.org/{}", doi))
+                .or_else(|| field("url")),
+        };
+        bibliography.entries.insert(key, entry);
+    }
+    bibliography
+}
+
+/// Read a `{braced}`, `"quoted"` or bare field value, returning it with the
+/// braces of its delimiters removed, and its length.
+fn bibtex_value(text: &str) -> (String, usize) {
+    let bytes = text.as_bytes();
+    match bytes.first() {
+        Some(&open @ (b'{' | b'"')) => {
+            let mut depth = 0;
+            for (i, &b) in bytes.iter().enumerate().skip(1) {
+                match b {
+                    b'{' => depth += 1,
+                    b'}' if depth == 0 && open == b'{' => return (text[1..i].to_string(), i + 1),
+                    b'}' => depth -= 1,
+                    b'"' if depth == 0 && open == b'"' => return (text[1..i].to_string(), i + 1),
+                    _ => {}
+                }
+            }
+            (text[1..].to_string(), text.len())
+        }
+        _ => {
+            let len = text.find([',', '}', ')', '\n']).unwrap_or(text.len());
+            (text[..len].trim().to_string(), len)
+        }
+    }
+}
+
+/// Parse a CSL JSON array of items, as exported by Zotero.
+fn parse_csl_json(source: &str) -> Result<Bibliography, String> {
+    let value = json::parse(source).map_err(|e| e.to_string())?;
+    let json::Value::Array(items) = value else {
+        return Err("expected an array of items".to_string());
+    };
+    let mut bibliography = Bibliography::default();
+    for item in &items {
+        let Some(id) = item.get("id").and_then(json::Value::as_str) else {
+            continue;
+        };
+        let text = |key: &str| {
+            item.get(key)
+                .and_then(json::Value::as_str)
+                .map(str::to_string)
+        };
+        let authors = match item.get("author").or(item.get("editor")) {
+            Some(json::Value::Array(names)) => names
+                .iter()
+                .map(|name| {
+                    let part = |key| name.get(key).and_then(json::Value::as_str);
+                    Name {
+                        family: part("family")
+                            .or(part("literal"))
+                            .unwrap_or_default()
+                            .to_string(),
+                        given: part("given").unwrap_or_default().to_string(),
+                    }
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        let issued = item.get("issued");
+        let year = match issued.and_then(|issued| issued.get("date-parts")) {
+            Some(json::Value::Array(parts)) => match parts.first() {
+                Some(json::Value::Array(part)) => match part.first() {
+                    Some(json::Value::Number(year)) => Some(year.to_string()),
+                    Some(json::Value::String(year)) => Some(year.clone()),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => issued
+                .and_then(|issued| issued.get("raw").or(issued.get("literal")))
+                .and_then(json::Value::as_str)
+                .map(|date| date.chars().take(4).collect()),
+        };
+        let entry = Entry {
+            authors,
+            year,
+            title: text("title"),
+            container: text("container-title"),
+            publisher: text("publisher"),
+            url: text("DOI")
+                .map(|doi| format!("https://doi.org/{}", doi))
+                .or_else(|| text("URL")),
+        };
+        bibliography.entries.insert(id.to_string(), entry);
+    }
+    Ok(bibliography)
+}
+
+/// Length of the citation key at the start of `text`.
+///
+/// Keys may contain internal punctuation, as in `doe:2020.a`, but not end with it.
+fn key_len(text: &str) -> usize {
+    let len = text
+        .find(|c: char| !c.is_alphanumeric() && !"_-:./#+".contains(c))
+        .unwrap_or(text.len());
+    let key = text[..len].trim_end_matches(|c: char| !c.is_alphanumeric() && c != '_');
+    if key.starts_with(|c: char| c.is_alphanumeric() || c == '_') {
+        key.len()
+    } else {
+        0
+    }
+}
+
+/// One `prefix @key, locator` part of a bracketed citation.
+struct Cite<'a> {
+    prefix: &'a str,
+    key: &'a str,
+    suppress_author: bool,
+    locator: &'a str,
+}
+
+/// Parse the inside of `[see @doe, p. 3; -@roe]`, if it is a citation.
+fn parse_cites(inner: &str) -> Option<Vec<Cite<'_>>> {
+    inner
+        .split(';')
+        .map(|part| {
+            let at = part
+                .match_indices('@')
+                .map(|(i, _)| i)
+                .find(|&i| i == 0 || part[..i].ends_with([' ', '-']))?;
+            let len = key_len(&part[at + 1..]);
+            if len == 0 {
+                return None;
+            }
+            let prefix = &part[..at];
+            let suppress_author = prefix.ends_with('-');
+            Some(Cite {
+                prefix: prefix.trim_end_matches('-').trim(),
+                key: &part[at + 1..at + 1 + len],
+                suppress_author,
+                locator: part[at + 1 + len..].trim().trim_start_matches(',').trim(),
+            })
+        })
+        .collect()
+}
+
+/// Turn citations in text events into author-date links to references, and
+/// list the cited works at the end.
+pub fn expand<'a>(
+    events: impl Iterator<Item = Event<'a>>,
+    bibliography: &Bibliography,
+) -> Vec<Event<'a>> {
+    let mut cited = Vec::new();
+    // Citations are not recognized in code, frontmatter or links.
+    let mut verbatim = 0;
+    let mut out = Vec::new();
+    // Brackets are separate text events.
+    for event in TextMergeStream::new(events) {
+        match &event {
+            Event::Start(
+                Tag::CodeBlock(_) | Tag::Link { .. } | Tag::Image { .. } | Tag::MetadataBlock(_),
+            ) => verbatim += 1,
+            Event::End(
+                TagEnd::CodeBlock | TagEnd::Link | TagEnd::Image | TagEnd::MetadataBlock(_),
+            ) => verbatim -= 1,
+            Event::Text(text) if verbatim == 0 && text.contains('@') => {
+                push_citations(&mut out, text, bibliography, &mut cited);
+                continue;
+            }
+            _ => {}
+        }
+        out.push(event);
+    }
+
+    let mut references: Vec<_> = cited
+        .iter()
+        .filter_map(|key| Some((key, bibliography.entries.get(key)?)))
+        .collect();
+    if references.is_empty() {
+        return out;
+    }
+    references
+        .sort_by(|(_, a), (_, b)| (a.author_label(), a.year()).cmp(&(b.author_label(), b.year())));
+    let mut html = String::from("<div id=\"refs\" class=\"references\" role=\"list\">\n");
+    for (key, entry) in references {
+        html.push_str("<div id=\"ref-");
+        _ = escape_html(&mut html, key);
+        html.push_str("\" class=\"csl-entry\" role=\"listitem\">");
+        entry.write_reference(&mut html);
+        html.push_str("</div>\n");
+    }
+    html.push_str("</div>\n");
+    out.push(Event::Html(html.into()));
+    out
+}
+
+fn push_citation_link(html: &mut String, key: &str, label: &str) {
+    html.push_str("<a href=\"#ref-");
+    _ = escape_href(&mut *html, key);
+    html.push_str("\">");
+    _ = escape_html(&mut *html, label);
+    html.push_str("</a>");
+}
+
+fn push_missing(html: &mut String, key: &str) {
+    html.push_str("<span class=\"citation-missing\" title=\"Not in the bibliography\">");
+    _ = escape_html(&mut *html, key);
+    html.push_str("?</span>");
+}
+
+fn push_citations<'a>(
+    out: &mut Vec<Event<'a>>,
+    text: &str,
+    bibliography: &Bibliography,
+    cited: &mut Vec<String>,
+) {
+    let mut cite = |key: &str| {
+        if !cited.iter().any(|k| k == key) {
+            cited.push(key.to_string());
+        }
+        bibliography.entries.get(key)
+    };
+    let mut plain = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(['[', '@']) {
+        let mut html = String::new();
+        let len;
+        if rest[start..].starts_with('[') {
+            let Some(cites) = rest[start + 1..]
+                .find(']')
+                .and_then(|end| Some((end, parse_cites(&rest[start + 1..start + 1 + end])?)))
+            else {
+                plain.push_str(&rest[..start + 1]);
+                rest = &rest[start + 1..];
+                continue;
+            };
+            len = cites.0 + 2;
+            // (see Doe 1999, p. 3; Roe 2001)
+            html.push_str("<span class=\"citation\">(");
+            for (i, part) in cites.1.iter().enumerate() {
+                if i > 0 {
+                    html.push_str("; ");
+                }
+                if !part.prefix.is_empty() {
+                    _ = escape_html(&mut html, part.prefix);
+                    html.push(' ');
+                }
+                match cite(part.key) {
+                    Some(entry) => {
+                        let label = match part.suppress_author {
+                            true => entry.year().to_string(),
+                            false => format!("{} {}", entry.author_label(), entry.year()),
+                        };
+                        push_citation_link(&mut html, part.key, &label);
+                    }
+                    None => push_missing(&mut html, part.key),
+                }
+                if !part.locator.is_empty() {
+                    html.push_str(", ");
+                    _ = escape_html(&mut html, part.locator);
+                }
+            }
+            html.push_str(")</span>");
+        } else {
+            // A bare `@doe` is only a citation of a known key, so that
+            // mentions and addresses are left alone.
+            let key = &rest[start + 1..start + 1 + key_len(&rest[start + 1..])];
+            let before = rest[..start].chars().last().or(plain.chars().last());
+            let at_word_start = before.is_none_or(|c| c.is_whitespace() || c == '(');
+            let entry = match at_word_start && !key.is_empty() {
+                true => bibliography.entries.get(key),
+                false => None,
+            };
+            let Some(entry) = entry else {
+                plain.push_str(&rest[..start + 1]);
+                rest = &rest[start + 1..];
+                continue;
+            };
+            cite(key);
+            len = key.len() + 1;
+            // Doe (1999)
+            html.push_str("<span class=\"citation\">");
+            _ = escape_html(&mut html, &entry.author_label());
+            html.push_str(" (");
+            push_citation_link(&mut html, key, entry.year());
+            html.push_str(")</span>");
+        }
+        plain.push_str(&rest[..start]);
+        if !plain.is_empty() {
+            out.push(Event::Text(CowStr::from(std::mem::take(&mut plain))));
+        }
+        out.push(Event::InlineHtml(html.into()));
+        rest = &rest[start + len..];
+    }
+    plain.push_str(rest);
+    if !plain.is_empty() {
+        out.push(Event::Text(CowStr::from(plain)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markdown::{to_html, Options};
+    use std::{env, fs};
+
+    const BIBTEX: &str = r#"
+@string{acm = "ACM"}
+@article{doe99,
+  author = {Doe, Jane and {World Health Organization}},
+  title = {On {Markdown} Previews},
+  journal = "Journal of Text",
+  year = 1999,
+  doi = {10.1/x},
+}
+@book{roe:2001.a,
+  author = {Richard Roe and Ann Poe and Bo Loe},
+  title = {Books \& More},
+  date = {2001-05-01},
+}
+"#;
+
+    #[test]
+    fn bibtex_entries() {
+        let bibliography = parse_bibtex(BIBTEX);
+        let doe = &bibliography.entries["doe99"];
+        assert_eq!(doe.author_label(), "Doe and World Health Organization");
+        assert_eq!(doe.year(), "1999");
+        assert_eq!(doe.title.as_deref(), Some("On Markdown Previews"));
+        let roe = &bibliography.entries["roe:2001.a"];
+        assert_eq!(roe.author_label(), "Roe et al.");
+        assert_eq!(roe.year(), "2001");
+        assert_eq!(roe.title.as_deref(), Some("Books & More"));
+        assert_eq!(bibliography.entries.len(), 2);
+    }
+
+    #[test]
+    fn csl_json_entries() {
+        let bibliography = parse_csl_json(
+            r#"[{"id": "doe99", "author": [{"family": "Doe", "given": "Jane"}],
+                 "issued": {"date-parts": [[1999, 5]]}, "title": "T", "URL": "https://x.org"}]"#,
+        )
+        .unwrap();
+        let doe = &bibliography.entries["doe99"];
+        assert_eq!(doe.author_label(), "Doe");
+        assert_eq!(doe.year(), "1999");
+        assert_eq!(doe.url.as_deref(), Some("https://x.org"));
+    }
+
+    #[test]
+    fn citations_and_references() {
+        let dir = env::temp_dir().join("mdopen-test-citations");
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("refs.bib"), BIBTEX).unwrap();
+        let options = Options {
+            bibliography: Some(dir.join("refs.bib")),
+            ..Options::default()
+        };
+        let html = to_html(
+            "As @roe:2001.a shows [see @doe99, p. 3; -@roe:2001.a], not [@nope] or me@doe99.\n\n`[@doe99]`\n",
+            &options,
+        );
+        assert!(html.contains(
+            r##"As <span class="citation">Roe et al. (<a href="#ref-roe:2001.a">2001</a>)</span> shows "##
+        ));
+        assert!(html.contains(
+            r##"<span class="citation">(see <a href="#ref-doe99">Doe and World Health Organization 1999</a>, p. 3; <a href="#ref-roe:2001.a">2001</a>)</span>"##
+        ));
+        assert!(html.contains(
+            r#"<span class="citation-missing" title="Not in the bibliography">nope?</span>"#
+        ));
+        assert!(html.contains(" or me@doe99."));
+        assert!(html.contains("<code>[@doe99]</code>"));
+        assert!(html.contains(
+            r#"<div id="ref-doe99" class="csl-entry" role="listitem">Doe, Jane and World Health Organization. 1999. “On Markdown Previews.” <em>Journal of Text</em>. <a href="https://doi.org/10.1/x">https://doi.org/10.1/x</a>.</div>"#
+        ));
+        assert!(
+            html.contains("Roe, Richard, Ann Poe, and Bo Loe. 2001. <em>Books &amp; More</em>.")
+        );
+        let doe = html.find("id=\"ref-doe99\"").unwrap();
+        let roe = html.find("id=\"ref-roe:2001.a\"").unwrap();
+        assert!(doe < roe);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,17 +1,237 @@
+use crate::{export, watch};
 use lexopt::{
     Arg::{Long, Short, Value},
     ValueExt,
 };
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
-const VERSION: &'static str = env!("CARGO_PKG_VERSION");
-const USAGE: &'static str =
-    "usage: mdopen [-h|--help] [-v|--version] [-b|--browser BROWSER] [-p|--port PORT] [FILES...]";
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+const USAGE: &str =
+    "usage: mdopen [-h|--help] [-v|--version] [-b|--browser BROWSER] [-p|--port PORT] [--host HOST] [--idle-timeout DURATION] [--exit-on-close] [--reload|--follow [--watch-ignore GLOBS] [--watch-poll[=INTERVAL]]] [--rst-command COMMAND] [--toc top|sidebar] [--syntax-theme NAME|LIGHT,DARK|list] [--syntax-theme-file FILE] [--syntax-dir DIR] [--reading-time] [--slides] [--feed] [--sitemap] [--site-url URL] [--no-smart-punctuation] [--no-footnotes] [--no-tables] [--no-tasklists] [--no-gfm] [--no-math] [--sub-sup] [--breaks] [--no-mermaid] [--dot-command COMMAND] [--plantuml-command COMMAND] [--plantuml-server URL] [--wikilinks] [--server-math] [--number-headings] [--edit-tasks] [--line-numbers] [--lang-alias ALIAS=LANG,...] [--detect-lang] [--sanitize] [--sanitize-allow TAGS] [--bibliography FILE] [--render [--fragment]] [--export-watch SRC OUT [--minify]] [FILES...|-]
+       mdopen render [OPTIONS] FILE|- [-o|--output FILE] [--self-contained] [--fragment]
+       mdopen render [OPTIONS] FILES...|SUMMARY.md -o BOOK.epub
+       mdopen ctl [-p|--port PORT] COMMAND [ARGS...]";
+
+/// Where to put the table of contents of markdown pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TocPosition {
+    Top,
+    Sidebar,
+}
+
+impl FromStr for TocPosition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "top" => Ok(TocPosition::Top),
+            "sidebar" => Ok(TocPosition::Sidebar),
+            _ => Err(format!("expected top or sidebar, got {:?}", s)),
+        }
+    }
+}
+
+/// highlight.js themes, named after their stylesheets.
+pub const SYNTAX_THEMES: &[&str] = &[
+    "a11y-dark",
+    "a11y-light",
+    "agate",
+    "an-old-hope",
+    "androidstudio",
+    "arduino-light",
+    "arta",
+    "ascetic",
+    "atom-one-dark",
+    "atom-one-dark-reasonable",
+    "atom-one-light",
+    "brown-paper",
+    "codepen-embed",
+    "color-brewer",
+    "dark",
+    "default",
+    "devibeans",
+    "docco",
+    "far",
+    "felipec",
+    "foundation",
+    "github",
+    "github-dark",
+    "github-dark-dimmed",
+    "gml",
+    "googlecode",
+    "gradient-dark",
+    "gradient-light",
+    "grayscale",
+    "hybrid",
+    "idea",
+    "intellij-light",
+    "ir-black",
+    "isbl-editor-dark",
+    "isbl-editor-light",
+    "kimbie-dark",
+    "kimbie-light",
+    "lightfair",
+    "lioshi",
+    "magula",
+    "mono-blue",
+    "monokai",
+    "monokai-sublime",
+    "night-owl",
+    "nnfx-dark",
+    "nnfx-light",
+    "nord",
+    "obsidian",
+    "panda-syntax-dark",
+    "panda-syntax-light",
+    "paraiso-dark",
+    "paraiso-light",
+    "pojoaque",
+    "purebasic",
+    "qtcreator-dark",
+    "qtcreator-light",
+    "rainbow",
+    "routeros",
+    "school-book",
+    "shades-of-purple",
+    "srcery",
+    "stackoverflow-dark",
+    "stackoverflow-light",
+    "sunburst",
+    "tokyo-night-dark",
+    "tokyo-night-light",
+    "tomorrow-night-blue",
+    "tomorrow-night-bright",
+    "vs",
+    "vs2015",
+    "xcode",
+    "xt256",
+];
+
+/// Code highlighting theme.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyntaxTheme {
+    /// highlight.js themes for light and dark color schemes.
+    Named { light: String, dark: String },
+    /// A `.tmTheme` or highlight.js stylesheet, loaded at startup.
+    File(PathBuf),
+}
+
+impl Default for SyntaxTheme {
+    fn default() -> Self {
+        SyntaxTheme::Named {
+            light: String::from("github"),
+            dark: String::from("github-dark"),
+        }
+    }
+}
+
+impl FromStr for SyntaxTheme {
+    type Err = String;
+
+    /// Parse `NAME` for both color schemes, or `LIGHT,DARK`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (light, dark) = s.split_once(',').unwrap_or((s, s));
+        for name in [light, dark] {
+            if !SYNTAX_THEMES.contains(&name) {
+                return Err(format!(
+                    "unknown syntax theme {:?}, see --syntax-theme list",
+                    name
+                ));
+            }
+        }
+        Ok(SyntaxTheme::Named {
+            light: light.to_string(),
+            dark: dark.to_string(),
+        })
+    }
+}
 
 #[derive(Debug)]
 pub struct Args {
     pub files: Vec<String>,
     pub port: u16,
+    pub host: IpAddr,
     pub browser: Option<String>,
+    pub idle_timeout: Option<Duration>,
+    /// Exit once the pages connected for live reload are all closed.
+    pub exit_on_close: bool,
+    /// Reload pages when files under the served directory change.
+    pub reload: bool,
+    /// Take pages to the markdown file saved last, with live reload.
+    pub follow: bool,
+    /// Files and directories whose changes don't reload pages.
+    pub watch_ignore: Vec<String>,
+    /// How often to check files for changes.
+    pub watch_interval: Duration,
+    /// Command converting AsciiDoc on stdin to HTML on stdout.
+    #[cfg(feature = "asciidoc")]
+    pub asciidoc_command: String,
+    /// Command converting reStructuredText on stdin to HTML on stdout.
+    pub rst_command: String,
+    pub toc: Option<TocPosition>,
+    pub syntax_theme: SyntaxTheme,
+    /// Directory of extra highlight.js languages.
+    pub syntax_dir: Option<PathBuf>,
+    /// Show word count and reading time above markdown pages.
+    pub reading_time: bool,
+    /// Present markdown files as slides.
+    pub slides: bool,
+    /// Serve and export an Atom feed of the markdown files.
+    pub feed: bool,
+    /// Serve and export `sitemap.xml` and `llms.txt`.
+    pub sitemap: bool,
+    /// Public URL of exported sites, for absolute links in feeds and sitemaps.
+    pub site_url: Option<String>,
+    /// Markdown extensions to turn off.
+    pub no_smart_punctuation: bool,
+    pub no_footnotes: bool,
+    pub no_tables: bool,
+    pub no_tasklists: bool,
+    pub no_gfm: bool,
+    pub no_math: bool,
+    /// Parse `~sub~` and `^sup^`.
+    pub sub_sup: bool,
+    /// Render single newlines as line breaks.
+    pub breaks: bool,
+    pub no_mermaid: bool,
+    /// Command converting Graphviz on stdin to SVG on stdout.
+    pub dot_command: String,
+    /// Command converting PlantUML on stdin to SVG on stdout.
+    pub plantuml_command: String,
+    /// PlantUML server used instead of the command.
+    pub plantuml_server: Option<String>,
+    /// Resolve `[[Page]]` links to markdown files.
+    pub wikilinks: bool,
+    /// Render math to MathML on the server.
+    pub server_math: bool,
+    /// Prefix headings with section numbers.
+    pub number_headings: bool,
+    /// Let task list checkboxes be toggled, writing to the file.
+    pub edit_tasks: bool,
+    /// Show line numbers in all code blocks.
+    pub line_numbers: bool,
+    /// Code block languages and what to highlight them as.
+    pub lang_aliases: Vec<(String, String)>,
+    /// Guess the language of code blocks without one.
+    pub detect_lang: bool,
+    /// Clean raw HTML in markdown.
+    pub sanitize: bool,
+    /// Tags to keep when sanitizing, besides the defaults.
+    pub sanitize_allow: Vec<String>,
+    /// BibTeX or CSL JSON file to resolve citations against.
+    pub bibliography: Option<PathBuf>,
+    /// Arguments of `mdopen ctl`, if running in control mode.
+    pub ctl: Option<Vec<String>>,
+    /// Render `files` with `mdopen render` or `--render` instead of serving them.
+    pub render: bool,
+    /// How to write rendered files.
+    pub export: export::Options,
+    /// Directory to keep exported as HTML, and where to.
+    pub export_watch: Option<(PathBuf, PathBuf)>,
 }
 
 impl Args {
@@ -28,8 +248,55 @@ impl Args {
 
 fn parse_args() -> Result<Args, lexopt::Error> {
     let mut port = 5032;
+    let mut host = IpAddr::V4(Ipv4Addr::LOCALHOST);
     let mut browser = Option::<String>::None;
+    let mut idle_timeout = Option::<Duration>::None;
+    let mut exit_on_close = false;
     let mut files = Vec::<String>::new();
+    let mut ctl = Option::<Vec<String>>::None;
+    let mut render = false;
+    let mut export = export::Options::default();
+    let mut export_watch = Option::<(PathBuf, PathBuf)>::None;
+    #[cfg(feature = "asciidoc")]
+    let mut asciidoc_command = String::from("asciidoctor --embedded --out-file - -");
+    let mut rst_command = String::from("pandoc --from rst --to html");
+    let mut toc = Option::<TocPosition>::None;
+    let mut reload = false;
+    let mut follow = false;
+    let mut watch_interval = watch::POLL_INTERVAL;
+    let mut watch_ignore: Vec<String> = watch::DEFAULT_IGNORE
+        .iter()
+        .map(|pattern| pattern.to_string())
+        .collect();
+    let mut reading_time = false;
+    let mut slides = false;
+    let mut feed = false;
+    let mut sitemap = false;
+    let mut site_url = Option::<String>::None;
+    let mut syntax_theme = SyntaxTheme::default();
+    let mut syntax_dir = Option::<PathBuf>::None;
+    let mut no_smart_punctuation = false;
+    let mut no_footnotes = false;
+    let mut no_tables = false;
+    let mut no_tasklists = false;
+    let mut no_gfm = false;
+    let mut no_math = false;
+    let mut sub_sup = false;
+    let mut breaks = false;
+    let mut no_mermaid = false;
+    let mut dot_command = String::from("dot -Tsvg");
+    let mut plantuml_command = String::from("plantuml -tsvg -pipe");
+    let mut plantuml_server = Option::<String>::None;
+    let mut wikilinks = false;
+    let mut server_math = false;
+    let mut number_headings = false;
+    let mut edit_tasks = false;
+    let mut line_numbers = false;
+    let mut lang_aliases = Vec::new();
+    let mut detect_lang = false;
+    let mut sanitize = false;
+    let mut sanitize_allow = Vec::new();
+    let mut bibliography = Option::<PathBuf>::None;
 
     let mut parser = lexopt::Parser::from_env();
 
@@ -38,12 +305,209 @@ fn parse_args() -> Result<Args, lexopt::Error> {
             Short('p') | Long("port") => {
                 port = parser.value()?.parse()?;
             }
+            Long("host") => {
+                host = parser.value()?.parse()?;
+            }
             Short('b') | Long("browser") => {
                 browser = Some(parser.value()?.parse()?);
             }
-            Value(val) => {
-                files.push(val.parse()?);
+            Long("idle-timeout") => {
+                let value: String = parser.value()?.parse()?;
+                let timeout = humantime::parse_duration(&value).map_err(|e| {
+                    lexopt::Error::ParsingFailed {
+                        value,
+                        error: Box::new(e),
+                    }
+                })?;
+                idle_timeout = Some(timeout);
+            }
+            Long("reload") => {
+                reload = true;
+            }
+            Long("exit-on-close") => {
+                reload = true;
+                exit_on_close = true;
+            }
+            Long("follow") => {
+                reload = true;
+                follow = true;
+            }
+            Long("watch-poll") => {
+                if let Some(value) = parser.optional_value() {
+                    let value: String = value.parse()?;
+                    watch_interval = humantime::parse_duration(&value).map_err(|e| {
+                        lexopt::Error::ParsingFailed {
+                            value,
+                            error: Box::new(e),
+                        }
+                    })?;
+                }
+            }
+            Long("watch-ignore") => {
+                let patterns: String = parser.value()?.parse()?;
+                watch_ignore.extend(
+                    patterns
+                        .split(',')
+                        .filter(|pattern| !pattern.trim().is_empty())
+                        .map(|pattern| pattern.trim().to_string()),
+                );
+            }
+            #[cfg(feature = "asciidoc")]
+            Long("asciidoc-command") => {
+                asciidoc_command = parser.value()?.parse()?;
+            }
+            Long("rst-command") => {
+                rst_command = parser.value()?.parse()?;
+            }
+            Long("toc") => {
+                toc = Some(parser.value()?.parse()?);
+            }
+            Long("syntax-theme") => {
+                let value: String = parser.value()?.parse()?;
+                if value == "list" {
+                    // Ignore errors, like a closed pipe when piped to `head`.
+                    let mut stdout = std::io::stdout();
+                    for theme in SYNTAX_THEMES {
+                        _ = writeln!(stdout, "{}", theme);
+                    }
+                    std::process::exit(0);
+                }
+                syntax_theme = value.parse()?;
+            }
+            Long("syntax-theme-file") => {
+                syntax_theme = SyntaxTheme::File(parser.value()?.into());
+            }
+            Long("syntax-dir") => {
+                syntax_dir = Some(parser.value()?.into());
+            }
+            Long("reading-time") => {
+                reading_time = true;
+            }
+            Long("slides") => {
+                slides = true;
+            }
+            Long("feed") => {
+                feed = true;
+            }
+            Long("sitemap") => {
+                sitemap = true;
+            }
+            Long("site-url") => {
+                site_url = Some(parser.value()?.parse()?);
+            }
+            Long("no-smart-punctuation") => {
+                no_smart_punctuation = true;
+            }
+            Long("no-footnotes") => {
+                no_footnotes = true;
+            }
+            Long("no-tables") => {
+                no_tables = true;
+            }
+            Long("no-tasklists") => {
+                no_tasklists = true;
+            }
+            Long("no-gfm") => {
+                no_gfm = true;
+            }
+            Long("no-math") => {
+                no_math = true;
+            }
+            Long("sub-sup") => {
+                sub_sup = true;
+            }
+            Long("breaks") => {
+                breaks = true;
+            }
+            Long("no-mermaid") => {
+                no_mermaid = true;
+            }
+            Long("dot-command") => {
+                dot_command = parser.value()?.parse()?;
+            }
+            Long("plantuml-command") => {
+                plantuml_command = parser.value()?.parse()?;
+            }
+            Long("plantuml-server") => {
+                plantuml_server = Some(parser.value()?.parse()?);
+            }
+            Long("wikilinks") => {
+                wikilinks = true;
+            }
+            Long("server-math") => {
+                server_math = true;
+            }
+            Long("number-headings") => {
+                number_headings = true;
+            }
+            Long("edit-tasks") => {
+                edit_tasks = true;
+            }
+            Long("line-numbers") => {
+                line_numbers = true;
+            }
+            Long("detect-lang") => {
+                detect_lang = true;
+            }
+            Long("lang-alias") => {
+                let value: String = parser.value()?.parse()?;
+                for pair in value.split(',').filter(|pair| !pair.trim().is_empty()) {
+                    let Some((alias, language)) = pair.split_once('=') else {
+                        return Err(lexopt::Error::ParsingFailed {
+                            value,
+                            error: "expected ALIAS=LANG".into(),
+                        });
+                    };
+                    lang_aliases.push((alias.trim().to_string(), language.trim().to_string()));
+                }
+            }
+            Long("sanitize") => {
+                sanitize = true;
+            }
+            Long("sanitize-allow") => {
+                let tags: String = parser.value()?.parse()?;
+                sanitize_allow.extend(tags.split(',').map(|tag| tag.trim().to_string()));
+                sanitize = true;
+            }
+            Long("bibliography") => {
+                bibliography = Some(parser.value()?.into());
+            }
+            Value(val) if files.is_empty() && ctl.is_none() && !render && val == "ctl" => {
+                ctl = Some(Vec::new());
+            }
+            Value(val) if files.is_empty() && ctl.is_none() && !render && val == "render" => {
+                render = true;
+            }
+            Long("render") if ctl.is_none() => {
+                render = true;
+            }
+            Short('o') | Long("output") if render => {
+                export.output = Some(parser.value()?.into());
+            }
+            Long("self-contained") if render => {
+                export.self_contained = true;
+            }
+            Long("fragment") if render => {
+                export.fragment = true;
+            }
+            Long("minify") => {
+                export.minify = true;
+            }
+            Long("format") if render => {
+                export.format = parser.value()?.parse()?;
+            }
+            Long("width") if render => {
+                export.width = Some(parser.value()?.parse()?);
+            }
+            Long("export-watch") => {
+                let src = parser.value()?.into();
+                let out = parser.value()?.into();
+                export_watch = Some((src, out));
             }
+            Value(val) => match ctl {
+                Some(ref mut ctl) => ctl.push(val.parse()?),
+                None => files.push(val.parse()?),
+            },
             Short('v') | Long("version") => {
                 eprintln!("{}", VERSION);
                 std::process::exit(0);
@@ -60,5 +524,49 @@ fn parse_args() -> Result<Args, lexopt::Error> {
         browser,
         files,
         port,
+        host,
+        idle_timeout,
+        exit_on_close,
+        reload,
+        follow,
+        watch_ignore,
+        watch_interval,
+        #[cfg(feature = "asciidoc")]
+        asciidoc_command,
+        rst_command,
+        toc,
+        syntax_theme,
+        syntax_dir,
+        reading_time,
+        slides,
+        feed,
+        sitemap,
+        site_url,
+        no_smart_punctuation,
+        no_footnotes,
+        no_tables,
+        no_tasklists,
+        no_gfm,
+        no_math,
+        sub_sup,
+        breaks,
+        no_mermaid,
+        dot_command,
+        plantuml_command,
+        plantuml_server,
+        wikilinks,
+        server_math,
+        number_headings,
+        edit_tasks,
+        line_numbers,
+        lang_aliases,
+        detect_lang,
+        sanitize,
+        sanitize_allow,
+        bibliography,
+        ctl,
+        render,
+        export,
+        export_watch,
     })
 }
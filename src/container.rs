@@ -0,0 +1,259 @@
+//! Fenced containers like `:::details Summary` or `::: warning Title`, closed
+//! by a `:::` line.
+//!
+//! Containers are turned into HTML blocks around their content before
+//! parsing, so the content is still rendered as markdown.
+
+use crate::sourcemap::SourceMap;
+use pulldown_cmark_escape::escape_html;
+use std::borrow::Cow;
+
+/// An open container: its colon count and closing HTML.
+struct Open {
+    colons: usize,
+    close: &'static str,
+}
+
+/// Split a `:::name rest` line into the colon count, name and rest.
+fn marker(line: &str) -> Option<(usize, &str, &str)> {
+    let colons = line.len() - line.trim_start_matches(':').len();
+    if colons < 3 {
+        return None;
+    }
+    let rest = line[colons..].trim();
+    let (name, rest) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    Some((colons, name, rest.trim()))
+}
+
+/// Split an mkdocs `!!! note "Title"` line into its marker, kind and title.
+fn mkdocs_marker(line: &str) -> Option<(&str, &str, Option<&str>)> {
+    let (marker, rest) = line.split_once(char::is_whitespace)?;
+    if !matches!(marker, "!!!" | "???" | "???+") {
+        return None;
+    }
+    let rest = rest.trim();
+    let (kind, title) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    let title = title.trim();
+    let title = title
+        .strip_prefix('"')
+        .and_then(|t| t.strip_suffix('"'))
+        .or((!title.is_empty()).then_some(title));
+    is_kind(kind).then_some((marker, kind, title))
+}
+
+/// Whether `name` can be used as a CSS class for an admonition.
+fn is_kind(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Whether `line` is indented by less than four columns, as block markers
+/// must be; more makes it a line of an indented code block.
+fn is_block_indent(line: &str) -> bool {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    indent < 4 && !line[indent..].starts_with('\t')
+}
+
+/// Whether `line` opens or closes a code fence.
+fn is_fence(line: &str) -> bool {
+    line.starts_with("```") || line.starts_with("~~~")
+}
+
+/// Write the opening HTML of an admonition, titled by its kind by default.
+fn push_admonition(out: &mut String, kind: &str, title: Option<&str>) {
+    out.push_str("<div class=\"admonition ");
+    out.push_str(kind);
+    out.push_str("\">\n<p class=\"admonition-title\">");
+    _ = escape_html(
+        &mut *out,
+        &title.map_or_else(|| default_title(kind), str::to_string),
+    );
+    out.push_str("</p>\n\n");
+}
+
+/// `note` becomes `Note`.
+fn default_title(kind: &str) -> String {
+    let mut chars = kind.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Replace container markers with HTML, leaving code blocks alone.
+///
+/// Besides `:::` containers, mkdocs style `!!! note` admonitions with
+/// indented content are supported, and `??? note` for collapsed ones.
+/// The map leads from the result back to `md`.
+pub fn expand(md: &str) -> (Cow<'_, str>, SourceMap) {
+    let mut map = SourceMap::default();
+    if !md.contains(":::") && !md.contains("!!!") && !md.contains("???") {
+        return (Cow::Borrowed(md), map);
+    }
+    let mut out = String::with_capacity(md.len());
+    // Where the current line ends in `md`; lines only lose their start here.
+    let mut end = 0;
+    let mut stack: Vec<Open> = Vec::new();
+    let mut fence: Option<&str> = None;
+    // Closing HTML of the mkdocs admonition whose indented lines are being read.
+    let mut mkdocs: Option<&str> = None;
+    for mut line in md.split_inclusive('\n') {
+        map.generated(out.len(), end);
+        end += line.len();
+        if let Some(close) = mkdocs {
+            if let Some(inner) = line.strip_prefix("    ").or(line.strip_prefix('\t')) {
+                line = inner;
+            } else if !line.trim().is_empty() && fence.is_none() {
+                out.push('\n');
+                out.push_str(close);
+                out.push_str("\n\n");
+                mkdocs = None;
+            }
+        }
+
+        let trimmed = line.trim();
+        if let Some(open) = fence {
+            if trimmed.starts_with(open) && trimmed.trim_start_matches(&open[..1]).is_empty() {
+                fence = None;
+            }
+            map.copied(out.len(), end - line.len());
+            out.push_str(line);
+            continue;
+        }
+        if !is_block_indent(line) {
+            map.copied(out.len(), end - line.len());
+            out.push_str(line);
+            continue;
+        }
+        if is_fence(trimmed) {
+            let marker = &trimmed[..1];
+            let len = trimmed.len() - trimmed.trim_start_matches(marker).len();
+            fence = Some(&trimmed[..len]);
+            map.copied(out.len(), end - line.len());
+            out.push_str(line);
+            continue;
+        }
+        if mkdocs.is_none() {
+            if let Some((marker, kind, title)) = mkdocs_marker(trimmed) {
+                if marker == "!!!" {
+                    push_admonition(&mut out, kind, title);
+                    mkdocs = Some("</div>");
+                } else {
+                    out.push_str(if marker == "???+" {
+                        "<details open"
+                    } else {
+                        "<details"
+                    });
+                    out.push_str(" class=\"admonition ");
+                    out.push_str(kind);
+                    out.push_str("\">\n<summary>");
+                    let title = title.map_or_else(|| default_title(kind), str::to_string);
+                    _ = escape_html(&mut out, &title);
+                    out.push_str("</summary>\n\n");
+                    mkdocs = Some("</details>");
+                }
+                continue;
+            }
+        }
+        match marker(trimmed) {
+            Some((colons, "", _)) if stack.last().is_some_and(|open| colons >= open.colons) => {
+                let open = stack.pop().unwrap();
+                out.push('\n');
+                out.push_str(open.close);
+                out.push_str("\n\n");
+            }
+            Some((colons, "details", summary)) => {
+                out.push_str("<details>\n<summary>");
+                let summary = if summary.is_empty() {
+                    "Details"
+                } else {
+                    summary
+                };
+                _ = escape_html(&mut out, summary);
+                out.push_str("</summary>\n\n");
+                stack.push(Open {
+                    colons,
+                    close: "</details>",
+                });
+            }
+            Some((colons, kind, title)) if is_kind(kind) => {
+                push_admonition(&mut out, kind, (!title.is_empty()).then_some(title));
+                stack.push(Open {
+                    colons,
+                    close: "</div>",
+                });
+            }
+            _ => {
+                map.copied(out.len(), end - line.len());
+                out.push_str(line);
+            }
+        }
+    }
+    map.generated(out.len(), end);
+    // Close containers left open at the end of the document.
+    for close in mkdocs
+        .into_iter()
+        .chain(stack.iter().rev().map(|open| open.close))
+    {
+        out.push('\n');
+        out.push_str(close);
+        out.push('\n');
+    }
+    (Cow::Owned(out), map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(md: &str) -> Cow<'_, str> {
+        super::expand(md).0
+    }
+
+    #[test]
+    fn details_containers() {
+        let md = ":::details More\ntext\n\n```\n:::\n```\n:::\nafter\n";
+        assert_eq!(
+            expand(md),
+            "<details>\n<summary>More</summary>\n\ntext\n\n```\n:::\n```\n\n</details>\n\nafter\n"
+        );
+        assert_eq!(
+            expand("::: details\nx"),
+            "<details>\n<summary>Details</summary>\n\nx\n</details>\n"
+        );
+        assert_eq!(expand(":::\n::: <b>\n"), ":::\n::: <b>\n");
+        let code = "text\n\n    :::note\n    !!! tip\n\t```\n:::warning\nx\n:::\n";
+        assert!(expand(code).starts_with("text\n\n    :::note\n    !!! tip\n\t```\n<div"));
+    }
+
+    #[test]
+    fn admonitions() {
+        assert_eq!(
+            expand("::: warning Be careful\ntext\n:::\n"),
+            "<div class=\"admonition warning\">\n<p class=\"admonition-title\">Be careful</p>\n\ntext\n\n</div>\n\n"
+        );
+        assert_eq!(
+            expand("!!! tip\n    text\n\n    more\nafter\n"),
+            "<div class=\"admonition tip\">\n<p class=\"admonition-title\">Tip</p>\n\ntext\n\nmore\n\n</div>\n\nafter\n"
+        );
+        assert_eq!(
+            expand("??? note \"Open me\"\n    text\n"),
+            "<details class=\"admonition note\">\n<summary>Open me</summary>\n\ntext\n\n</details>\n"
+        );
+    }
+
+    #[test]
+    fn offsets_map_back_to_the_source() {
+        let md = "intro\n\n!!! note\n    - [ ] a\n\n- [ ] b\n";
+        let (out, map) = super::expand(md);
+        for task in ["- [ ] a", "- [ ] b"] {
+            let at = out.find(task).unwrap();
+            assert_eq!(map.source(at), md.find(task).unwrap());
+        }
+        let title = out.find("<p class").unwrap();
+        assert_eq!(map.source(title), md.find("!!!").unwrap());
+        assert_eq!(map.source(out.len()), md.len());
+    }
+}
@@ -0,0 +1,208 @@
+//! Remote control of a running instance, used by `mdopen ctl` and editor plugins.
+//!
+//! Commands are posted as JSON objects to the control endpoint:
+//!
+//! * `{"command": "open-file", "paths": [...]}` opens files in the browser,
+//! * `{"command": "navigate", "url": "/docs/index.md"}` opens a URL path,
+//! * `{"command": "reload-all"}` reloads all connected pages,
+//! * `{"command": "shutdown"}` stops the server.
+
+use crate::{
+    api_error, client, file_url, is_json, json, json_response, open_browser, served_url_path, sse,
+    AppConfig, API_PREFIX,
+};
+use log::info;
+use std::env;
+use std::io::Cursor;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tiny_http::{Request, Response};
+
+/// Set when the server should exit after sending the current response.
+pub static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+pub const USAGE: &str =
+    "usage: mdopen ctl [-p|--port PORT] (open-file PATH... | navigate URL | reload-all | shutdown)";
+
+fn control_url() -> String {
+    format!("{}control", API_PREFIX)
+}
+
+/// Handle a control command request.
+pub fn handle(request: &mut Request, config: &AppConfig) -> Response<Cursor<Vec<u8>>> {
+    // Any page could otherwise stop the server or open tabs with a form post.
+    if !is_json(request) {
+        return api_error("expected a JSON body", 415);
+    }
+    let mut body = String::new();
+    if let Err(e) = request.as_reader().read_to_string(&mut body) {
+        return api_error(&format!("cannot read body: {}", e), 400);
+    }
+    let value = match json::parse(&body) {
+        Ok(v) => v,
+        Err(e) => return api_error(&e.to_string(), 400),
+    };
+
+    match value.get("command").and_then(json::Value::as_str) {
+        Some("open-file") => {
+            let paths: Vec<&str> = match (value.get("paths"), value.get("path")) {
+                (Some(json::Value::Array(paths)), _) => {
+                    paths.iter().filter_map(json::Value::as_str).collect()
+                }
+                (_, Some(json::Value::String(path))) => vec![path],
+                _ => return api_error("missing \"paths\"", 400),
+            };
+            open_files(config, &paths)
+        }
+        Some("navigate") => {
+            let Some(url) = value.get("url").and_then(json::Value::as_str) else {
+                return api_error("missing \"url\"", 400);
+            };
+            if !url.starts_with('/') {
+                return api_error("url must be a path starting with /", 400);
+            }
+            open_url(config, url)
+        }
+        Some("reload-all") if config.reload => {
+            sse::broadcast_all("reload", "");
+            json_response(&json::Value::object([("ok", true.into())]), 200)
+        }
+        Some("reload-all") => api_error("live reload is not enabled", 501),
+        Some("shutdown") => {
+            info!("shutdown requested");
+            SHUTDOWN.store(true, Ordering::Relaxed);
+            json_response(&json::Value::object([("ok", true.into())]), 200)
+        }
+        Some(command) => api_error(&format!("unknown command {:?}", command), 400),
+        None => api_error("missing \"command\"", 400),
+    }
+}
+
+/// Open files given relative to the root or as absolute paths inside it.
+fn open_files(config: &AppConfig, paths: &[&str]) -> Response<Cursor<Vec<u8>>> {
+    let root = match env::current_dir() {
+        Ok(r) => r,
+        Err(e) => return api_error(&e.to_string(), 500),
+    };
+
+    let mut opened = Vec::new();
+    for file in paths {
+        let Some(url_path) = served_url_path(&root, file) else {
+            return api_error(&format!("{} is outside of the served root", file), 403);
+        };
+        let url = file_url(config.port, &url_path);
+        info!("opening {}", &url);
+        if let Err(e) = open_browser(&config.browser, &url) {
+            return api_error(&format!("cannot open browser: {}", e), 500);
+        }
+        opened.push(url);
+    }
+    json_response(&json::Value::object([("opened", opened.into())]), 200)
+}
+
+fn open_url(config: &AppConfig, url_path: &str) -> Response<Cursor<Vec<u8>>> {
+    let url = file_url(config.port, url_path);
+    info!("opening {}", &url);
+    if let Err(e) = open_browser(&config.browser, &url) {
+        return api_error(&format!("cannot open browser: {}", e), 500);
+    }
+    json_response(&json::Value::object([("opened", vec![url].into())]), 200)
+}
+
+/// Send a command to the instance at `addr`, returning the response body.
+pub fn send(addr: SocketAddr, command: &json::Value) -> Result<String, String> {
+    match client::request(addr, "POST", &control_url(), Some(&command.to_string())) {
+        Ok((200, body)) => Ok(body),
+        Ok((_, body)) => Err(json::parse(&body)
+            .ok()
+            .and_then(|v| {
+                v.get("error")
+                    .and_then(json::Value::as_str)
+                    .map(String::from)
+            })
+            .unwrap_or(body)),
+        Err(e) => Err(format!("cannot reach mdopen at http://{}: {}", addr, e)),
+    }
+}
+
+/// Build a command for the running instance from `mdopen ctl` arguments.
+fn parse_command(args: &[String]) -> Option<json::Value> {
+    let (command, rest) = args.split_first()?;
+    let value = match (command.as_str(), rest) {
+        ("open-file", paths) if !paths.is_empty() => {
+            let cwd = env::current_dir().unwrap_or_default();
+            let paths: Vec<String> = paths
+                .iter()
+                .map(|p| cwd.join(p).to_string_lossy().to_string())
+                .collect();
+            json::Value::object([("command", "open-file".into()), ("paths", paths.into())])
+        }
+        ("navigate", [url]) => {
+            json::Value::object([("command", "navigate".into()), ("url", url.as_str().into())])
+        }
+        ("reload-all" | "shutdown", []) => {
+            json::Value::object([("command", command.as_str().into())])
+        }
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Run `mdopen ctl`, returning the process exit code.
+pub fn run(addr: SocketAddr, args: &[String]) -> i32 {
+    let Some(command) = parse_command(args) else {
+        eprintln!("{}", USAGE);
+        return 1;
+    };
+    match send(addr, &command) {
+        Ok(body) => {
+            println!("{}", body);
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::config;
+    use std::io::Read;
+    use tiny_http::{Header, Method, TestRequest};
+
+    fn post(body: &'static str, content_type: &str) -> (u16, String) {
+        let mut request: Request = TestRequest::new()
+            .with_method(Method::Post)
+            .with_path(&control_url())
+            .with_header(Header::from_bytes(&b"Content-Type"[..], content_type).unwrap())
+            .with_body(body)
+            .into();
+        let response = handle(&mut request, &config());
+        let status = response.status_code().0;
+        let mut body = String::new();
+        response.into_reader().read_to_string(&mut body).unwrap();
+        (status, body)
+    }
+
+    #[test]
+    fn commands_must_be_json() {
+        for content_type in ["text/plain", "application/x-www-form-urlencoded"] {
+            let (status, _) = post(r#"{"command": "shutdown"}"#, content_type);
+            assert_eq!(status, 415);
+        }
+        assert!(!SHUTDOWN.load(Ordering::Relaxed));
+
+        let (status, body) = post(r#"{"command": "jump"}"#, "application/json");
+        assert_eq!(
+            (status, body.as_str()),
+            (400, r#"{"error":"unknown command \"jump\""}"#)
+        );
+        let (status, _) = post(r#"{"command": "reload-all"}"#, "application/json");
+        assert_eq!(status, 501);
+        let (status, _) = post(r#"{"command": "navigate", "url": "x"}"#, "application/json");
+        assert_eq!(status, 400);
+    }
+}
@@ -0,0 +1,189 @@
+//! Guessing the language of code blocks that don't name one, from their
+//! shebang or from how their lines start.
+
+/// Interpreters in shebang lines and the languages they run.
+const INTERPRETERS: &[(&str, &str)] = &[
+    ("bash", "bash"),
+    ("sh", "bash"),
+    ("zsh", "bash"),
+    ("dash", "bash"),
+    ("fish", "fish"),
+    ("python", "python"),
+    ("node", "javascript"),
+    ("deno", "typescript"),
+    ("ruby", "ruby"),
+    ("perl", "perl"),
+    ("php", "php"),
+    ("lua", "lua"),
+    ("awk", "awk"),
+    ("Rscript", "r"),
+];
+
+/// Beginnings of lines typical of each language.
+const LINE_STARTS: &[(&str, &[&str])] = &[
+    (
+        "rust",
+        &[
+            "fn ",
+            "pub fn ",
+            "let mut ",
+            "use std::",
+            "impl ",
+            "impl<",
+            "pub struct ",
+            "#[derive",
+            "mod ",
+            "match ",
+        ],
+    ),
+    (
+        "python",
+        &[
+            "def ",
+            "import ",
+            "from ",
+            "class ",
+            "elif ",
+            "print(",
+            "if __name__",
+            "@",
+        ],
+    ),
+    (
+        "go",
+        &["package ", "func ", "import (", "type ", "go ", "defer "],
+    ),
+    (
+        "c",
+        &[
+            "#include ",
+            "#define ",
+            "int main(",
+            "typedef ",
+            "static ",
+            "void ",
+        ],
+    ),
+    (
+        "javascript",
+        &[
+            "const ",
+            "function ",
+            "export ",
+            "import {",
+            "console.log(",
+            "module.exports",
+            "async function ",
+            "let ",
+        ],
+    ),
+    (
+        "bash",
+        &[
+            "echo ", "export ", "sudo ", "cd ", "if [", "apt ", "cargo ", "npm ", "git ",
+        ],
+    ),
+    (
+        "sql",
+        &[
+            "SELECT ", "INSERT ", "UPDATE ", "DELETE ", "CREATE ", "ALTER ", "DROP ", "FROM ",
+            "WHERE ",
+        ],
+    ),
+    (
+        "dockerfile",
+        &[
+            "FROM ",
+            "RUN ",
+            "COPY ",
+            "WORKDIR ",
+            "CMD ",
+            "ENTRYPOINT ",
+            "ENV ",
+        ],
+    ),
+    ("diff", &["diff --git", "--- ", "+++ ", "@@ "]),
+];
+
+/// Language of a shebang line like `#!/usr/bin/env python3`.
+fn shebang(line: &str) -> Option<&'static str> {
+    let command = line.strip_prefix("#!")?;
+    let mut words = command.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words.find(|word| !word.starts_with('-'))?;
+    }
+    let program = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    INTERPRETERS
+        .iter()
+        .find(|(interpreter, _)| *interpreter == program)
+        .map(|&(_, language)| language)
+}
+
+/// Guess the language of `source`, if it looks clearly like one.
+pub fn language(source: &str) -> Option<&'static str> {
+    let first = source.lines().find(|line| !line.trim().is_empty())?.trim();
+    if let Some(language) = shebang(first) {
+        return Some(language);
+    }
+    if first.starts_with("<?php") {
+        return Some("php");
+    }
+    if first.starts_with("<?xml") {
+        return Some("xml");
+    }
+    if first.starts_with('<') && first.ends_with('>') {
+        return Some("html");
+    }
+    if (first.starts_with(['{', '[']) && first.len() <= 2) || first.starts_with("{\"") {
+        return Some("json");
+    }
+    if first.starts_with("$ ") {
+        return Some("console");
+    }
+
+    let mut best = None;
+    let mut best_hits = 0;
+    for (language, starts) in LINE_STARTS {
+        let hits = source
+            .lines()
+            .filter(|line| {
+                starts
+                    .iter()
+                    .any(|start| line.trim_start().starts_with(start))
+            })
+            .count();
+        // Ties are too unsure to pick one.
+        if hits > best_hits {
+            best = Some(*language);
+            best_hits = hits;
+        } else if hits == best_hits {
+            best = None;
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn languages_are_guessed() {
+        assert_eq!(
+            language("#!/usr/bin/env python3\nprint(1)\n"),
+            Some("python")
+        );
+        assert_eq!(language("#!/bin/sh -e\nls\n"), Some("bash"));
+        assert_eq!(language("{\n  \"a\": 1\n}\n"), Some("json"));
+        assert_eq!(language("$ ls\nfile\n"), Some("console"));
+        assert_eq!(
+            language("use std::io;\n\nfn main() {\n    let mut x = 1;\n}\n"),
+            Some("rust")
+        );
+        assert_eq!(language("package main\n\nfunc main() {\n}\n"), Some("go"));
+        assert_eq!(language("SELECT a\nFROM t\nWHERE b;\n"), Some("sql"));
+        assert_eq!(language("just some words\n"), None);
+        assert_eq!(language(""), None);
+    }
+}
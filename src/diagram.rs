@@ -0,0 +1,95 @@
+//! Server-side rendering of diagram code blocks through external programs.
+
+use crate::external;
+use log::error;
+use pulldown_cmark_escape::{escape_href, escape_html};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::sync::Mutex;
+
+/// Rendered diagrams by hash of the command and the source.
+static CACHE: Mutex<Option<HashMap<u64, String>>> = Mutex::new(None);
+
+fn cached(
+    command: &str,
+    source: &str,
+    f: impl FnOnce() -> io::Result<String>,
+) -> io::Result<String> {
+    let mut hasher = DefaultHasher::new();
+    (command, source).hash(&mut hasher);
+    let key = hasher.finish();
+
+    if let Some(svg) = CACHE
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .get(&key)
+    {
+        return Ok(svg.clone());
+    }
+    let svg = f()?;
+    CACHE
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(key, svg.clone());
+    Ok(svg)
+}
+
+/// Drop the XML declaration and doctype that precede the `<svg>` element.
+fn inline_svg(output: &str) -> &str {
+    output.find("<svg").map_or(output, |start| &output[start..])
+}
+
+/// Show the diagram source as a code block when it cannot be rendered.
+fn fallback(language: &str, source: &str) -> String {
+    let mut html = format!("<pre><code class=\"language-{}\">", language);
+    _ = escape_html(&mut html, source);
+    html.push_str("</code></pre>\n");
+    html
+}
+
+/// Render diagram source to inline SVG with `command`, or show the source on failure.
+fn convert(language: &str, command: &str, source: &str) -> String {
+    match cached(command, source, || external::convert(command, source)) {
+        Ok(svg) => format!("<div class=\"diagram\">{}</div>\n", inline_svg(&svg)),
+        Err(e) => {
+            error!("cannot render {} diagram: {}", language, e);
+            fallback(language, source)
+        }
+    }
+}
+
+/// Render Graphviz source to inline SVG with `command`, like `dot -Tsvg`.
+pub fn graphviz(command: &str, source: &str) -> String {
+    convert("dot", command, source)
+}
+
+/// Render PlantUML source to inline SVG with `command`, like `plantuml -tsvg -pipe`.
+pub fn plantuml(command: &str, source: &str) -> String {
+    convert("plantuml", command, source)
+}
+
+/// Embed a PlantUML diagram rendered by the server at `server`.
+///
+/// The source is sent hex encoded, which every PlantUML server accepts. The
+/// browser shows the source instead if the image fails to load.
+pub fn plantuml_image(server: &str, source: &str) -> String {
+    let mut url = format!("{}/svg/~h", server.trim_end_matches('/'));
+    for byte in source.bytes() {
+        _ = write!(url, "{:02x}", byte);
+    }
+    let mut html = String::from("<div class=\"diagram\"><img src=\"");
+    _ = escape_href(&mut html, &url);
+    html.push_str(
+        "\" alt=\"PlantUML diagram\" \
+         onerror=\"this.parentNode.hidden = true; this.parentNode.nextElementSibling.hidden = false\">\
+         </div><div hidden>",
+    );
+    html.push_str(&fallback("plantuml", source));
+    html.push_str("</div>\n");
+    html
+}
@@ -0,0 +1,567 @@
+//! Export of markdown files as an EPUB 3 book, for `mdopen render -o book.epub`.
+//!
+//! Each file becomes a chapter, or each file listed in a `SUMMARY.md`. Local
+//! images are stored in the book, links between chapters keep working, and
+//! math is rendered to MathML since e-readers don't run scripts.
+
+use crate::export::{local_path, rewrite_urls};
+use crate::summary::{self, Chapter};
+use crate::{include, links, markdown, mime, GITHUB_STYLE};
+use pulldown_cmark_escape::escape_html;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Changes to the GitHub stylesheet for e-readers, which have their own
+/// margins and no use for anchors or copy buttons.
+const STYLE: &str = "
+body { margin: 0; padding: 0; }
+.markdown-body { font-size: 1em; }
+.markdown-body .anchor, .markdown-body .copy-code { display: none; }
+.markdown-body pre { white-space: pre-wrap; word-wrap: break-word; }
+.markdown-body img { max-width: 100%; }
+";
+
+/// Elements without content, which XHTML needs closed like `<br/>`.
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// HTML entities likely in raw HTML, which XML doesn't define.
+const ENTITIES: &[(&str, u32)] = &[
+    ("nbsp", 160),
+    ("copy", 169),
+    ("reg", 174),
+    ("deg", 176),
+    ("middot", 183),
+    ("laquo", 171),
+    ("raquo", 187),
+    ("times", 215),
+    ("ndash", 8211),
+    ("mdash", 8212),
+    ("lsquo", 8216),
+    ("rsquo", 8217),
+    ("ldquo", 8220),
+    ("rdquo", 8221),
+    ("bull", 8226),
+    ("hellip", 8230),
+    ("trade", 8482),
+    ("rarr", 8594),
+];
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xedb88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &b| {
+        CRC_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Writer of zip files with uncompressed entries, as the `mimetype` of an
+/// EPUB must be.
+#[derive(Default)]
+struct Zip {
+    data: Vec<u8>,
+    central: Vec<u8>,
+    count: u16,
+}
+
+impl Zip {
+    fn add(&mut self, name: &str, content: &[u8]) {
+        // Version 2.0, no flags, stored, dated 1980-01-01.
+        let fields = |out: &mut Vec<u8>| {
+            for value in [20u16, 0, 0, 0, 0x21] {
+                out.extend(value.to_le_bytes());
+            }
+            out.extend(crc32(content).to_le_bytes());
+            out.extend((content.len() as u32).to_le_bytes());
+            out.extend((content.len() as u32).to_le_bytes());
+            out.extend((name.len() as u16).to_le_bytes());
+            out.extend(0u16.to_le_bytes());
+        };
+        let offset = self.data.len() as u32;
+        self.data.extend(0x04034b50u32.to_le_bytes());
+        fields(&mut self.data);
+        self.data.extend(name.as_bytes());
+        self.data.extend(content);
+
+        self.central.extend(0x02014b50u32.to_le_bytes());
+        self.central.extend(20u16.to_le_bytes());
+        fields(&mut self.central);
+        // Comment length, disk, internal and external attributes.
+        self.central.extend([0; 10]);
+        self.central.extend(offset.to_le_bytes());
+        self.central.extend(name.as_bytes());
+        self.count += 1;
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let offset = self.data.len() as u32;
+        let size = self.central.len() as u32;
+        self.data.append(&mut self.central);
+        self.data.extend(0x06054b50u32.to_le_bytes());
+        self.data.extend([0; 4]);
+        self.data.extend(self.count.to_le_bytes());
+        self.data.extend(self.count.to_le_bytes());
+        self.data.extend(size.to_le_bytes());
+        self.data.extend(offset.to_le_bytes());
+        self.data.extend([0; 2]);
+        self.data
+    }
+}
+
+/// Replace HTML entities XML doesn't know, and escape stray ampersands.
+fn xml_text(text: &str, out: &mut String) {
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp + 1..];
+        let name = rest.find(';').map(|end| &rest[..end]).filter(|name| {
+            !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'#')
+        });
+        match name {
+            Some("amp" | "lt" | "gt" | "quot" | "apos") => out.push('&'),
+            Some(name) if name.starts_with('#') => out.push('&'),
+            Some(name) => match ENTITIES.iter().find(|(entity, _)| *entity == name) {
+                Some((_, code)) => {
+                    out.push_str(&format!("&#{};", code));
+                    rest = &rest[name.len() + 1..];
+                }
+                None => out.push_str("&amp;"),
+            },
+            None => out.push_str("&amp;"),
+        }
+    }
+    out.push_str(rest);
+}
+
+/// Rewrite the tag at the start of `html` as XHTML, returning it and the
+/// length of the original.
+fn xhtml_tag(html: &str) -> Option<(String, usize)> {
+    let name_len = html[1..]
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == ':'))
+        .unwrap_or(html.len() - 1);
+    let name = &html[1..1 + name_len];
+    if name.is_empty() || !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let mut out = String::from("<");
+    out.push_str(name);
+    let mut seen = Vec::new();
+    let mut rest = &html[1 + name_len..];
+    let self_closing = loop {
+        rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix("/>") {
+            rest = after;
+            break true;
+        }
+        if let Some(after) = rest.strip_prefix('>') {
+            rest = after;
+            break false;
+        }
+        let attr_len = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '=' | '>' | '/'))
+            .unwrap_or(rest.len());
+        if attr_len == 0 {
+            // A stray `/` or `=`, or the end of the input.
+            rest = rest.get(1..)?;
+            continue;
+        }
+        let attr = &rest[..attr_len];
+        rest = rest[attr_len..].trim_start();
+        let value = match rest.strip_prefix('=') {
+            Some(after) => {
+                let after = after.trim_start();
+                let (value, len) = match after.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let end = after[1..].find(quote)? + 1;
+                        (&after[1..end], end + 1)
+                    }
+                    _ => {
+                        let end = after
+                            .find(|c: char| c.is_whitespace() || c == '>')
+                            .unwrap_or(after.len());
+                        (&after[..end], end)
+                    }
+                };
+                rest = &after[len..];
+                value
+            }
+            // Boolean attributes like `open` need a value.
+            None => attr,
+        };
+        if seen.contains(&attr) {
+            continue;
+        }
+        seen.push(attr);
+        out.push(' ');
+        out.push_str(attr);
+        out.push_str("=\"");
+        let mut escaped = String::new();
+        xml_text(value, &mut escaped);
+        out.push_str(&escaped.replace('"', "&quot;").replace('<', "&lt;"));
+        out.push('"');
+    };
+    // Inline SVG is only drawn in its own namespace.
+    if name == "svg" && !seen.contains(&"xmlns") {
+        out.push_str(" xmlns=\"http://www.w3.org/2000/svg\"");
+    }
+    if self_closing || VOID_ELEMENTS.contains(&name.to_ascii_lowercase().as_str()) {
+        out.push_str("/>");
+    } else {
+        out.push('>');
+    }
+    Some((out, html.len() - rest.len()))
+}
+
+/// Convert rendered HTML to well-formed XHTML, as EPUB needs.
+fn to_xhtml(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(lt) = rest.find('<') {
+        xml_text(&rest[..lt], &mut out);
+        rest = &rest[lt..];
+        if rest.starts_with("<!--") || rest.starts_with("</") {
+            let end = match rest.starts_with("<!--") {
+                true => rest.find("-->").map_or(rest.len(), |end| end + 3),
+                false => rest.find('>').map_or(rest.len(), |end| end + 1),
+            };
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+            continue;
+        }
+        match xhtml_tag(rest) {
+            Some((tag, len)) => {
+                out.push_str(&tag);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push_str("&lt;");
+                rest = &rest[1..];
+            }
+        }
+    }
+    xml_text(rest, &mut out);
+    out
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    _ = escape_html(&mut escaped, text);
+    escaped
+}
+
+fn chapter_file(index: usize) -> String {
+    format!("chapter-{}.xhtml", index + 1)
+}
+
+/// Navigation document listing the chapters as nested lists.
+fn nav_html(chapters: &[Chapter]) -> String {
+    summary::list_html(chapters, |i, chapter| {
+        format!(
+            "<a href=\"{}\">{}</a>",
+            chapter_file(i),
+            escape(&chapter.title)
+        )
+    })
+}
+
+fn xhtml_document(title: &str, body: &str, language: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE html>\n<html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" lang=\"{lang}\" xml:lang=\"{lang}\">\n<head>\n<meta charset=\"utf-8\"/>\n<title>{}</title>\n<link rel=\"stylesheet\" type=\"text/css\" href=\"style.css\"/>\n</head>\n<body>\n{}\n</body>\n</html>\n",
+        escape(title),
+        body,
+        lang = escape(language),
+    )
+}
+
+/// Chapters of the book made of `files`: those listed in a summary, or the
+/// files themselves. Also returns the title of the summary.
+fn chapters(root: &Path, files: &[String]) -> io::Result<(Option<String>, Vec<Chapter>)> {
+    if let [file] = files {
+        let path = root.join(file);
+        if summary::is_summary(&path) {
+            let md = fs::read_to_string(&path)?;
+            let summary = summary::parse(&md, path.parent().unwrap_or(root));
+            // Draft chapters have nothing to show.
+            let chapters = summary
+                .chapters
+                .into_iter()
+                .filter(|chapter| chapter.path.is_some())
+                .collect();
+            return Ok((summary.title, chapters));
+        }
+    }
+    let chapters = files
+        .iter()
+        .map(|file| Chapter {
+            title: String::new(),
+            path: Some(root.join(file)),
+            level: 1,
+        })
+        .collect();
+    Ok((None, chapters))
+}
+
+/// Render `files`, relative to `root`, as an EPUB book written to `output`.
+pub fn write(
+    options: &markdown::Options,
+    root: &Path,
+    files: &[String],
+    output: &Path,
+) -> io::Result<()> {
+    let (title, mut chapters) = chapters(root, files)?;
+    if chapters.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "no chapters"));
+    }
+    let paths: Vec<PathBuf> = chapters
+        .iter()
+        .map(|chapter| chapter.path.clone().unwrap_or_default())
+        .map(|path| path.canonicalize().unwrap_or(path))
+        .collect();
+
+    let mut zip = Zip::default();
+    zip.add("mimetype", b"application/epub+zip");
+    zip.add(
+        "META-INF/container.xml",
+        b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\n<rootfiles>\n<rootfile full-path=\"OEBPS/content.opf\" media-type=\"application/oebps-package+xml\"/>\n</rootfiles>\n</container>\n",
+    );
+
+    let mut metadata = Vec::new();
+    let mut manifest = String::new();
+    let mut spine = String::new();
+    // Stored images by their path on disk.
+    let mut images = HashMap::<PathBuf, String>::new();
+    for (i, chapter) in chapters.iter_mut().enumerate() {
+        let path = &paths[i];
+        let md = include::expand(&fs::read_to_string(path)?, path, root);
+        let base = links::Base::new(root, path);
+        let options = markdown::Options {
+            server_math: true,
+            // Mermaid diagrams are drawn by a script.
+            mermaid: false,
+            editable_tasks: false,
+            links: Some(base.clone()),
+            ..options.clone()
+        };
+        let doc = markdown::render(&md, &options);
+        if chapter.title.is_empty() {
+            chapter.title = doc
+                .title()
+                .map(str::to_string)
+                .or_else(|| doc.headings.first().map(|heading| heading.text.clone()))
+                .unwrap_or_else(|| {
+                    path.file_stem()
+                        .map(|stem| stem.to_string_lossy().to_string())
+                        .unwrap_or_default()
+                });
+        }
+        if i == 0 {
+            metadata = doc.metadata.clone();
+        }
+
+        let html = rewrite_urls(&doc.html, "img", "src", |url| {
+            let file = local_path(url, root, &base.dir)?;
+            let file = file.canonicalize().ok()?;
+            if let Some(name) = images.get(&file) {
+                return Some(name.clone());
+            }
+            let data = fs::read(&file).ok()?;
+            let ext = file.extension()?.to_str()?.to_ascii_lowercase();
+            let media_type = mime::from_extension(&ext).filter(|m| m.starts_with("image/"))?;
+            let name = format!("images/{}.{}", images.len() + 1, ext);
+            zip.add(&format!("OEBPS/{}", name), &data);
+            manifest.push_str(&format!(
+                "<item id=\"image-{}\" href=\"{}\" media-type=\"{}\"/>\n",
+                images.len() + 1,
+                name,
+                media_type
+            ));
+            images.insert(file, name.clone());
+            Some(name)
+        });
+        // Links to other chapters point into the book.
+        let html = rewrite_urls(&html, "a", "href", |url| {
+            let (file, fragment) = match url.split_once('#') {
+                Some((file, fragment)) => (file, Some(fragment)),
+                None => (url, None),
+            };
+            if file.is_empty() {
+                return None;
+            }
+            let target = local_path(file, root, &base.dir)?.canonicalize().ok()?;
+            let index = paths.iter().position(|path| *path == target)?;
+            Some(match fragment {
+                Some(fragment) => format!("{}#{}", chapter_file(index), fragment),
+                None => chapter_file(index),
+            })
+        });
+        let body = format!("<div class=\"markdown-body\">\n{}</div>", to_xhtml(&html));
+        let mut properties = Vec::new();
+        if body.contains("<math") {
+            properties.push("mathml");
+        }
+        if body.contains("<svg") {
+            properties.push("svg");
+        }
+        let properties = match properties.is_empty() {
+            true => String::new(),
+            false => format!(" properties=\"{}\"", properties.join(" ")),
+        };
+        manifest.push_str(&format!(
+            "<item id=\"chapter-{n}\" href=\"{}\" media-type=\"application/xhtml+xml\"{}/>\n",
+            chapter_file(i),
+            properties,
+            n = i + 1,
+        ));
+        spine.push_str(&format!("<itemref idref=\"chapter-{}\"/>\n", i + 1));
+        let language = field(&metadata, "lang").unwrap_or("en");
+        zip.add(
+            &format!("OEBPS/{}", chapter_file(i)),
+            xhtml_document(&chapter.title, &body, language).as_bytes(),
+        );
+    }
+
+    let title = title
+        .as_deref()
+        .or(field(&metadata, "title"))
+        .or_else(|| output.file_stem().and_then(|stem| stem.to_str()))
+        .unwrap_or("mdopen");
+    let language = field(&metadata, "lang").unwrap_or("en");
+    let nav = format!(
+        "<nav epub:type=\"toc\" id=\"toc\">\n<h1>Contents</h1>\n{}\n</nav>",
+        nav_html(&chapters)
+    );
+    zip.add(
+        "OEBPS/nav.xhtml",
+        xhtml_document(title, &nav, language).as_bytes(),
+    );
+    let mut style = GITHUB_STYLE.to_vec();
+    style.extend(STYLE.as_bytes());
+    zip.add("OEBPS/style.css", &style);
+
+    let mut hasher = DefaultHasher::new();
+    title.hash(&mut hasher);
+    paths.hash(&mut hasher);
+    let creator = field(&metadata, "author")
+        .map(|author| format!("<dc:creator>{}</dc:creator>\n", escape(author)))
+        .unwrap_or_default();
+    let opf = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"id\">\n<metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n<dc:identifier id=\"id\">urn:mdopen:{:016x}</dc:identifier>\n<dc:title>{}</dc:title>\n{}<dc:language>{}</dc:language>\n<meta property=\"dcterms:modified\">{}</meta>\n</metadata>\n<manifest>\n<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n<item id=\"style\" href=\"style.css\" media-type=\"text/css\"/>\n{}</manifest>\n<spine>\n{}</spine>\n</package>\n",
+        hasher.finish(),
+        escape(title),
+        creator,
+        escape(language),
+        humantime::format_rfc3339_seconds(SystemTime::now()),
+        manifest,
+        spine,
+    );
+    zip.add("OEBPS/content.opf", opf.as_bytes());
+    fs::write(output, zip.finish())
+}
+
+/// Non-empty frontmatter field.
+fn field<'a>(metadata: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    metadata
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.as_str())
+        .filter(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive;
+    use std::env;
+
+    #[test]
+    fn html_becomes_xhtml() {
+        assert_eq!(
+            to_xhtml("<p>a<br>b &nbsp;&amp; c & d</p><img src=\"x.png\" alt='it\"s'><details open><summary>s</summary></details><svg viewBox=\"0 0 1 1\"></svg> 1 < 2"),
+            "<p>a<br/>b &#160;&amp; c &amp; d</p><img src=\"x.png\" alt=\"it&quot;s\"/><details open=\"open\"><summary>s</summary></details><svg viewBox=\"0 0 1 1\" xmlns=\"http://www.w3.org/2000/svg\"></svg> 1 &lt; 2"
+        );
+    }
+
+    #[test]
+    fn nav_lists_are_nested() {
+        let chapter = |level| Chapter {
+            title: "c".into(),
+            path: None,
+            level,
+        };
+        let link = |i| format!("<li><a href=\"chapter-{}.xhtml\">c</a>", i);
+        assert_eq!(
+            nav_html(&[chapter(1), chapter(2), chapter(3), chapter(1), chapter(3)]),
+            format!(
+                "<ol>{}<ol>{}<ol>{}</li></ol></li></ol></li>{}<ol>{}</li></ol></li></ol>",
+                link(1),
+                link(2),
+                link(3),
+                link(4),
+                link(5)
+            )
+        );
+    }
+
+    #[test]
+    fn books_are_zipped() {
+        let dir = env::temp_dir().join("mdopen-test-epub");
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("SUMMARY.md"), "# Book\n\n- [One](one.md)\n").unwrap();
+        fs::write(dir.join("one.md"), "Hi ![p](p.png) [again](one.md#x)\n").unwrap();
+        fs::write(dir.join("p.png"), b"\x89PNG\r\n\x1a\n").unwrap();
+
+        let options = markdown::Options::default();
+        write(
+            &options,
+            &dir,
+            &["SUMMARY.md".into()],
+            // Archives are recognized by their extension.
+            &dir.join("book.zip"),
+        )
+        .unwrap();
+
+        let book = dir.join("book.zip");
+        let names: Vec<String> = archive::list(&book)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert_eq!(names[0], "mimetype");
+        assert!(names.contains(&"OEBPS/images/1.png".to_string()));
+        let read = |name| String::from_utf8(archive::read(&book, name).unwrap().unwrap()).unwrap();
+        assert_eq!(read("mimetype"), "application/epub+zip");
+        let chapter = read("OEBPS/chapter-1.xhtml");
+        assert!(chapter.contains("<img src=\"images/1.png\" alt=\"p\" loading=\"lazy\"/>"));
+        assert!(chapter.contains("<a href=\"chapter-1.xhtml#x\">again</a>"));
+        assert!(read("OEBPS/content.opf").contains("<dc:title>Book</dc:title>"));
+        assert!(read("OEBPS/nav.xhtml").contains("<li><a href=\"chapter-1.xhtml\">One</a></li>"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,383 @@
+//! Rendering of a file to HTML without starting the server, for `mdopen render`.
+//!
+//! With `--self-contained` the page needs no server: the stylesheet is
+//! inlined, local images are embedded as data URIs and math is rendered to
+//! MathML. Code is not highlighted, as highlight.js would be loaded from the
+//! CDN; Mermaid diagrams still are.
+//!
+//! `--format man` converts markdown to a man page instead of HTML, and
+//! `--format json` describes its structure. `--format text` and `--format ansi`
+//! render it for reading in a terminal.
+//!
+//! An `.epub` output is written by the `epub` module instead, and a
+//! `SUMMARY.md` rendered to a directory is exported as a book by `site`.
+
+use crate::{
+    epub, include, links, man, markdown, markdown_page, mime, minify, outline, site, summary, text,
+    AppConfig, GITHUB_STYLE, STATIC_PREFIX, STDIN_MARKDOWN,
+};
+use percent_encoding::percent_decode_str;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub const USAGE: &str =
+    "usage: mdopen render [OPTIONS] FILE|- [-o|--output FILE] [--self-contained] [--fragment] [--format html|man|json|text|ansi] [--width COLUMNS] [--minify]
+       mdopen render [OPTIONS] FILES...|SUMMARY.md -o BOOK.epub
+       mdopen render [OPTIONS] SUMMARY.md -o DIR";
+
+/// What `mdopen render` converts markdown to.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Html,
+    /// troff man page.
+    Man,
+    /// Document structure, see `outline`.
+    Json,
+    /// Wrapped plain text.
+    Text,
+    /// Text styled with ANSI escapes, for terminals.
+    Ansi,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "html" => Ok(Format::Html),
+            "man" => Ok(Format::Man),
+            "json" => Ok(Format::Json),
+            "text" => Ok(Format::Text),
+            "ansi" => Ok(Format::Ansi),
+            _ => Err(format!(
+                "expected html, man, json, text or ansi, got {:?}",
+                s
+            )),
+        }
+    }
+}
+
+/// How `mdopen render` writes a file.
+#[derive(Debug, Default)]
+pub struct Options {
+    /// File to write to, instead of stdout.
+    pub output: Option<PathBuf>,
+    /// Inline assets, so the page works without the server.
+    pub self_contained: bool,
+    /// Only the rendered markdown, without the page around it.
+    pub fragment: bool,
+    pub format: Format,
+    /// Columns to wrap text output at, instead of the terminal width.
+    pub width: Option<usize>,
+    /// Strip whitespace and comments from HTML and CSS.
+    pub minify: bool,
+}
+
+/// Render the markdown file at `path`, or stdin for `-`.
+fn render(config: &AppConfig, path: &str, export: &Options) -> io::Result<String> {
+    let root = env::current_dir()?;
+    let (md, title, base) = if path == "-" {
+        // Read by `main`, like when serving stdin.
+        let md = STDIN_MARKDOWN.get().cloned().unwrap_or_default();
+        (md, "stdin".to_string(), None)
+    } else {
+        let file = root.join(path);
+        let md = include::expand(&fs::read_to_string(&file)?, &file, &root);
+        let title = file
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        (md, title, Some(links::Base::new(&root, &file)))
+    };
+    match export.format {
+        Format::Html => {}
+        Format::Man => {
+            let name = Path::new(&title).file_stem().unwrap_or_default();
+            return Ok(man::from_markdown(&md, &name.to_string_lossy()));
+        }
+        Format::Json => {
+            let options = markdown::Options {
+                links: base,
+                ..config.markdown.clone()
+            };
+            return Ok(format!("{}\n", outline::to_json(&md, &options)));
+        }
+        Format::Text | Format::Ansi => {
+            // Set by most shells, if exported.
+            let width = export.width.unwrap_or_else(|| {
+                env::var("COLUMNS")
+                    .ok()
+                    .and_then(|columns| columns.parse().ok())
+                    .unwrap_or(text::DEFAULT_WIDTH)
+            });
+            let color = export.format == Format::Ansi;
+            return Ok(text::render(&md, &config.markdown, width, color));
+        }
+    }
+    let dir = base
+        .as_ref()
+        .map_or_else(|| root.clone(), |base| base.dir.clone());
+    if export.fragment {
+        let options = markdown::Options {
+            links: base,
+            ..config.markdown.clone()
+        };
+        let html = markdown::to_html(&md, &options);
+        return Ok(match export.self_contained {
+            true => embed_images(&html, &root, &dir),
+            false => html,
+        });
+    }
+    let html = markdown_page(&md, &title, "", base, config);
+    if !export.self_contained {
+        return Ok(html);
+    }
+    let html = inline_styles(&html, config.syntax_theme_css.as_deref());
+    Ok(embed_images(&without_cdn_scripts(&html), &root, &dir))
+}
+
+/// Remove highlight.js and KaTeX from a page whose math is MathML, so it
+/// loads nothing from the CDN for them.
+fn without_cdn_scripts(html: &str) -> String {
+    ["syntax highlighting", "math"]
+        .into_iter()
+        .fold(html.to_string(), |html, name| {
+            let marker = format!("\t<!-- {} -->\n", name);
+            let Some(start) = html.find(&marker) else {
+                return html;
+            };
+            // A section of the template ends where the next one starts.
+            let body = start + marker.len();
+            let end = html[body..]
+                .find("\n\t<!-- ")
+                .map_or(html.len(), |end| body + end + 1);
+            format!("{}{}", &html[..start], &html[end..])
+        })
+}
+
+/// Inline the stylesheets served by mdopen into a rendered page.
+pub fn inline_styles(html: &str, syntax_theme_css: Option<&str>) -> String {
+    let style = format!(
+        "<style>\n{}\n</style>",
+        String::from_utf8_lossy(GITHUB_STYLE)
+    );
+    let mut html = html.replace(
+        &format!(
+            "<link rel=\"stylesheet\" href=\"{}style.css\">",
+            STATIC_PREFIX
+        ),
+        &style,
+    );
+    if let Some(css) = syntax_theme_css {
+        html = html.replace(
+            &format!(
+                "<link href=\"{}syntax-theme.css\" rel=\"stylesheet\">",
+                STATIC_PREFIX
+            ),
+            &format!("<style>\n{}\n</style>", css),
+        );
+    }
+    html
+}
+
+/// Replace the double quoted `attribute` of `tag` elements, like `src` of
+/// `img`, by what `replace` returns for it.
+pub fn rewrite_urls(
+    html: &str,
+    tag: &str,
+    attribute: &str,
+    mut replace: impl FnMut(&str) -> Option<String>,
+) -> String {
+    let open = format!("<{} ", tag);
+    let name = format!(" {}=\"", attribute);
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find(&open) {
+        let tag_end = rest[start..]
+            .find('>')
+            .map_or(rest.len(), |end| start + end);
+        let Some(value) = rest[start..tag_end]
+            .find(&name)
+            .map(|i| start + i + name.len())
+        else {
+            out.push_str(&rest[..tag_end]);
+            rest = &rest[tag_end..];
+            continue;
+        };
+        let value_end = rest[value..].find('"').map_or(tag_end, |end| value + end);
+        out.push_str(&rest[..value]);
+        let url = &rest[value..value_end];
+        match replace(url) {
+            Some(url) => out.push_str(&url),
+            None => out.push_str(url),
+        }
+        rest = &rest[value_end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Replace the `src` of `<img>` tags pointing at local files with data URIs.
+fn embed_images(html: &str, root: &Path, dir: &Path) -> String {
+    rewrite_urls(html, "img", "src", |url| {
+        local_path(url, root, dir).and_then(|path| data_uri(&path))
+    })
+}
+
+/// The file a relative or root-relative URL in a page points at.
+pub fn local_path(url: &str, root: &Path, dir: &Path) -> Option<PathBuf> {
+    let url = url.replace("&amp;", "&");
+    if url.starts_with("//") || url.starts_with('#') || url.is_empty() {
+        return None;
+    }
+    // Anything with a scheme, like `https:` or `data:`.
+    if let Some(colon) = url.find(':') {
+        if !url[..colon].contains(['/', '?', '#']) {
+            return None;
+        }
+    }
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let path = percent_decode_str(path).decode_utf8_lossy();
+    Some(match path.strip_prefix('/') {
+        Some(path) => root.join(path),
+        None => dir.join(path.as_ref()),
+    })
+}
+
+fn data_uri(path: &Path) -> Option<String> {
+    let data = fs::read(path).ok()?;
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default();
+    let mime = mime::from_extension(ext)
+        .or_else(|| mime::sniff(&data))
+        .filter(|mime| mime.starts_with("image/"))?;
+    Some(format!("data:{};base64,{}", mime, base64(&data)))
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn is_epub(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("epub"))
+}
+
+/// Run `mdopen render`, returning the process exit code.
+pub fn run(mut config: AppConfig, files: &[String], export: &Options) -> i32 {
+    if let Some(output) = export.output.as_deref().filter(|path| is_epub(path)) {
+        let root = env::current_dir().unwrap_or_default();
+        if let Err(e) = epub::write(&config.markdown, &root, files, output) {
+            eprintln!("cannot write {}: {}", output.display(), e);
+            return 1;
+        }
+        return 0;
+    }
+    let [file] = files else {
+        eprintln!("{}", USAGE);
+        return 1;
+    };
+    let book_dir = export
+        .output
+        .as_deref()
+        .filter(|path| path.extension().is_none() && summary::is_summary(Path::new(file)));
+    if let Some(output) = book_dir {
+        let summary = env::current_dir().unwrap_or_default().join(file);
+        let src = summary.parent().unwrap_or(&summary);
+        let built = site::Site::new(&config, src, output).and_then(|mut site| {
+            site.minify = export.minify;
+            site.build()
+        });
+        if let Err(e) = built {
+            eprintln!("cannot write {}: {}", output.display(), e);
+            return 1;
+        }
+        return 0;
+    }
+    // KaTeX would need the page to run scripts from the CDN.
+    config.markdown.server_math |= export.self_contained;
+    let html = match render(&config, file, export) {
+        Ok(html) if export.minify && export.format == Format::Html => minify::html(&html),
+        Ok(html) => html,
+        Err(e) => {
+            eprintln!("cannot render {}: {}", file, e);
+            return 1;
+        }
+    };
+    let written = match &export.output {
+        Some(path) => fs::write(path, html),
+        None => io::stdout().write_all(html.as_bytes()),
+    };
+    if let Err(e) = written {
+        eprintln!("cannot write output: {}", e);
+        return 1;
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_images_are_embedded() {
+        let dir = env::temp_dir().join("mdopen-test-export");
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("img")).unwrap();
+        fs::write(dir.join("img/a b.png"), b"\x89PNG\r\n\x1a\n").unwrap();
+
+        let html = embed_images(
+            "<img src=\"img/a%20b.png\" alt=\"a\"><img src=\"https://x/y.png\"><img src=\"/img/a%20b.png\"><img src=\"missing.png\">",
+            &dir,
+            &dir,
+        );
+        assert_eq!(
+            html,
+            "<img src=\"data:image/png;base64,iVBORw0KGgo=\" alt=\"a\"><img src=\"https://x/y.png\"><img src=\"data:image/png;base64,iVBORw0KGgo=\"><img src=\"missing.png\">"
+        );
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"abc"), "YWJj");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cdn_scripts_are_removed() {
+        let html = markdown_page(
+            "# A\n\n```rust\nfn main() {}\n```\n",
+            "a.md",
+            "",
+            None,
+            &crate::tests::config(),
+        );
+        assert!(html.contains("highlight.min.js") && html.contains("katex"));
+        let html = without_cdn_scripts(&html);
+        assert!(!html.contains("highlight.min.js"));
+        assert!(!html.contains("katex"));
+        assert!(html.contains("\t<!-- highlight the current section in the sidebar -->"));
+        assert!(html.contains("<code class=\"language-rust\">"));
+    }
+}
@@ -0,0 +1,176 @@
+//! Atom feed of the markdown files in a directory, newest first, for
+//! `--feed`. Useful to follow a changelog or a directory of decision records.
+//!
+//! Files are dated by the `updated` or `date` field of their frontmatter, and
+//! by their modification time otherwise.
+
+use crate::{frontmatter, markdown, wikilink};
+use pulldown_cmark_escape::escape_html;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Path of the feed, served and exported.
+pub const FEED_FILE: &str = "feed.xml";
+
+/// Entries in a feed, the newest ones.
+const MAX_ENTRIES: usize = 20;
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    _ = escape_html(&mut escaped, text);
+    escaped
+}
+
+pub fn field<'a>(metadata: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    metadata
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, value)| value.trim())
+        .filter(|value| !value.is_empty())
+}
+
+/// Parse a date like `2024-05-01` or `2024-05-01T12:00:00Z`.
+fn parse_date(date: &str) -> Option<SystemTime> {
+    let date = date.trim_matches(['"', '\'']);
+    match date.len() {
+        10 => humantime::parse_rfc3339_weak(&format!("{}T00:00:00Z", date)).ok(),
+        _ => humantime::parse_rfc3339_weak(date).ok(),
+    }
+}
+
+/// Markdown file listed in a feed or an index.
+pub struct Entry {
+    pub path: PathBuf,
+    pub md: String,
+    pub metadata: Vec<(String, String)>,
+    pub updated: SystemTime,
+}
+
+impl Entry {
+    /// Title from the frontmatter or the first heading, or the file name.
+    pub fn title(&self, doc: &markdown::Document) -> String {
+        doc.title()
+            .or(doc.headings.first().map(|heading| heading.text.as_str()))
+            .map(str::to_string)
+            .unwrap_or_else(|| {
+                let stem = self.path.file_stem().unwrap_or_default();
+                stem.to_string_lossy().to_string()
+            })
+    }
+}
+
+/// Markdown files under `root`, newest first.
+pub fn entries(root: &Path, options: &markdown::Options) -> Vec<Entry> {
+    let mut files = Vec::new();
+    wikilink::markdown_files(root, &mut files);
+    let mut entries: Vec<Entry> = files
+        .into_iter()
+        .filter_map(|path| {
+            let md = fs::read_to_string(&path).ok()?;
+            let metadata = markdown::frontmatter_fields(&md, options);
+            let updated = field(&metadata, "updated")
+                .or(field(&metadata, "date"))
+                .and_then(parse_date)
+                .or_else(|| fs::metadata(&path).and_then(|m| m.modified()).ok())?;
+            Some(Entry {
+                path,
+                md,
+                metadata,
+                updated,
+            })
+        })
+        .collect();
+    entries.sort_by(|a, b| b.updated.cmp(&a.updated).then(a.path.cmp(&b.path)));
+    entries
+}
+
+/// Atom feed of the markdown files under `root`.
+///
+/// `base` is the URL of the directory, and `url` gives the URL of the page
+/// of a file relative to it.
+pub fn atom(
+    root: &Path,
+    options: &markdown::Options,
+    base: &str,
+    url: impl Fn(&Path) -> String,
+) -> String {
+    let mut entries = entries(root, options);
+    entries.truncate(MAX_ENTRIES);
+    let name = root
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "mdopen".to_string());
+    let updated = entries
+        .first()
+        .map_or(SystemTime::UNIX_EPOCH, |entry| entry.updated);
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n<title>{name}</title>\n<id>{base}</id>\n<link href=\"{base}\"/>\n<link rel=\"self\" href=\"{base}{FEED_FILE}\"/>\n<updated>{}</updated>\n<author><name>{name}</name></author>\n",
+        humantime::format_rfc3339_seconds(updated),
+        name = escape(&name),
+        base = escape(base),
+    );
+    for entry in entries {
+        let doc = markdown::render(&entry.md, options);
+        let title = entry.title(&doc);
+        let link = format!("{}{}", base, url(&entry.path));
+        xml.push_str(&format!(
+            "<entry>\n<title>{}</title>\n<id>{link}</id>\n<link href=\"{link}\"/>\n<updated>{}</updated>\n",
+            escape(&title),
+            humantime::format_rfc3339_seconds(entry.updated),
+            link = escape(&link),
+        ));
+        if let Some(author) = field(&entry.metadata, "author") {
+            xml.push_str(&format!(
+                "<author><name>{}</name></author>\n",
+                escape(author)
+            ));
+        }
+        // Without the table of frontmatter fields.
+        let table = frontmatter::to_html(&doc.metadata);
+        let html = doc.html.strip_prefix(&table).unwrap_or(&doc.html);
+        xml.push_str(&format!(
+            "<content type=\"html\">{}</content>\n</entry>\n",
+            escape(html)
+        ));
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn newest_files_come_first() {
+        let dir = env::temp_dir().join("mdopen-test-feed");
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("adr")).unwrap();
+        fs::write(
+            dir.join("adr/old.md"),
+            "---\ndate: 2020-01-02\n---\n# Old & gone\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("adr/new.md"),
+            "---\ntitle: New\ndate: 2021-03-04T05:06:07Z\nauthor: Ann\n---\nText\n",
+        )
+        .unwrap();
+        fs::write(dir.join(".hidden.md"), "# Hidden\n").unwrap();
+
+        let options = markdown::Options::default();
+        let xml = atom(&dir, &options, "http://localhost/", |path| {
+            path.strip_prefix(&dir)
+                .unwrap()
+                .to_string_lossy()
+                .to_string()
+        });
+        assert!(xml.contains("<updated>2021-03-04T05:06:07Z</updated>\n<author><name>mdopen-test-feed</name></author>\n<entry>\n<title>New</title>\n<id>http://localhost/adr/new.md</id>\n<link href=\"http://localhost/adr/new.md\"/>\n<updated>2021-03-04T05:06:07Z</updated>\n<author><name>Ann</name></author>\n<content type=\"html\">&lt;p&gt;Text&lt;/p&gt;\n</content>\n</entry>\n<entry>\n<title>Old &amp; gone</title>"));
+        assert!(xml.contains("<updated>2020-01-02T00:00:00Z</updated>"));
+        assert!(!xml.contains("Hidden"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
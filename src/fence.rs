@@ -0,0 +1,468 @@
+//! Rendering of code blocks, with attributes from the fence info string.
+
+use crate::ansi;
+use pulldown_cmark_escape::escape_html;
+use std::borrow::Cow;
+
+/// What a fence info string like `rust linenos` asks for.
+#[derive(Debug, Default, PartialEq)]
+pub struct Info<'a> {
+    pub language: Option<&'a str>,
+    /// Show a line number gutter.
+    pub line_numbers: bool,
+    /// Number of the first line in the gutter, from `startFrom="10"`.
+    pub start_line: Option<usize>,
+    /// File name shown above the code, from `title="src/main.rs"`.
+    pub title: Option<&'a str>,
+    /// Inclusive ranges of emphasized lines, from `{3,7-9}` or `hl_lines="3 7-9"`.
+    pub highlight: Vec<(usize, usize)>,
+    /// Other attributes, like `no_run` in `rust,no_run` or `example` in
+    /// `{.python .example}`.
+    pub attributes: Vec<&'a str>,
+}
+
+impl<'a> Info<'a> {
+    pub fn parse(info: &'a str) -> Self {
+        let mut parsed = Info::default();
+        let (before, braces, after) = split_braces(info);
+        for (i, word) in Words(before).chain(Words(after)).enumerate() {
+            match word.split_once('=') {
+                Some(_) => parsed.attribute(word),
+                // Like pulldown-cmark, only the first word is the language.
+                // Rustdoc style attributes may follow it, as in `rust,no_run`.
+                None if i == 0 && !before.trim().is_empty() => {
+                    let mut parts = word.split(',').filter(|part| !part.is_empty());
+                    if !word.starts_with(',') {
+                        parsed.language = parts.next();
+                    }
+                    parsed.attributes.extend(parts);
+                }
+                None if word == "linenos" => parsed.line_numbers = true,
+                None => {}
+            }
+        }
+        match braces {
+            // Pandoc attributes, like `{.haskell .numberLines startFrom="10"}`.
+            Some(braces) if Words(braces).any(|w| w.starts_with(['.', '#']) || w.contains('=')) => {
+                for word in Words(braces) {
+                    match word.strip_prefix('.') {
+                        Some("numberLines" | "number-lines" | "linenos") => {
+                            parsed.line_numbers = true
+                        }
+                        Some(class) if parsed.language.is_none() => parsed.language = Some(class),
+                        Some(class) => parsed.attributes.push(class),
+                        None if word.contains('=') => parsed.attribute(word),
+                        None => {}
+                    }
+                }
+            }
+            Some(ranges) => parsed.highlight.extend(line_ranges(ranges)),
+            None => {}
+        }
+        parsed
+    }
+
+    /// Apply a `key=value` attribute.
+    fn attribute(&mut self, word: &'a str) {
+        let Some((key, value)) = word.split_once('=') else {
+            return;
+        };
+        let value = unquote(value);
+        match key {
+            "title" => self.title = Some(value),
+            "hl_lines" => self.highlight.extend(line_ranges(value)),
+            "startFrom" | "start-from" | "linenostart" => {
+                self.start_line = value.parse().ok();
+                self.line_numbers |= self.start_line.is_some();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Split the first `{...}` group out of an info string, leaving what is
+/// before and after it.
+fn split_braces(info: &str) -> (&str, Option<&str>, &str) {
+    let Some(open) = info.find('{') else {
+        return (info, None, "");
+    };
+    // A brace in a quoted value like `title="{x}"` is not a group.
+    if info[..open].matches('"').count() % 2 == 1 {
+        return (info, None, "");
+    }
+    match info[open..].find('}') {
+        Some(close) => (
+            &info[..open],
+            Some(&info[open + 1..open + close]),
+            &info[open + close + 1..],
+        ),
+        None => (info, None, ""),
+    }
+}
+
+/// Replace the language of an info string by what it is an alias of, so
+/// `viml` can be highlighted as `vim`.
+pub fn resolve_alias<'a>(info: &'a str, aliases: &[(String, String)]) -> Cow<'a, str> {
+    let info = info.trim_start();
+    let end = info
+        .find(|c: char| c.is_whitespace() || c == '{' || c == ',')
+        .unwrap_or(info.len());
+    match aliases.iter().find(|(alias, _)| *alias == info[..end]) {
+        Some((_, language)) => Cow::Owned(format!("{}{}", language, &info[end..])),
+        None => Cow::Borrowed(info),
+    }
+}
+
+/// Words of an info string, keeping quoted values like `title="a b"` together.
+struct Words<'a>(&'a str);
+
+impl<'a> Iterator for Words<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let rest = self.0.trim_start();
+        if rest.is_empty() {
+            return None;
+        }
+        let mut quote = None;
+        let end = rest
+            .char_indices()
+            .find(|&(_, c)| match quote {
+                Some(q) if c == q => {
+                    quote = None;
+                    false
+                }
+                Some(_) => false,
+                None if c == '"' || c == '\'' => {
+                    quote = Some(c);
+                    false
+                }
+                None => c.is_whitespace(),
+            })
+            .map_or(rest.len(), |(i, _)| i);
+        self.0 = &rest[end..];
+        Some(&rest[..end])
+    }
+}
+
+/// Parse line numbers and ranges like `3,7-9` or `3 7-9`, skipping invalid ones.
+fn line_ranges(spec: &str) -> impl Iterator<Item = (usize, usize)> + '_ {
+    spec.split([',', ' '])
+        .filter_map(|range| match range.split_once('-') {
+            Some((start, end)) => Some((start.trim().parse().ok()?, end.trim().parse().ok()?)),
+            None => {
+                let line = range.trim().parse().ok()?;
+                Some((line, line))
+            }
+        })
+        .filter(|&(start, end)| start > 0 && start <= end)
+}
+
+/// Line height of code blocks in `em`, as set by the GitHub stylesheet.
+const LINE_HEIGHT: f32 = 1.45;
+
+/// Inline style painting a background behind the given lines.
+///
+/// A gradient on the code element survives highlight.js replacing its content.
+fn highlight_style(ranges: &[(usize, usize)]) -> String {
+    const COLOR: &str = "rgba(234, 179, 8, 0.2)";
+    let mut gradient = String::from("linear-gradient(");
+    let mut end = 0.0;
+    for &(first, last) in ranges {
+        let top = (first - 1) as f32 * LINE_HEIGHT;
+        let bottom = last as f32 * LINE_HEIGHT;
+        gradient.push_str(&format!(
+            "transparent {}em {}em, {} {}em {}em, ",
+            end, top, COLOR, top, bottom
+        ));
+        end = bottom;
+    }
+    gradient.push_str(&format!("transparent {}em)", end));
+    format!(
+        "display: block; background-origin: content-box; background-repeat: no-repeat; background-image: {}",
+        gradient
+    )
+}
+
+/// Write diff lines, each in a span classed by what the line is.
+fn push_diff(html: &mut String, source: &str) {
+    for line in source.lines() {
+        let kind = if line.starts_with("+++") || line.starts_with("---") {
+            "header"
+        } else if line.starts_with("@@") {
+            "hunk"
+        } else if line.starts_with('+') {
+            "added"
+        } else if line.starts_with('-') {
+            "removed"
+        } else {
+            "context"
+        };
+        html.push_str(&format!("<span class=\"diff-line diff-{}\">", kind));
+        _ = escape_html(&mut *html, line);
+        html.push_str("</span>\n");
+    }
+}
+
+/// Length of the shell prompt `line` starts with, like `$ `, `user@host:~$ `
+/// or `(venv) [me@box src]# `.
+fn prompt_len(line: &str) -> Option<usize> {
+    let mut rest = line;
+    // A virtualenv or conda environment, like `(venv) `.
+    if let Some((_, after)) = rest.strip_prefix('(').and_then(|r| r.split_once(") ")) {
+        rest = after;
+    }
+    if rest.starts_with('[') {
+        rest = &rest[rest.find(']')? + 1..];
+    } else {
+        // Prefixes like `user@host:~` are a single word.
+        let end = rest.find(['$', '#', '%'])?;
+        let prefix = &rest[..end];
+        if prefix.contains(char::is_whitespace)
+            || !(prefix.is_empty() || prefix.contains(['@', ':', '~']))
+        {
+            return None;
+        }
+        rest = &rest[end..];
+    }
+    let after = rest.strip_prefix(['$', '#', '%'])?;
+    match after.strip_prefix(' ') {
+        Some(command) => Some(line.len() - command.len()),
+        None if after.is_empty() => Some(line.len()),
+        None => None,
+    }
+}
+
+/// Write a shell session, with commands and their output in separate spans so
+/// that only commands are copied.
+fn push_console(html: &mut String, source: &str) {
+    let mut continued = false;
+    for line in source.lines() {
+        // Escapes are only interpreted within a line.
+        let prompt = if continued { Some(0) } else { prompt_len(line) };
+        match prompt {
+            Some(prompt) => {
+                html.push_str("<span class=\"console-command\"><span class=\"console-prompt\">");
+                _ = escape_html(&mut *html, &line[..prompt]);
+                html.push_str("</span><span class=\"console-input\">");
+                html.push_str(&ansi::to_html(&line[prompt..]));
+                html.push_str("</span></span>\n");
+                continued = line.ends_with('\\');
+            }
+            None => {
+                html.push_str("<span class=\"console-output\">");
+                html.push_str(&ansi::to_html(line));
+                html.push_str("</span>\n");
+            }
+        }
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = value
+            .strip_prefix(quote)
+            .and_then(|v| v.strip_suffix(quote))
+        {
+            return inner;
+        }
+    }
+    value
+}
+
+/// Render a code block for highlighting in the browser, with a copy button.
+pub fn to_html(info: &str, source: &str, line_numbers: bool) -> String {
+    let info = Info::parse(info);
+    let mut html = String::from("<div class=\"code-block\"");
+    if !info.attributes.is_empty() {
+        html.push_str(" data-attributes=\"");
+        _ = escape_html(&mut html, &info.attributes.join(" "));
+        html.push('"');
+    }
+    html.push('>');
+    if let Some(title) = info.title {
+        html.push_str("<div class=\"code-title\">");
+        _ = escape_html(&mut html, title);
+        html.push_str("</div>");
+    }
+    html.push_str("<button class=\"copy-code\" type=\"button\">Copy</button><pre>");
+    if line_numbers || info.line_numbers {
+        html.push_str("<span class=\"line-numbers\" aria-hidden=\"true\">");
+        let start = info.start_line.unwrap_or(1);
+        for n in start..start + source.lines().count() {
+            html.push_str(&format!("{}\n", n));
+        }
+        html.push_str("</span>");
+    }
+    html.push_str("<code");
+    let diff = info.language == Some("diff");
+    let terminal = info.language == Some("ansi");
+    let console = matches!(
+        info.language,
+        Some("console" | "shell-session" | "sh-session")
+    );
+    if diff {
+        // Colored by line below, so highlight.js should leave it alone.
+        html.push_str(" class=\"language-diff nohighlight\"");
+    } else if terminal {
+        html.push_str(" class=\"language-ansi nohighlight\"");
+    } else if console {
+        html.push_str(" class=\"language-console nohighlight\"");
+    } else if let Some(language) = info.language {
+        html.push_str(" class=\"language-");
+        _ = escape_html(&mut html, language);
+        html.push('"');
+    }
+    if !info.highlight.is_empty() {
+        let mut ranges = info.highlight;
+        ranges.sort();
+        html.push_str(" style=\"");
+        html.push_str(&highlight_style(&ranges));
+        html.push('"');
+    }
+    html.push('>');
+    if diff {
+        push_diff(&mut html, source);
+    } else if terminal {
+        html.push_str(&ansi::to_html(source));
+    } else if console {
+        push_console(&mut html, source);
+    } else {
+        _ = escape_html(&mut html, source);
+    }
+    html.push_str("</code></pre></div>\n");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_numbers_from_flag_or_fence() {
+        let html = to_html("rust linenos", "a\nb\n", false);
+        assert!(html.contains("<span class=\"line-numbers\" aria-hidden=\"true\">1\n2\n</span>"));
+        assert!(html.contains("<code class=\"language-rust\">a\nb\n</code>"));
+        assert!(to_html("", "a\n", true).contains("line-numbers"));
+        assert!(!to_html("rust", "a\n", false).contains("line-numbers"));
+    }
+
+    #[test]
+    fn aliases_are_resolved() {
+        let aliases = [
+            ("viml".to_string(), "vim".to_string()),
+            ("shell".to_string(), "bash".to_string()),
+        ];
+        assert_eq!(resolve_alias("viml", &aliases), "vim");
+        assert_eq!(
+            resolve_alias("shell{2} linenos", &aliases),
+            "bash{2} linenos"
+        );
+        assert_eq!(resolve_alias("vimlx", &aliases), "vimlx");
+        assert_eq!(resolve_alias("", &aliases), "");
+    }
+
+    #[test]
+    fn titles_can_be_quoted() {
+        let info = Info::parse(r#"rust title="src/my main.rs" linenos"#);
+        assert_eq!(info.language, Some("rust"));
+        assert_eq!(info.title, Some("src/my main.rs"));
+        assert!(info.line_numbers);
+        assert_eq!(Info::parse("sh title=run.sh").title, Some("run.sh"));
+        assert_eq!(Info::parse("title=run.sh").language, None);
+    }
+
+    #[test]
+    fn extra_attributes() {
+        let info = Info::parse("rust,no_run,ignore");
+        assert_eq!(info.language, Some("rust"));
+        assert_eq!(info.attributes, ["no_run", "ignore"]);
+
+        let info = Info::parse("{.haskell .numberLines .example startFrom=\"10\"}");
+        assert_eq!(info.language, Some("haskell"));
+        assert!(info.line_numbers);
+        assert_eq!(info.start_line, Some(10));
+        assert_eq!(info.attributes, ["example"]);
+
+        let info = Info::parse("rust {.numberLines} title=\"{x}.rs\"");
+        assert_eq!(info.language, Some("rust"));
+        assert!(info.line_numbers);
+        assert_eq!(info.title, Some("{x}.rs"));
+        assert_eq!(info.highlight, []);
+
+        let html = to_html("rust,ignore {.numberLines startFrom=7}", "a\nb\n", false);
+        assert!(html.starts_with("<div class=\"code-block\" data-attributes=\"ignore\">"));
+        assert!(html.contains(">7\n8\n</span><code class=\"language-rust\">"));
+    }
+
+    #[test]
+    fn highlighted_line_ranges() {
+        let ranges = |info| Info::parse(info).highlight;
+        assert_eq!(ranges("python {3,7-9}"), [(3, 3), (7, 9)]);
+        assert_eq!(ranges("python{1}"), [(1, 1)]);
+        assert_eq!(ranges(r#"python hl_lines="2 4-5""#), [(2, 2), (4, 5)]);
+        assert_eq!(ranges("python {0,5-2,x}"), []);
+        assert_eq!(Info::parse("python{1}").language, Some("python"));
+
+        let html = to_html("python {2}", "a\nb\n", false);
+        assert!(html.contains("transparent 0em 1.45em, rgba(234, 179, 8, 0.2) 1.45em 2.9em"));
+    }
+
+    #[test]
+    fn diff_lines_are_classified() {
+        let html = to_html(
+            "diff",
+            "--- a\n+++ b\n@@ -1 +1 @@\n-old\n+new\n same\n",
+            false,
+        );
+        assert!(html.contains("nohighlight"));
+        for line in [
+            "<span class=\"diff-line diff-header\">--- a</span>",
+            "<span class=\"diff-line diff-hunk\">@@ -1 +1 @@</span>",
+            "<span class=\"diff-line diff-removed\">-old</span>",
+            "<span class=\"diff-line diff-added\">+new</span>",
+            "<span class=\"diff-line diff-context\"> same</span>",
+        ] {
+            assert!(html.contains(line), "{}", line);
+        }
+    }
+
+    #[test]
+    fn terminal_output_is_colored() {
+        let html = to_html("ansi", "\x1b[34mdir\x1b[0m\n", false);
+        assert!(html.contains("<code class=\"language-ansi nohighlight\"><span class=\"ansi-blue\">dir</span>\n</code>"));
+    }
+
+    #[test]
+    fn console_commands_and_output() {
+        let html = to_html(
+            "console",
+            "$ echo a \\\n  b\na b\nme@box:~/src# ls\n\x1b[34mdir\x1b[0m\n$\n",
+            false,
+        );
+        let command = |prompt, input| {
+            format!(
+                "<span class=\"console-command\"><span class=\"console-prompt\">{}</span><span class=\"console-input\">{}</span></span>\n",
+                prompt, input
+            )
+        };
+        let expected = [
+            command("$ ", "echo a \\"),
+            command("", "  b"),
+            "<span class=\"console-output\">a b</span>\n".to_string(),
+            command("me@box:~/src# ", "ls"),
+            "<span class=\"console-output\"><span class=\"ansi-blue\">dir</span></span>\n"
+                .to_string(),
+            command("$", ""),
+        ]
+        .concat();
+        assert!(html.contains(&format!(
+            "<code class=\"language-console nohighlight\">{}</code>",
+            expected
+        )));
+
+        assert_eq!(prompt_len("(venv) [me@box src]$ make"), Some(21));
+        assert_eq!(prompt_len("100% done"), None);
+        assert_eq!(prompt_len("cost: $5"), None);
+    }
+}
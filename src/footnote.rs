@@ -0,0 +1,130 @@
+//! Footnotes laid out like on GitHub: numbered by first reference, listed at
+//! the end of the document, with links back to each reference.
+
+use crate::markdown::slugify;
+use pulldown_cmark::{CowStr, Event, Tag, TagEnd};
+use std::collections::HashMap;
+
+/// A referenced footnote: its number and how many times it is referenced.
+struct Note {
+    number: usize,
+    references: usize,
+}
+
+fn reference_id(name: &str, nth: usize) -> String {
+    match nth {
+        1 => format!("fnref-{}", slugify(name)),
+        _ => format!("fnref-{}-{}", slugify(name), nth),
+    }
+}
+
+/// Number footnote references and move definitions into a section at the end.
+///
+/// Definitions that are never referenced are left out, as GitHub does.
+pub fn collect<'a>(events: impl Iterator<Item = Event<'a>>) -> Vec<Event<'a>> {
+    let mut notes = HashMap::<CowStr, Note>::new();
+    let mut definitions = Vec::<(CowStr, Vec<Event>)>::new();
+    let mut current: Option<(CowStr, Vec<Event>)> = None;
+    let mut out = Vec::new();
+
+    for event in events {
+        let event = match event {
+            Event::FootnoteReference(name) => {
+                let next = notes.len() + 1;
+                let note = notes.entry(name.clone()).or_insert(Note {
+                    number: next,
+                    references: 0,
+                });
+                note.references += 1;
+                Event::InlineHtml(
+                    format!(
+                        "<sup><a href=\"#fn-{}\" id=\"{}\" data-footnote-ref>{}</a></sup>",
+                        slugify(&name),
+                        reference_id(&name, note.references),
+                        note.number
+                    )
+                    .into(),
+                )
+            }
+            Event::Start(Tag::FootnoteDefinition(name)) => {
+                current = Some((name, Vec::new()));
+                continue;
+            }
+            Event::End(TagEnd::FootnoteDefinition) => {
+                definitions.extend(current.take());
+                continue;
+            }
+            event => event,
+        };
+        match &mut current {
+            Some((_, content)) => content.push(event),
+            None => out.push(event),
+        }
+    }
+
+    let mut definitions: Vec<_> = definitions
+        .into_iter()
+        .filter_map(|(name, content)| {
+            let note = notes.get(&name)?;
+            Some((note.number, note.references, name, content))
+        })
+        .collect();
+    if definitions.is_empty() {
+        return out;
+    }
+    definitions.sort_by_key(|(number, ..)| *number);
+
+    out.push(Event::Html(
+        "<section class=\"footnotes\" data-footnotes>\n<h2 id=\"footnote-label\" class=\"sr-only\">Footnotes</h2>\n<ol>\n".into(),
+    ));
+    for (number, references, name, mut content) in definitions {
+        let mut backrefs = String::new();
+        for nth in 1..=references {
+            backrefs.push_str(&format!(
+                " <a href=\"#{}\" class=\"data-footnote-backref\" aria-label=\"Back to reference {}\">↩",
+                reference_id(&name, nth),
+                number
+            ));
+            if nth > 1 {
+                backrefs.push_str(&format!("<sup>{}</sup>", nth));
+            }
+            backrefs.push_str("</a>");
+        }
+        // Back links go at the end of the last paragraph, if there is one.
+        let backrefs = Event::InlineHtml(backrefs.into());
+        match content
+            .iter()
+            .rposition(|event| matches!(event, Event::End(TagEnd::Paragraph)))
+        {
+            Some(i) => content.insert(i, backrefs),
+            None => content.push(backrefs),
+        }
+
+        out.push(Event::Html(
+            format!("<li id=\"fn-{}\">\n", slugify(&name)).into(),
+        ));
+        out.extend(content);
+        out.push(Event::Html("</li>\n".into()));
+    }
+    out.push(Event::Html("</ol>\n</section>\n".into()));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::markdown::{to_html, Options};
+
+    #[test]
+    fn footnotes_are_numbered_by_reference() {
+        let md = "[^b] [^a] [^b]\n\n[^a]: A.\n[^b]: B.\n[^unused]: C.\n";
+        let html = to_html(md, &Options::default());
+        assert!(html.contains(r##"<a href="#fn-b" id="fnref-b" data-footnote-ref>1</a>"##));
+        assert!(html.contains(r##"<a href="#fn-a" id="fnref-a" data-footnote-ref>2</a>"##));
+        assert!(html.contains(r##"<a href="#fn-b" id="fnref-b-2" data-footnote-ref>1</a>"##));
+        let b = html.find(r#"<li id="fn-b">"#).unwrap();
+        let a = html.find(r#"<li id="fn-a">"#).unwrap();
+        assert!(b < a);
+        assert!(html.contains(r##"<a href="#fnref-b-2" class="data-footnote-backref" aria-label="Back to reference 1">↩<sup>2</sup></a></p>"##));
+        assert!(!html.contains("C."));
+    }
+}
@@ -0,0 +1,221 @@
+//! Parsing of YAML and TOML frontmatter into flat key/value fields.
+//!
+//! Only the shallow subset used by static site generators is understood:
+//! scalar values, inline arrays, and YAML block lists. Nested tables are
+//! flattened into dotted keys.
+
+use pulldown_cmark_escape::escape_html;
+
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    for quote in ['"', '\''] {
+        if let Some(inner) = value
+            .strip_prefix(quote)
+            .and_then(|v| v.strip_suffix(quote))
+        {
+            return inner.to_string();
+        }
+    }
+    value.to_string()
+}
+
+/// Turn `[a, "b"]` into `a, b`, and unquote other values.
+fn scalar_or_list(value: &str) -> String {
+    let value = value.trim();
+    match value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+        Some(items) => items
+            .split(',')
+            .map(unquote)
+            .filter(|item| !item.is_empty())
+            .collect::<Vec<_>>()
+            .join(", "),
+        None => unquote(value),
+    }
+}
+
+/// Remove a trailing `#` comment that is not inside quotes.
+///
+/// Quotes only start a string at the start of a value, so apostrophes in
+/// plain text don't hide comments.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        let value_start = line[..i].trim_end().ends_with([':', '=', '[', ',', '-'])
+            || line[..i].trim().is_empty();
+        match (c, quote) {
+            ('"' | '\'', None) if value_start => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('#', None) if i == 0 || line[..i].ends_with(char::is_whitespace) => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Parse YAML frontmatter.
+pub fn parse_yaml(source: &str) -> Vec<(String, String)> {
+    let mut fields: Vec<(String, String)> = Vec::new();
+    let mut parent: Option<String> = None;
+    // Keys whose fields were flattened into dotted keys.
+    let mut mappings = Vec::new();
+    for line in source.lines() {
+        let line = strip_comment(line).trim_end();
+        if line.trim().is_empty() || line == "---" {
+            continue;
+        }
+        let indented = line.starts_with([' ', '\t']);
+        let line = line.trim_start();
+
+        if indented || line.starts_with("- ") {
+            // Items of a block list or fields of a mapping under the last key.
+            let Some(parent) = &parent else {
+                continue;
+            };
+            if let Some(item) = line.strip_prefix("- ") {
+                if let Some((_, value)) = fields.iter_mut().rev().find(|(k, _)| k == parent) {
+                    if !value.is_empty() {
+                        value.push_str(", ");
+                    }
+                    value.push_str(&unquote(item));
+                }
+            } else if let Some((key, value)) = line.split_once(':') {
+                fields.push((format!("{}.{}", parent, key.trim()), scalar_or_list(value)));
+                mappings.push(parent.clone());
+            }
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let key = key.trim().to_string();
+        let value = value.trim();
+        if value.is_empty() {
+            parent = Some(key.clone());
+            fields.push((key, String::new()));
+        } else {
+            parent = None;
+            fields.push((key, scalar_or_list(value)));
+        }
+    }
+    fields.retain(|(key, _)| !mappings.contains(key));
+    fields
+}
+
+/// Parse TOML frontmatter.
+pub fn parse_toml(source: &str) -> Vec<(String, String)> {
+    let mut fields = Vec::new();
+    let mut table = String::new();
+    for line in source.lines() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() || line == "+++" {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            table = name.trim_matches(['[', ']']).trim().to_string();
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = unquote(key);
+        let key = if table.is_empty() {
+            key
+        } else {
+            format!("{}.{}", table, key)
+        };
+        fields.push((key, scalar_or_list(value)));
+    }
+    fields
+}
+
+/// Render fields as a table, the way GitHub shows frontmatter.
+pub fn to_html(fields: &[(String, String)]) -> String {
+    if fields.is_empty() {
+        return String::new();
+    }
+    let mut html = String::from("<table class=\"frontmatter\"><thead><tr>");
+    for (key, _) in fields {
+        html.push_str("<th>");
+        _ = escape_html(&mut html, key);
+        html.push_str("</th>");
+    }
+    html.push_str("</tr></thead><tbody><tr>");
+    for (_, value) in fields {
+        html.push_str("<td>");
+        _ = escape_html(&mut html, value);
+        html.push_str("</td>");
+    }
+    html.push_str("</tr></tbody></table>\n");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn yaml_fields() {
+        let yaml = "---
+title: \"A # not a comment\" # comment
+note: it's here # comment
+tags: [a, 'b']
+authors:
+  - Ann
+  - \"Bob\"
+author:
+  name: Ann
+  links: [x, y]
+empty:
+---
+";
+        assert_eq!(
+            parse_yaml(yaml),
+            fields(&[
+                ("title", "A # not a comment"),
+                ("note", "it's here"),
+                ("tags", "a, b"),
+                ("authors", "Ann, Bob"),
+                ("author.name", "Ann"),
+                ("author.links", "x, y"),
+                ("empty", ""),
+            ])
+        );
+    }
+
+    #[test]
+    fn toml_fields() {
+        let toml = "+++
+title = 'A # b' # comment
+\"draft\" = false
+tags = [\"a\", \"b\"]
+
+[params]
+author = \"Ann\"
+[[menu.main]]
+weight = 2
++++
+";
+        assert_eq!(
+            parse_toml(toml),
+            fields(&[
+                ("title", "A # b"),
+                ("draft", "false"),
+                ("tags", "a, b"),
+                ("params.author", "Ann"),
+                ("menu.main.weight", "2"),
+            ])
+        );
+        assert_eq!(
+            to_html(&fields(&[("a", "<b>")])),
+            "<table class=\"frontmatter\"><thead><tr><th>a</th></tr></thead><tbody><tr><td>&lt;b&gt;</td></tr></tbody></table>\n"
+        );
+    }
+}
@@ -0,0 +1,176 @@
+//! Images with size and alignment hints, loaded lazily.
+//!
+//! Sizes come from a `"=400x300"` title, as in some markdown editors, or from
+//! an attribute list like `{width=400 align=right}` right after the image.
+
+use pulldown_cmark::{CowStr, Event, Tag, TagEnd};
+use pulldown_cmark_escape::{escape_href, escape_html};
+use std::iter::Peekable;
+
+/// Attributes of an `img` element besides its source and alt text.
+#[derive(Debug, Default, PartialEq)]
+struct Attributes<'a> {
+    title: &'a str,
+    width: Option<&'a str>,
+    height: Option<&'a str>,
+    align: Option<&'a str>,
+}
+
+/// Whether `size` is a length HTML and CSS both accept, like `400` or `50%`.
+fn is_size(size: &str) -> bool {
+    let digits = size
+        .strip_suffix("px")
+        .or(size.strip_suffix('%'))
+        .unwrap_or(size);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Split a `=400x300` size hint off the end of an image title.
+fn title_size(title: &str) -> (&str, Option<&str>, Option<&str>) {
+    let (rest, hint) = match title.rsplit_once(' ') {
+        Some((rest, hint)) => (rest.trim_end(), hint),
+        None => ("", title),
+    };
+    let Some((width, height)) = hint.strip_prefix('=').and_then(|h| h.split_once('x')) else {
+        return (title, None, None);
+    };
+    if (!width.is_empty() && !is_size(width)) || (!height.is_empty() && !is_size(height)) {
+        return (title, None, None);
+    }
+    let width = Some(width).filter(|w| !w.is_empty());
+    let height = Some(height).filter(|h| !h.is_empty());
+    (rest, width, height)
+}
+
+/// Parse a `{width=400 height=300 align=center}` list at the start of `text`,
+/// returning it and its length.
+fn attribute_list(text: &str) -> Option<(Attributes<'_>, usize)> {
+    let end = text.strip_prefix('{')?.find('}')? + 2;
+    let mut attributes = Attributes::default();
+    for word in text[1..end - 1].split_whitespace() {
+        let (key, value) = word.split_once('=')?;
+        // Smart punctuation may have curled the quotes.
+        let value = value.trim_matches(['"', '\'', '“', '”', '‘', '’']);
+        match key {
+            "width" if is_size(value) => attributes.width = Some(value),
+            "height" if is_size(value) => attributes.height = Some(value),
+            "align" if matches!(value, "left" | "right" | "center") => {
+                attributes.align = Some(value)
+            }
+            _ => return None,
+        }
+    }
+    Some((attributes, end))
+}
+
+fn img_tag(url: &str, alt: &str, attributes: &Attributes) -> String {
+    let mut html = String::from("<img src=\"");
+    _ = escape_href(&mut html, url);
+    html.push_str("\" alt=\"");
+    _ = escape_html(&mut html, alt);
+    html.push('"');
+    if !attributes.title.is_empty() {
+        html.push_str(" title=\"");
+        _ = escape_html(&mut html, attributes.title);
+        html.push('"');
+    }
+    for (name, value) in [("width", attributes.width), ("height", attributes.height)] {
+        if let Some(value) = value {
+            html.push_str(&format!(" {}=\"{}\"", name, value));
+        }
+    }
+    if let Some(align) = attributes.align {
+        html.push_str(&format!(" class=\"align-{}\"", align));
+    }
+    html.push_str(" loading=\"lazy\">");
+    html
+}
+
+/// Text events directly following an image, joined together.
+fn following_text<'a>(events: &mut Peekable<impl Iterator<Item = Event<'a>>>) -> String {
+    let mut text = String::new();
+    while let Some(Event::Text(next)) = events.peek() {
+        text.push_str(next);
+        events.next();
+    }
+    text
+}
+
+/// Render images as `img` tags with their size hints and `loading="lazy"`.
+pub fn attributes<'a>(events: impl Iterator<Item = Event<'a>>) -> Vec<Event<'a>> {
+    let mut events = events.peekable();
+    let mut out = Vec::new();
+    while let Some(event) = events.next() {
+        let Event::Start(Tag::Image {
+            dest_url, title, ..
+        }) = event
+        else {
+            out.push(event);
+            continue;
+        };
+
+        // Like pulldown-cmark, use the plain text of the description as alt text.
+        let mut alt = String::new();
+        let mut depth = 0;
+        for event in events.by_ref() {
+            match event {
+                Event::Text(text) | Event::Code(text) => alt.push_str(&text),
+                Event::Start(Tag::Image { .. }) => depth += 1,
+                Event::End(TagEnd::Image) if depth == 0 => break,
+                Event::End(TagEnd::Image) => depth -= 1,
+                _ => {}
+            }
+        }
+
+        let (title, width, height) = title_size(&title);
+        let mut attributes = Attributes {
+            title,
+            width,
+            height,
+            align: None,
+        };
+        let text = following_text(&mut events);
+        let rest = match attribute_list(&text) {
+            Some((list, len)) => {
+                attributes.width = list.width.or(attributes.width);
+                attributes.height = list.height.or(attributes.height);
+                attributes.align = list.align;
+                &text[len..]
+            }
+            None => &text[..],
+        };
+        out.push(Event::InlineHtml(
+            img_tag(&dest_url, &alt, &attributes).into(),
+        ));
+        if !rest.is_empty() {
+            out.push(Event::Text(CowStr::from(rest.to_string())));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::markdown::{to_html, Options};
+
+    #[test]
+    fn sizes_from_titles_and_attribute_lists() {
+        let html = |md| to_html(md, &Options::default());
+        assert_eq!(
+            html("![a *b*](x.png \"=400x300\")"),
+            "<p><img src=\"x.png\" alt=\"a b\" width=\"400\" height=\"300\" loading=\"lazy\"></p>\n"
+        );
+        assert_eq!(
+            html("![a](x.png \"Caption =50%x\") after"),
+            "<p><img src=\"x.png\" alt=\"a\" title=\"Caption\" width=\"50%\" loading=\"lazy\"> after</p>\n"
+        );
+        assert_eq!(
+            html("![a](x.png){width=\"400\" align=right} text"),
+            "<p><img src=\"x.png\" alt=\"a\" width=\"400\" class=\"align-right\" loading=\"lazy\"> text</p>\n"
+        );
+        assert_eq!(
+            html("![a](x.png \"=big\"){onload=x}"),
+            "<p><img src=\"x.png\" alt=\"a\" title=\"=big\" loading=\"lazy\">{onload=x}</p>\n"
+        );
+    }
+}
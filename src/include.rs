@@ -0,0 +1,150 @@
+//! Splicing of other files into markdown with `{{#include file.md}}` or
+//! `<!-- include: file.md -->`.
+//!
+//! Includes are expanded textually before parsing, like mdBook does, so they
+//! also work inside code blocks. Write `\{{#include ...}}` to keep the
+//! directive as text.
+
+use log::warn;
+use pulldown_cmark_escape::escape_html;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const DIRECTIVES: [(&str, &str); 2] = [("{{#include ", "}}"), ("<!-- include:", "-->")];
+
+/// Expand includes in `source`, the content of `file`.
+///
+/// Paths are relative to the including file and must stay under `root`.
+pub fn expand(source: &str, file: &Path, root: &Path) -> String {
+    let mut stack = vec![file.canonicalize().unwrap_or_else(|_| file.to_path_buf())];
+    expand_nested(source, root, &mut stack)
+}
+
+fn expand_nested(source: &str, root: &Path, stack: &mut Vec<PathBuf>) -> String {
+    let mut out = String::with_capacity(source.len());
+    let mut rest = source;
+    while let Some((start, open, close)) = next_directive(rest) {
+        if rest[..start].ends_with('\\') {
+            out.push_str(&rest[..start - 1]);
+            out.push_str(open);
+            rest = &rest[start + open.len()..];
+            continue;
+        }
+        let Some(len) = rest[start + open.len()..].find(close) else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        let name = rest[start + open.len()..start + open.len() + len].trim();
+        match include(name, root, stack) {
+            Ok(content) => out.push_str(content.trim_end_matches('\n')),
+            Err(e) => {
+                warn!("cannot include {}: {}", name, e);
+                out.push_str("<span class=\"include-error\">Cannot include ");
+                _ = escape_html(&mut out, name);
+                out.push_str(": ");
+                _ = escape_html(&mut out, &e);
+                out.push_str("</span>");
+            }
+        }
+        rest = &rest[start + open.len() + len + close.len()..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Find the earliest directive in `text`.
+fn next_directive(text: &str) -> Option<(usize, &'static str, &'static str)> {
+    DIRECTIVES
+        .iter()
+        .filter_map(|&(open, close)| text.find(open).map(|start| (start, open, close)))
+        .min_by_key(|&(start, _, _)| start)
+}
+
+/// Path of the file included as `name` by the last file on `stack`.
+fn resolve(name: &str, root: &Path, stack: &[PathBuf]) -> Result<PathBuf, String> {
+    let including = stack.last().expect("stack starts with the page itself");
+    let dir = including.parent().unwrap_or(root);
+    let path = dir.join(name).canonicalize().map_err(|e| e.to_string())?;
+    let root = root.canonicalize().map_err(|e| e.to_string())?;
+    if !path.starts_with(&root) {
+        return Err("file is outside of the served directory".to_string());
+    }
+    if stack.contains(&path) {
+        return Err("file includes itself".to_string());
+    }
+    Ok(path)
+}
+
+fn include(name: &str, root: &Path, stack: &mut Vec<PathBuf>) -> Result<String, String> {
+    let path = resolve(name, root, stack)?;
+    let root = root.canonicalize().map_err(|e| e.to_string())?;
+    let data = fs::read(&path).map_err(|e| e.to_string())?;
+    let source = String::from_utf8_lossy(&data);
+    stack.push(path);
+    let content = expand_nested(&source, &root, stack);
+    stack.pop();
+    Ok(content)
+}
+
+/// Files included by `file`, directly or through the files it includes.
+pub fn included(file: &Path, root: &Path) -> Vec<PathBuf> {
+    let mut stack = vec![file.canonicalize().unwrap_or_else(|_| file.to_path_buf())];
+    let mut files = Vec::new();
+    collect_included(root, &mut stack, &mut files);
+    files
+}
+
+fn collect_included(root: &Path, stack: &mut Vec<PathBuf>, files: &mut Vec<PathBuf>) {
+    let Some(Ok(source)) = stack.last().map(fs::read_to_string) else {
+        return;
+    };
+    let mut rest = source.as_str();
+    while let Some((start, open, close)) = next_directive(rest) {
+        let directive = &rest[start + open.len()..];
+        let Some(len) = directive.find(close) else {
+            break;
+        };
+        let escaped = rest[..start].ends_with('\\');
+        if let (false, Ok(path)) = (escaped, resolve(directive[..len].trim(), root, stack)) {
+            if !files.contains(&path) {
+                files.push(path.clone());
+                stack.push(path);
+                collect_included(root, stack, files);
+                stack.pop();
+            }
+        }
+        rest = &directive[len + close.len()..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn includes_are_nested_and_cycles_are_reported() {
+        let root = env::temp_dir().join("mdopen-test-include");
+        _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("parts")).unwrap();
+        fs::write(root.join("parts/a.md"), "A {{#include b.md}}\n").unwrap();
+        fs::write(root.join("parts/b.md"), "B <!-- include: a.md -->").unwrap();
+
+        let page = root.join("index.md");
+        let html = expand("{{#include parts/a.md}} \\{{#include x}}", &page, &root);
+        assert!(html.starts_with("A B <span class=\"include-error\">Cannot include a.md: "));
+        assert!(html.ends_with(" {{#include x}}"));
+
+        let html = expand("{{#include ../etc/passwd}}", &page, &root);
+        assert!(html.starts_with("<span class=\"include-error\">"));
+
+        fs::write(&page, "{{#include parts/a.md}}").unwrap();
+        let parts = root.join("parts").canonicalize().unwrap();
+        assert_eq!(
+            included(&page, &root),
+            [parts.join("a.md"), parts.join("b.md")]
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
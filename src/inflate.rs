@@ -0,0 +1,378 @@
+//! Decoder for DEFLATE (RFC 1951) and gzip (RFC 1952) compressed data.
+
+use std::io;
+
+const MAX_BITS: usize = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Order in which code length code lengths are stored.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buf: u32,
+    count: u32,
+}
+
+impl Bits<'_> {
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        while self.count < n {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or_else(|| invalid("unexpected end of compressed data"))?;
+            self.pos += 1;
+            self.buf |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.buf & ((1u64 << n) - 1) as u32;
+        self.buf >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        self.buf = 0;
+        self.count = 0;
+    }
+}
+
+/// Canonical Huffman code: number of codes of each length and symbols in code order.
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for len in 1..=MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; offsets[MAX_BITS + 1] as usize];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> io::Result<u16> {
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for len in 1..=MAX_BITS {
+            code |= bits.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("invalid Huffman code"))
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes(bits: &mut Bits) -> io::Result<(Huffman, Huffman)> {
+    let nlen = bits.bits(5)? as usize + 257;
+    let ndist = bits.bits(5)? as usize + 1;
+    let ncode = bits.bits(4)? as usize + 4;
+    if nlen > 286 || ndist > 30 {
+        return Err(invalid("too many length or distance codes"));
+    }
+
+    let mut lengths = [0u8; 19];
+    for &index in &CODE_LENGTH_ORDER[..ncode] {
+        lengths[index] = bits.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&lengths);
+
+    let mut lengths = Vec::with_capacity(nlen + ndist);
+    while lengths.len() < nlen + ndist {
+        let symbol = code_lengths.decode(bits)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or_else(|| invalid("repeat with no previous length"))?;
+                (previous, 3 + bits.bits(2)?)
+            }
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        if lengths.len() + repeat as usize > nlen + ndist {
+            return Err(invalid("too many code lengths"));
+        }
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths[256] == 0 {
+        return Err(invalid("missing end-of-block code"));
+    }
+
+    Ok((
+        Huffman::new(&lengths[..nlen]),
+        Huffman::new(&lengths[nlen..]),
+    ))
+}
+
+fn inflate_block(
+    bits: &mut Bits,
+    out: &mut Vec<u8>,
+    limit: usize,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> io::Result<()> {
+    loop {
+        let symbol = literals.decode(bits)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let index = symbol - 257;
+                if index >= LENGTH_BASE.len() {
+                    return Err(invalid("invalid length code"));
+                }
+                let len =
+                    LENGTH_BASE[index] as usize + bits.bits(LENGTH_EXTRA[index] as u32)? as usize;
+                let index = distances.decode(bits)? as usize;
+                if index >= DIST_BASE.len() {
+                    return Err(invalid("invalid distance code"));
+                }
+                let dist =
+                    DIST_BASE[index] as usize + bits.bits(DIST_EXTRA[index] as u32)? as usize;
+                if dist > out.len() {
+                    return Err(invalid("distance too far back"));
+                }
+                let start = out.len() - dist;
+                for i in 0..len {
+                    out.push(out[start + i]);
+                }
+            }
+        }
+        if out.len() > limit {
+            return Err(invalid("decompressed data is too large"));
+        }
+    }
+}
+
+/// Decompress raw DEFLATE data, refusing to make more than `limit` bytes so
+/// that small input cannot exhaust memory.
+pub fn inflate(data: &[u8], limit: usize) -> io::Result<Vec<u8>> {
+    let mut bits = Bits {
+        data,
+        pos: 0,
+        buf: 0,
+        count: 0,
+    };
+    let mut out = Vec::with_capacity((data.len() * 3).min(limit));
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => {
+                bits.align();
+                let header = data
+                    .get(bits.pos..bits.pos + 4)
+                    .ok_or_else(|| invalid("unexpected end of compressed data"))?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                let nlen = u16::from_le_bytes([header[2], header[3]]);
+                if len != !nlen {
+                    return Err(invalid("corrupt stored block length"));
+                }
+                let start = bits.pos + 4;
+                let block = data
+                    .get(start..start + len as usize)
+                    .ok_or_else(|| invalid("unexpected end of compressed data"))?;
+                out.extend_from_slice(block);
+                bits.pos = start + len as usize;
+                if out.len() > limit {
+                    return Err(invalid("decompressed data is too large"));
+                }
+            }
+            1 => {
+                let (literals, distances) = fixed_codes();
+                inflate_block(&mut bits, &mut out, limit, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut bits)?;
+                inflate_block(&mut bits, &mut out, limit, &literals, &distances)?;
+            }
+            _ => return Err(invalid("invalid block type")),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+/// Decompress a gzip member to at most `limit` bytes, ignoring the checksum.
+pub fn gunzip(data: &[u8], limit: usize) -> io::Result<Vec<u8>> {
+    const FEXTRA: u8 = 4;
+    const FNAME: u8 = 8;
+    const FCOMMENT: u8 = 16;
+    const FHCRC: u8 = 2;
+
+    if data.len() < 18 || data[..3] != [0x1f, 0x8b, 8] {
+        return Err(invalid("not a gzip file"));
+    }
+    let flags = data[3];
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        let len = data
+            .get(pos..pos + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+            .ok_or_else(|| invalid("truncated gzip header"))?;
+        pos += 2 + len;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let end = data
+                .get(pos..)
+                .and_then(|rest| rest.iter().position(|&b| b == 0))
+                .ok_or_else(|| invalid("truncated gzip header"))?;
+            pos += end + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+    let body = data
+        .get(pos..)
+        .ok_or_else(|| invalid("truncated gzip header"))?;
+    inflate(body, limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// Text of the `DYNAMIC` block.
+    fn dynamic_text() -> Vec<u8> {
+        (0..20)
+            .flat_map(|i| {
+                format!(
+                    "line {} of a dynamic block, with repeated words words words\n",
+                    i
+                )
+                .into_bytes()
+            })
+            .collect()
+    }
+
+    /// Lines compressed by zlib into a block with dynamic codes.
+    const DYNAMIC: &str = "a5d23b0ec2301444d13eab780b48910984cf728c6d8485b19163c962f7888e7aa6b9dded4e4e25da62f56ecec2a7b857f276cbd53f671ba93facc577743d061bb585fdbf53feade0d7955f0ffc7ae4d78d5f4ffc7ae6d70bbf5e05120a27c11304501044412005c11404541054416005ced517";
+
+    #[test]
+    fn blocks_of_every_type_are_inflated() {
+        // Made with zlib at level 0, with Z_FIXED and at level 9.
+        let stored = hex("010c00f3ff73746f72656420626c6f636b");
+        assert_eq!(inflate(&stored, 100).unwrap(), b"stored block");
+        let fixed = hex("4b4c4a4e842185b4cc8ad41400");
+        assert_eq!(inflate(&fixed, 100).unwrap(), b"abcabcabcabc fixed");
+        let dynamic = hex(DYNAMIC);
+        assert_eq!(dynamic[0] >> 1 & 3, 2);
+        assert_eq!(inflate(&dynamic, 10000).unwrap(), dynamic_text());
+
+        assert!(inflate(&dynamic, 1000).is_err());
+        assert!(inflate(&stored, 5).is_err());
+        assert!(inflate(&dynamic[..50], 10000).is_err());
+        // Block type 3 is reserved.
+        assert!(inflate(&[0b111], 100).is_err());
+        let mut corrupt = stored.clone();
+        corrupt[3] ^= 1;
+        assert!(inflate(&corrupt, 100).is_err());
+    }
+
+    #[test]
+    fn gzip_headers_are_skipped() {
+        const FEXTRA: u8 = 4;
+        const FNAME: u8 = 8;
+        const FCOMMENT: u8 = 16;
+        const FHCRC: u8 = 2;
+
+        let body = hex("4b4c4a4e842185b4cc8ad41400");
+        for flags in [
+            0,
+            FEXTRA,
+            FNAME,
+            FCOMMENT,
+            FHCRC,
+            FEXTRA | FNAME | FCOMMENT | FHCRC,
+        ] {
+            let mut gz = vec![0x1f, 0x8b, 8, flags, 0, 0, 0, 0, 0, 3];
+            if flags & FEXTRA != 0 {
+                gz.extend_from_slice(&[3, 0, b'a', b'b', 0]);
+            }
+            if flags & FNAME != 0 {
+                gz.extend_from_slice(b"name.txt\0");
+            }
+            if flags & FCOMMENT != 0 {
+                gz.extend_from_slice(b"a comment\0");
+            }
+            if flags & FHCRC != 0 {
+                gz.extend_from_slice(&[0x12, 0x34]);
+            }
+            gz.extend_from_slice(&body);
+            // CRC-32 and size, which are not checked.
+            gz.extend_from_slice(&[0; 8]);
+            assert_eq!(
+                gunzip(&gz, 100).unwrap(),
+                b"abcabcabcabc fixed",
+                "flags {}",
+                flags
+            );
+        }
+        assert!(gunzip(b"PK\x03\x04 not gzip at all", 100).is_err());
+        let mut truncated = vec![0x1f, 0x8b, 8, FNAME, 0, 0, 0, 0, 0, 3];
+        truncated.extend_from_slice(b"no end of name");
+        assert!(gunzip(&truncated, 100).is_err());
+    }
+}
@@ -0,0 +1,99 @@
+//! Checking of relative links against the served files.
+
+use crate::archive;
+use percent_encoding::percent_decode_str;
+use std::path::{Component, Path, PathBuf};
+
+/// Location of a rendered file, to resolve its relative links.
+#[derive(Debug, Clone)]
+pub struct Base {
+    /// Directory served at `/`.
+    pub root: PathBuf,
+    /// Directory containing the file.
+    pub dir: PathBuf,
+}
+
+impl Base {
+    pub fn new(root: &Path, file: &Path) -> Self {
+        Base {
+            root: root.to_path_buf(),
+            dir: file.parent().unwrap_or(root).to_path_buf(),
+        }
+    }
+
+    /// Whether `url` is a local link to a file that does not exist.
+    pub fn is_broken(&self, url: &str) -> bool {
+        if url.is_empty() || url.starts_with('#') || url.starts_with("//") {
+            return false;
+        }
+        // Anything with a scheme, like `https:` or `mailto:`.
+        if let Some(colon) = url.find(':') {
+            if !url[..colon].contains(['/', '?', '#']) {
+                return false;
+            }
+        }
+        let path = url.split(['?', '#']).next().unwrap_or_default();
+        let path = percent_decode_str(path).decode_utf8_lossy();
+
+        let (mut resolved, mut depth) = if path.starts_with('/') {
+            (self.root.clone(), 0)
+        } else {
+            let dir = self.dir.strip_prefix(&self.root).unwrap_or(Path::new(""));
+            (self.dir.clone(), dir.components().count())
+        };
+        for component in Path::new(path.as_ref()).components() {
+            match component {
+                Component::Normal(part) => {
+                    resolved.push(part);
+                    depth += 1;
+                }
+                // Links above the root cannot be served.
+                Component::ParentDir if depth == 0 => return true,
+                Component::ParentDir => {
+                    resolved.pop();
+                    depth -= 1;
+                }
+                _ => {}
+            }
+        }
+        if resolved.exists() {
+            return false;
+        }
+        // Paths inside archives are served without existing on disk.
+        !resolved
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(&self.root))
+            .any(|dir| dir.is_file() && archive::is_archive(dir))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    #[test]
+    fn missing_files_are_broken() {
+        let root = env::temp_dir().join("mdopen-test-links");
+        _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("README.md"), "").unwrap();
+        fs::write(root.join("docs/a b.md"), "").unwrap();
+
+        let base = Base::new(&root, &root.join("docs/index.md"));
+        for url in [
+            "a%20b.md#usage",
+            "../README.md",
+            "/docs/",
+            "https://example.com",
+        ] {
+            assert!(!base.is_broken(url), "{}", url);
+        }
+        for url in ["b.md", "../../README.md", "/README.md/x/../../missing.md"] {
+            assert!(base.is_broken(url), "{}", url);
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,24 +1,219 @@
+use cli::{SyntaxTheme, TocPosition};
 use log::{debug, error, info};
 use nanotemplate::template as render;
-use percent_encoding::percent_decode;
+use percent_encoding::{percent_decode, utf8_percent_encode, NON_ALPHANUMERIC};
+use pulldown_cmark_escape::escape_html;
+use std::collections::BTreeMap;
 use std::env;
 use std::ffi::OsStr;
 use std::fmt::Write;
-use std::fs;
-use std::io::{self, Cursor};
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
 use std::thread;
-use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tiny_http::{Header, Method, Request, Response, ResponseBox, Server, StatusCode};
 
+mod abbr;
+mod ansi;
+mod archive;
+mod cache;
+mod citation;
 mod cli;
+mod client;
+mod container;
+mod control;
+mod detect;
+mod diagram;
+mod epub;
+mod export;
+mod external;
+mod feed;
+mod fence;
+mod footnote;
+mod frontmatter;
+mod image;
+mod include;
+mod inflate;
+mod json;
+mod links;
+mod man;
 mod markdown;
+mod mathml;
+#[cfg(feature = "mdns")]
+mod mdns;
+mod metrics;
+mod mime;
+mod minify;
+mod notebook;
+mod outline;
+mod sanitize;
+mod site;
+mod sitemap;
+mod slides;
+mod sourcemap;
+mod spoiler;
+mod sse;
+mod summary;
+mod text;
+mod theme;
+mod viewer;
+mod watch;
+mod wikilink;
 
 pub static INDEX: &str = include_str!("template/index.html");
+/// Live reload client injected into served HTML files.
+static RELOAD_SCRIPT: &str = include_str!("template/reload.js");
 pub static GITHUB_STYLE: &[u8] = include_bytes!("vendor/github.css");
 
 pub static STATIC_PREFIX: &str = "/@/";
 
+/// URL at which markdown read from stdin is served.
+pub static STDIN_URL: &str = "/__stdin__";
+
+/// URL of the health check endpoint.
+pub static HEALTH_URL: &str = "/__mdopen_health";
+
+/// Prefix of the HTTP API endpoints.
+pub static API_PREFIX: &str = "/__mdopen_api/";
+
+/// URL of the Prometheus metrics endpoint.
+pub static METRICS_URL: &str = "/__mdopen_metrics";
+
+/// Prefix of the live reload event streams, followed by the path of a page.
+pub static RELOAD_PREFIX: &str = "/__mdopen_reload/";
+
+/// Cargo features compiled into the binary.
+static FEATURES: &[&str] = &[
+    #[cfg(feature = "asciidoc")]
+    "asciidoc",
+    #[cfg(feature = "mdns")]
+    "mdns",
+];
+
+/// Time the server was started.
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+static DOWNLOAD_ICON: &str = r#"<svg class="octicon octicon-download" viewBox="0 0 16 16" width="16" height="16" aria-hidden="true"><path d="M2.75 14A1.75 1.75 0 0 1 1 12.25v-2.5a.75.75 0 0 1 1.5 0v2.5c0 .138.112.25.25.25h10.5a.25.25 0 0 0 .25-.25v-2.5a.75.75 0 0 1 1.5 0v2.5A1.75 1.75 0 0 1 13.25 14Z"></path><path d="M7.25 7.689V2a.75.75 0 0 1 1.5 0v5.689l1.97-1.969a.749.749 0 1 1 1.06 1.06l-3.25 3.25a.749.749 0 0 1-1.06 0L4.22 6.78a.749.749 0 1 1 1.06-1.06l1.97 1.969Z"></path></svg>"#;
+
+/// Files rendered instead of the listing when a directory is requested.
+static DIR_INDEX_FILES: &[&str] = &["README.md", "readme.md", "index.md"];
+
+/// Markdown read from stdin when `-` is passed as a file.
+static STDIN_MARKDOWN: OnceLock<String> = OnceLock::new();
+
+/// Settings shared by request handlers.
+pub struct AppConfig {
+    pub port: u16,
+    pub browser: Option<String>,
+    #[cfg(feature = "asciidoc")]
+    pub asciidoc_command: String,
+    pub rst_command: String,
+    pub toc: Option<TocPosition>,
+    pub syntax_theme: SyntaxTheme,
+    /// Stylesheet of a `--syntax-theme-file` theme.
+    pub syntax_theme_css: Option<String>,
+    /// Directory of extra highlight.js languages, served as `syntax/NAME.js`.
+    pub syntax_dir: Option<PathBuf>,
+    /// Show word count and reading time above markdown pages.
+    pub reading_time: bool,
+    /// Present markdown pages as slides.
+    pub slides: bool,
+    /// Reload pages when their files change.
+    pub reload: bool,
+    /// Take pages to the markdown file saved last.
+    pub follow: bool,
+    /// Serve an Atom feed of the markdown files at `/feed.xml`.
+    pub feed: bool,
+    /// Serve `/sitemap.xml` and `/llms.txt`.
+    pub sitemap: bool,
+    /// Public URL of exported sites.
+    pub site_url: Option<String>,
+    pub markdown: markdown::Options,
+}
+
+/// Unix timestamp (in seconds) of the last handled request.
+static LAST_ACTIVITY: AtomicU64 = AtomicU64::new(0);
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn touch_activity() {
+    LAST_ACTIVITY.store(unix_now(), Ordering::Relaxed);
+}
+
+/// Add the live reload client to an HTML document, before `</body>` if it has
+/// one.
+fn inject_reload(html: &str) -> String {
+    let script = format!(
+        "<script data-prefix=\"{}\">\n{}</script>\n",
+        RELOAD_PREFIX, RELOAD_SCRIPT
+    );
+    let end = html
+        .to_ascii_lowercase()
+        .rfind("</body>")
+        .unwrap_or(html.len());
+    format!("{}{}{}", &html[..end], script, &html[end..])
+}
+
+/// Spawn a thread that exits the process when no requests arrive for `timeout`.
+///
+/// Pages open with live reload keep the server alive, and the time counts
+/// from when the last of them is closed.
+fn exit_when_idle(timeout: Duration) {
+    touch_activity();
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(1));
+        if sse::clients() > 0 {
+            touch_activity();
+            continue;
+        }
+        let idle = unix_now().saturating_sub(LAST_ACTIVITY.load(Ordering::Relaxed));
+        if idle >= timeout.as_secs() {
+            info!(
+                "no requests for {}, exiting",
+                humantime::format_duration(timeout)
+            );
+            sse::shutdown();
+            process::exit(0);
+        }
+    });
+}
+
+/// How long the server waits for a page to connect again, as when it is
+/// reloaded, before `--exit-on-close` exits.
+const CLOSE_GRACE: Duration = Duration::from_secs(3);
+
+/// Spawn a thread that exits the process once pages have connected for live
+/// reload and all of them are closed.
+fn exit_when_closed() {
+    thread::spawn(|| {
+        let mut closed_since = None;
+        let mut connected = false;
+        loop {
+            thread::sleep(Duration::from_millis(500));
+            if sse::clients() > 0 {
+                connected = true;
+                closed_since = None;
+                continue;
+            }
+            let closed = *closed_since.get_or_insert_with(Instant::now);
+            if connected && closed.elapsed() >= CLOSE_GRACE {
+                info!("all pages closed, exiting");
+                process::exit(0);
+            }
+        }
+    });
+}
+
 fn html_response(
     text: impl Into<Vec<u8>>,
     status: impl Into<StatusCode>,
@@ -30,22 +225,69 @@ fn html_response(
         .with_status_code(status)
 }
 
+pub fn json_response(
+    value: &json::Value,
+    status: impl Into<StatusCode>,
+) -> Response<Cursor<Vec<u8>>> {
+    Response::from_data(value.to_string())
+        .with_header(Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap())
+        .with_status_code(status)
+}
+
+fn redirect_response(location: &str) -> Response<Cursor<Vec<u8>>> {
+    Response::from_data(Vec::new())
+        .with_header(Header::from_bytes(&b"Location"[..], location.as_bytes()).unwrap())
+        .with_status_code(301)
+}
+
 fn not_found_response() -> Response<Cursor<Vec<u8>>> {
     let body = "<h1>404 Not Found</h1>";
-    let html = render(INDEX, [("title", "mdopen"), ("body", body)]).unwrap();
+    let html = html_page("mdopen", body, &SyntaxTheme::default());
     html_response(html, 404)
 }
 
+fn forbidden_response() -> Response<Cursor<Vec<u8>>> {
+    let body = "<h1>403 Forbidden</h1>";
+    let html = html_page("mdopen", body, &SyntaxTheme::default());
+    html_response(html, 403)
+}
+
 fn internal_error_response() -> Response<Cursor<Vec<u8>>> {
     let body = "<h1>500 Internal Server Error</h1>";
-    let html = render(INDEX, [("title", "mdopen"), ("body", body)]).unwrap();
+    let html = html_page("mdopen", body, &SyntaxTheme::default());
     html_response(html, 500)
 }
 
 /// Returns response for static content request
-fn try_asset_file(request: &Request) -> Option<Response<Cursor<Vec<u8>>>> {
+fn try_asset_file(request: &Request, config: &AppConfig) -> Option<Response<Cursor<Vec<u8>>>> {
     let asset_url = request.url().strip_prefix(STATIC_PREFIX)?;
 
+    // The theme file may be edited between runs, so it is not cached.
+    if let ("syntax-theme.css", Some(css)) = (asset_url, &config.syntax_theme_css) {
+        let resp = Response::from_data(css.as_bytes())
+            .with_header(Header::from_bytes(&b"Content-Type"[..], &b"text/css"[..]).unwrap())
+            .with_header(Header::from_bytes(&b"Cache-Control"[..], &b"no-cache"[..]).unwrap());
+        return Some(resp);
+    }
+
+    if let (Some(name), Some(dir)) = (asset_url.strip_prefix("syntax/"), &config.syntax_dir) {
+        let language = name.strip_suffix(".js").unwrap_or_default();
+        let valid = !language.is_empty()
+            && language
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+        let data = valid.then(|| fs::read(dir.join(name)).ok()).flatten();
+        return Some(match data {
+            // Modules are only run when served as JavaScript.
+            Some(data) => Response::from_data(data)
+                .with_header(
+                    Header::from_bytes(&b"Content-Type"[..], &b"text/javascript"[..]).unwrap(),
+                )
+                .with_header(Header::from_bytes(&b"Cache-Control"[..], &b"no-cache"[..]).unwrap()),
+            None => not_found_response(),
+        });
+    }
+
     let data = match asset_url {
         "style.css" => GITHUB_STYLE,
         _ => {
@@ -60,68 +302,953 @@ fn try_asset_file(request: &Request) -> Option<Response<Cursor<Vec<u8>>>> {
     Some(resp)
 }
 
-/// Get content type from extension.
-fn mime_type(ext: &str) -> Option<&'static str> {
-    match ext {
-        "js" => Some("application/javascript"),
-        "css" => Some("text/css"),
-        "gif" => Some("image/gif"),
-        "png" => Some("image/png"),
-        "jpg" | "jpeg" => Some("image/jpeg"),
-        "pdf" => Some("application/pdf"),
-        "html" => Some("text/html"),
-        "txt" => Some("text/plain"),
-        _ => None,
+/// Stylesheets of the highlight.js themes, switching with the color scheme.
+fn syntax_theme_links(theme: &SyntaxTheme) -> String {
+    const STYLES: &str = "https://cdn.jsdelivr.net/gh/highlightjs/cdn-release@11.9.0/build/styles";
+    match theme {
+        SyntaxTheme::File(_) => format!(
+            "<link href=\"{}syntax-theme.css\" rel=\"stylesheet\">",
+            STATIC_PREFIX
+        ),
+        SyntaxTheme::Named { light, dark } if light == dark => {
+            format!("<link href=\"{}/{}.min.css\" rel=\"stylesheet\">", STYLES, light)
+        }
+        SyntaxTheme::Named { light, dark } => format!(
+            "<link href=\"{}/{}.min.css\" media=\"(prefers-color-scheme: dark)\" rel=\"stylesheet\">\n\t<link href=\"{}/{}.min.css\" media=\"(prefers-color-scheme: light), (prefers-color-scheme: no-preference)\" rel=\"stylesheet\">",
+            STYLES, dark, STYLES, light
+        ),
+    }
+}
+
+/// Render the page template around `body`.
+fn html_page(title: &str, body: &str, theme: &SyntaxTheme) -> String {
+    html_page_with_head(title, "", body, theme)
+}
+
+/// Render the page template around `body`, with extra tags in its head.
+fn html_page_with_head(title: &str, head: &str, body: &str, theme: &SyntaxTheme) -> String {
+    let mut escaped_title = String::new();
+    _ = escape_html(&mut escaped_title, title);
+    render(
+        INDEX,
+        [
+            ("title", escaped_title.as_str()),
+            ("head", head),
+            ("syntax_theme", &syntax_theme_links(theme)),
+            ("body", body),
+        ],
+    )
+    .unwrap()
+}
+
+/// Description and OpenGraph tags for a page with the given frontmatter.
+fn meta_tags(metadata: &[(String, String)]) -> String {
+    let field = |key: &str| {
+        metadata
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+            .filter(|v| !v.is_empty())
+    };
+    let mut tags = Vec::new();
+    if let Some(title) = field("title") {
+        tags.push(("property", "og:title", title));
+        tags.push(("name", "twitter:title", title));
+    }
+    if let Some(description) = field("description").or(field("summary")) {
+        tags.push(("name", "description", description));
+        tags.push(("property", "og:description", description));
+        tags.push(("name", "twitter:description", description));
+    }
+    if let Some(image) = field("image").or(field("cover")) {
+        tags.push(("property", "og:image", image));
+        tags.push(("name", "twitter:image", image));
+    }
+    if let Some(author) = field("author").or(field("authors")) {
+        tags.push(("name", "author", author));
+    }
+    if !tags.is_empty() {
+        tags.push(("property", "og:type", "article"));
+        let card = match field("image").or(field("cover")) {
+            Some(_) => "summary_large_image",
+            None => "summary",
+        };
+        tags.push(("name", "twitter:card", card));
+    }
+
+    let mut html = String::new();
+    for (attribute, name, content) in tags {
+        _ = write!(html, "<meta {}=\"{}\" content=\"", attribute, name);
+        _ = escape_html(&mut html, content);
+        html.push_str("\">\n");
+    }
+    html
+}
+
+/// Render a markdown page, titled by its frontmatter if it has one.
+///
+/// `footer` is HTML appended to the rendered document. Relative links are
+/// checked against `links` when the page comes from a file.
+fn markdown_page(
+    md: &str,
+    title: &str,
+    footer: &str,
+    links: Option<links::Base>,
+    config: &AppConfig,
+) -> String {
+    // Only files on disk can have their tasks toggled, or be scrolled to by
+    // editors.
+    let options = markdown::Options {
+        editable_tasks: config.markdown.editable_tasks && links.is_some(),
+        source_lines: config.reload && links.is_some(),
+        links,
+        ..config.markdown.clone()
+    };
+    let doc = metrics::time_render(|| markdown::render(md, &options));
+    let title = doc.title().unwrap_or(title);
+    // Frontmatter can turn the reading time on or off for one page.
+    let reading_time = match doc.metadata.iter().find(|(key, _)| key == "reading_time") {
+        Some((_, value)) => value == "true",
+        None => config.reading_time,
+    };
+    let content = if reading_time {
+        format!(
+            "<p class=\"reading-time\">{} {} · {} min read</p>{}{}",
+            doc.words,
+            if doc.words == 1 { "word" } else { "words" },
+            doc.reading_minutes(),
+            doc.html,
+            footer
+        )
+    } else {
+        format!("{}{}", doc.html, footer)
+    };
+    let toc = markdown::toc_html(&doc.headings);
+    let body = match config.toc {
+        _ if config.slides => slides::to_html(md, &options),
+        Some(_) if toc.is_empty() => content,
+        Some(TocPosition::Top) => format!(
+            "<nav class=\"toc\"><details open><summary>Contents</summary>{}</details></nav>{}",
+            toc, content
+        ),
+        Some(TocPosition::Sidebar) => format!(
+            "<nav class=\"toc toc-sidebar\"><details open><summary>Contents</summary>{}</details></nav>{}",
+            toc, content
+        ),
+        None => content,
+    };
+    let mut escaped_title = String::new();
+    _ = escape_html(&mut escaped_title, title);
+    let head = meta_tags(&doc.metadata) + &live_head(config);
+    render(
+        INDEX,
+        [
+            ("title", escaped_title.as_str()),
+            ("head", &head),
+            ("syntax_theme", &syntax_theme_links(&config.syntax_theme)),
+            ("body", &body),
+        ],
+    )
+    .unwrap()
+}
+
+/// Tags which turn on live reload and following in the page, if enabled.
+fn live_head(config: &AppConfig) -> String {
+    let mut head = String::new();
+    if config.reload {
+        _ = writeln!(
+            head,
+            "<meta name=\"mdopen-reload\" content=\"{}\">",
+            RELOAD_PREFIX
+        );
+    }
+    if config.follow {
+        head.push_str("<meta name=\"mdopen-follow\">\n");
+    }
+    head
+}
+
+/// Returns response for markdown read from stdin
+fn try_stdin(request: &Request, config: &AppConfig) -> Option<Response<Cursor<Vec<u8>>>> {
+    if request.url() != STDIN_URL {
+        return None;
+    }
+    let md = STDIN_MARKDOWN.get()?;
+    let html = markdown_page(md, "stdin", "", None, config);
+    Some(html_response(html, 200))
+}
+
+/// Returns response for the health check endpoint
+fn try_health(request: &Request) -> Option<Response<Cursor<Vec<u8>>>> {
+    if request.url() != HEALTH_URL {
+        return None;
+    }
+    Some(json_response(&health(), 200))
+}
+
+/// State of the server reported by the health check endpoint.
+fn health() -> json::Value {
+    let uptime = STARTED.get().map(Instant::elapsed).unwrap_or_default();
+    let root = env::current_dir()
+        .map(|p| p.to_string_lossy().to_string())
+        .ok();
+    json::Value::object([
+        ("name", "mdopen".into()),
+        ("version", cli::VERSION.into()),
+        ("uptime", uptime.as_secs().into()),
+        ("features", FEATURES.to_vec().into()),
+        ("root", root.into()),
+        ("reload_clients", sse::clients().into()),
+    ])
+}
+
+/// Returns response for the metrics endpoint
+fn try_metrics(request: &Request) -> Option<Response<Cursor<Vec<u8>>>> {
+    if request.url() != METRICS_URL {
+        return None;
+    }
+    let resp = Response::from_data(metrics::render()).with_header(
+        Header::from_bytes(&b"Content-Type"[..], &b"text/plain; version=0.0.4"[..]).unwrap(),
+    );
+    Some(resp)
+}
+
+/// URL of the served directory, as the client reached it.
+fn request_base_url(request: &Request, config: &AppConfig) -> String {
+    let host = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Host"))
+        .map_or_else(
+            || format!("localhost:{}", config.port),
+            |h| h.value.to_string(),
+        );
+    format!("http://{}/", host)
+}
+
+/// Returns the Atom feed of the served directory, unless it has a feed file
+fn try_feed(request: &Request, config: &AppConfig) -> Option<Response<Cursor<Vec<u8>>>> {
+    if !config.feed || request.url() != format!("/{}", feed::FEED_FILE) {
+        return None;
+    }
+    let root = env::current_dir().ok()?;
+    if root.join(feed::FEED_FILE).exists() {
+        return None;
+    }
+    let base = request_base_url(request, config);
+    let xml = feed::atom(&root, &config.markdown, &base, |path| {
+        wikilink::url_path(&root, path)[1..].to_string()
+    });
+    let resp = Response::from_data(xml).with_header(
+        Header::from_bytes(
+            &b"Content-Type"[..],
+            &b"application/atom+xml; charset=utf-8"[..],
+        )
+        .unwrap(),
+    );
+    Some(resp)
+}
+
+/// Returns the sitemap or `llms.txt` of the served directory, unless it has
+/// such a file
+fn try_sitemap(request: &Request, config: &AppConfig) -> Option<Response<Cursor<Vec<u8>>>> {
+    if !config.sitemap {
+        return None;
+    }
+    let file = [sitemap::SITEMAP_FILE, sitemap::LLMS_FILE]
+        .into_iter()
+        .find(|file| request.url() == format!("/{}", file))?;
+    let root = env::current_dir().ok()?;
+    if root.join(file).exists() {
+        return None;
+    }
+    let base = request_base_url(request, config);
+    let url = |path: &Path| wikilink::url_path(&root, path)[1..].to_string();
+    let (body, mime) = match file {
+        sitemap::SITEMAP_FILE => (
+            sitemap::sitemap(&root, &config.markdown, &base, url),
+            "application/xml; charset=utf-8",
+        ),
+        _ => (
+            sitemap::llms_txt(&root, &config.markdown, &base, url),
+            "text/plain; charset=utf-8",
+        ),
+    };
+    let resp = Response::from_data(body)
+        .with_header(Header::from_bytes(&b"Content-Type"[..], mime).unwrap());
+    Some(resp)
+}
+
+/// Whether the body of `request` is JSON.
+///
+/// Endpoints with side effects require it, since other sites can only post
+/// JSON to the server after a CORS preflight, which it never allows.
+pub fn is_json(request: &Request) -> bool {
+    request
+        .headers()
+        .iter()
+        .any(|h| h.field.equiv("Content-Type") && h.value.as_str().starts_with("application/json"))
+}
+
+pub fn api_error(message: &str, status: u16) -> Response<Cursor<Vec<u8>>> {
+    json_response(&json::Value::object([("error", message.into())]), status)
+}
+
+/// Markdown, title and whether to render a full page, from the body of a
+/// render request.
+fn render_request(body: String, is_json: bool) -> Result<(String, String, bool), String> {
+    if !is_json {
+        return Ok((body, "mdopen".to_string(), false));
+    }
+    let value = json::parse(&body).map_err(|e| e.to_string())?;
+    let Some(md) = value.get("markdown").and_then(json::Value::as_str) else {
+        return Err("missing string field \"markdown\"".to_string());
+    };
+    let title = value
+        .get("title")
+        .and_then(json::Value::as_str)
+        .unwrap_or("mdopen");
+    let page = value
+        .get("page")
+        .and_then(json::Value::as_bool)
+        .unwrap_or(false);
+    Ok((md.to_string(), title.to_string(), page))
+}
+
+/// Render markdown from the request body.
+///
+/// The body is either raw markdown, or a JSON object with a `markdown` field
+/// and an optional `page` flag to wrap the result into the full HTML page.
+fn api_render(request: &mut Request, config: &AppConfig) -> Response<Cursor<Vec<u8>>> {
+    let mut body = String::new();
+    if let Err(e) = request.as_reader().read_to_string(&mut body) {
+        return api_error(&format!("cannot read body: {}", e), 400);
+    }
+
+    let (md, title, page) = match render_request(body, is_json(request)) {
+        Ok(request) => request,
+        Err(message) => return api_error(&message, 400),
+    };
+
+    let html = metrics::time_render(|| markdown::to_html(&md, &config.markdown));
+    let html = if page {
+        html_page(&title, &html, &config.syntax_theme)
+    } else {
+        html
+    };
+    html_response(html, 200)
+}
+
+/// Returns response for API requests
+fn try_api(request: &mut Request, config: &AppConfig) -> Option<Response<Cursor<Vec<u8>>>> {
+    let (path, _) = split_url(request.url());
+    let endpoint = path.strip_prefix(API_PREFIX)?;
+    let resp = match (request.method(), endpoint) {
+        (Method::Post, "render") => api_render(request, config),
+        (Method::Get, "tree") => api_tree(request),
+        (Method::Post, "control") => control::handle(request, config),
+        (Method::Post, "task") => api_task(request, config),
+        (Method::Post, "scroll") => api_scroll(request, config),
+        (_, "render" | "tree" | "control" | "task" | "scroll") => {
+            api_error("method not allowed", 405)
+        }
+        _ => api_error("unknown endpoint", 404),
+    };
+    Some(resp)
+}
+
+/// Header that makes the browser save the response as `filename`.
+fn content_disposition(filename: &str) -> Header {
+    // Plain `filename` for old clients, RFC 5987 `filename*` for non-ASCII names.
+    let fallback: String = filename
+        .chars()
+        .map(|c| {
+            if c.is_ascii_graphic() && c != '"' && c != '\\' || c == ' ' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let encoded = utf8_percent_encode(filename, NON_ALPHANUMERIC);
+    let value = format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback, encoded
+    );
+    Header::from_bytes(&b"Content-Disposition"[..], value.as_bytes()).unwrap()
+}
+
+/// Map a request URL to a path under `root`.
+///
+/// Returns `None` if the URL points outside of `root`, either through `..`
+/// components or through a symlink.
+fn resolve_path(root: &Path, url: &str) -> Option<PathBuf> {
+    let url = percent_decode(url.as_bytes()).decode_utf8_lossy();
+
+    let mut relative = PathBuf::new();
+    for component in Path::new(url.as_ref()).components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::ParentDir => {
+                if !relative.pop() {
+                    return None;
+                }
+            }
+            Component::CurDir | Component::RootDir => {}
+            Component::Prefix(_) => return None,
+        }
+    }
+
+    let path = root.join(relative);
+    if let Ok(canonical) = path.canonicalize() {
+        let root = root.canonicalize().ok()?;
+        if !canonical.starts_with(root) {
+            return None;
+        }
+    }
+    Some(path)
+}
+
+/// File shown by the page at `url_path`, which is an index file for
+/// directories.
+fn page_file(root: &Path, url_path: &str) -> Option<PathBuf> {
+    let path = resolve_path(root, url_path)?;
+    if !path.is_dir() {
+        return Some(path);
+    }
+    DIR_INDEX_FILES
+        .iter()
+        .map(|name| path.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Whether the page showing `page` changes with the file `changed`.
+///
+/// Files other than markdown, like images and bibliographies, can be used by
+/// any page.
+fn depends_on(page: &Path, changed: &Path, root: &Path) -> bool {
+    let is_markdown = matches!(
+        changed.extension().and_then(OsStr::to_str),
+        Some("md" | "markdown")
+    );
+    // Pages in an archive change with it.
+    if page.starts_with(changed) || !is_markdown {
+        return true;
+    }
+    let changed = changed
+        .canonicalize()
+        .unwrap_or_else(|_| changed.to_path_buf());
+    include::included(page, root).contains(&changed)
+}
+
+/// Split request URL into path and query string.
+fn split_url(url: &str) -> (&str, &str) {
+    url.split_once('?').unwrap_or((url, ""))
+}
+
+/// Get percent-decoded value of a query string parameter.
+fn query_param(query: &str, name: &str) -> Option<String> {
+    query.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        (key == name).then(|| {
+            let value = value.replace('+', " ");
+            percent_decode(value.as_bytes())
+                .decode_utf8_lossy()
+                .to_string()
+        })
+    })
+}
+
+/// Entry of a directory listing.
+struct DirItem {
+    name: String,
+    /// URL path of the entry, starting with `/`.
+    path: String,
+    is_dir: bool,
+    size: u64,
+    /// Modification time as a Unix timestamp.
+    mtime: Option<u64>,
+}
+
+impl DirItem {
+    /// URL of the entry, percent-encoded and escaped for an HTML attribute.
+    fn href(&self) -> String {
+        let url = wikilink::url_path(Path::new("/"), Path::new(&self.path));
+        let mut href = String::new();
+        _ = escape_html(&mut href, &url);
+        href
+    }
+
+    /// Name of the entry, escaped for HTML.
+    fn html_name(&self) -> String {
+        let mut name = String::new();
+        _ = escape_html(&mut name, &self.name);
+        name
+    }
+}
+
+/// List the directory at `dir`, which is located at `url_path` under the root.
+///
+/// Directories go first, then files, both sorted by name.
+fn get_contents(dir: &Path, url_path: &Path) -> io::Result<Vec<DirItem>> {
+    let mut items = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = Path::new("/")
+            .join(url_path)
+            .join(&name)
+            .to_string_lossy()
+            .to_string();
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs());
+        items.push(DirItem {
+            name,
+            path,
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            mtime,
+        });
+    }
+    items.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+    Ok(items)
+}
+
+fn is_image(name: &str) -> bool {
+    let ext = Path::new(name)
+        .extension()
+        .and_then(OsStr::to_str)
+        .unwrap_or_default();
+    mime::from_extension(ext).is_some_and(|mime| mime.starts_with("image/"))
+}
+
+/// Render the directory as a grid of thumbnails which open in a lightbox.
+fn gallery(items: &[DirItem]) -> String {
+    let mut grid = String::new();
+    let mut others = String::new();
+    for (i, item) in items.iter().enumerate() {
+        let (path, name) = (item.href(), item.html_name());
+        if item.is_dir || !is_image(&item.name) {
+            _ = write!(others, "<li><a href='{}'>{}</a></li>", path, name);
+            continue;
+        }
+        _ = write!(
+            grid,
+            "<figure><a href='#image-{i}'><img src='{path}' alt='{name}' loading='lazy'></a>\
+             <figcaption><a href='{path}'>{name}</a></figcaption></figure>\
+             <a href='#_' class='lightbox' id='image-{i}'><img src='{path}' alt='{name}' loading='lazy'></a>",
+        );
+    }
+    let mut html = format!("<div class='gallery'>{}</div>", grid);
+    if !others.is_empty() {
+        _ = write!(html, "<ul>{}</ul>", others);
+    }
+    html
+}
+
+/// Render the directory listing page, as a gallery if it is mostly images.
+fn directory_page(title: &str, items: &[DirItem], show_gallery: bool, show_list: bool) -> String {
+    let images = items.iter().filter(|item| is_image(&item.name)).count();
+    let mostly_images = images > 0 && images * 2 >= items.len();
+    if show_gallery || (mostly_images && !show_list) {
+        let body = format!(
+            "<h1>Directory</h1><p><a href='?list'>Show as list</a></p>{}",
+            gallery(items)
+        );
+        return html_page(title, &body, &SyntaxTheme::default());
+    }
+
+    let mut listing = String::new();
+    for item in items {
+        let (href, name) = (item.href(), item.html_name());
+        if item.is_dir {
+            _ = write!(listing, "<li><a href='{}'>{}</a></li>", href, name);
+        } else {
+            _ = write!(
+                listing,
+                "<li><a href='{}'>{}</a> <a href='{}?download' class='download' title='Download'>{}</a></li>",
+                href, name, href, DOWNLOAD_ICON
+            );
+        }
+    }
+
+    if listing.is_empty() {
+        listing.push_str("Nothing to see here");
+    }
+    let toggle = if images > 0 {
+        "<p><a href='?gallery'>Show as gallery</a></p>"
+    } else {
+        ""
+    };
+    let listing = format!("<h1>Directory</h1>{}<ul>{}</ul>", toggle, listing);
+    html_page(title, &listing, &SyntaxTheme::default())
+}
+
+/// Find the archive containing `path`, and the path of the entry inside it.
+fn find_archive(path: &Path) -> Option<(&Path, String)> {
+    let archive = path
+        .ancestors()
+        .find(|p| archive::is_archive(p) && p.is_file())?;
+    let inner = path.strip_prefix(archive).ok()?;
+    Some((archive, inner.to_string_lossy().to_string()))
+}
+
+/// List the directory `inner` of an archive located at `url_path` under the root.
+///
+/// Returns `None` if there is no such directory in the archive.
+fn archive_contents(
+    archive: &Path,
+    inner: &str,
+    url_path: &Path,
+) -> io::Result<Option<Vec<DirItem>>> {
+    let prefix = if inner.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", inner)
+    };
+    let mut found = inner.is_empty();
+    let mut children = BTreeMap::new();
+    for entry in archive::list(archive)? {
+        let Some(rest) = entry.name.strip_prefix(&prefix) else {
+            continue;
+        };
+        found = true;
+        if rest.is_empty() {
+            continue;
+        }
+        // Not every archive has entries for its directories.
+        let (name, is_dir) = match rest.split_once('/') {
+            Some((dir, _)) => (dir, true),
+            None => (rest, entry.is_dir),
+        };
+        let path = Path::new("/")
+            .join(url_path)
+            .join(name)
+            .to_string_lossy()
+            .to_string();
+        children.entry(name.to_string()).or_insert(DirItem {
+            name: name.to_string(),
+            path,
+            is_dir,
+            size: if is_dir { 0 } else { entry.size },
+            mtime: entry.mtime,
+        });
+    }
+    if !found {
+        return Ok(None);
+    }
+    let mut items: Vec<DirItem> = children.into_values().collect();
+    items.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+    Ok(Some(items))
+}
+
+/// Serve a file or directory inside an archive as if it were extracted.
+fn serve_archive(
+    request: &Request,
+    archive: &Path,
+    inner: &str,
+    relative_path: &Path,
+    title: &str,
+    config: &AppConfig,
+) -> io::Result<ResponseBox> {
+    let (url_path, query) = split_url(request.url());
+    let raw = query_param(query, "raw").is_some();
+    let download = query_param(query, "download").is_some();
+
+    if !url_path.ends_with('/') && !inner.is_empty() {
+        if let Some(data) = archive::read(archive, inner)? {
+            let ext = Path::new(inner)
+                .extension()
+                .and_then(OsStr::to_str)
+                .unwrap_or_default();
+            if matches!(ext, "md" | "markdown") && !raw && !download {
+                let md = String::from_utf8_lossy(&data);
+                let html = markdown_page(&md, title, "", None, config);
+                return Ok(html_response(html, 200).boxed());
+            }
+            let mime = if raw && matches!(ext, "md" | "markdown") {
+                Some("text/plain; charset=utf-8")
+            } else {
+                mime::from_extension(ext).or_else(|| mime::sniff(&data))
+            };
+            let mime = mime.unwrap_or("application/octet-stream");
+            let mut resp = Response::from_data(data)
+                .with_header(Header::from_bytes(&b"Content-Type"[..], mime.as_bytes()).unwrap());
+            if download {
+                resp.add_header(content_disposition(title));
+            }
+            return Ok(resp.boxed());
+        }
+    }
+
+    let Some(items) = archive_contents(archive, inner, relative_path)? else {
+        info!("not found: {}", request.url());
+        return Ok(not_found_response().boxed());
+    };
+    // Relative links between entries only work under a trailing slash.
+    if !url_path.ends_with('/') {
+        let location = if query.is_empty() {
+            format!("{}/", url_path)
+        } else {
+            format!("{}/?{}", url_path, query)
+        };
+        return Ok(redirect_response(&location).boxed());
+    }
+    let show_gallery = query_param(query, "gallery").is_some();
+    let show_list = query_param(query, "list").is_some();
+    let html = directory_page(title, &items, show_gallery, show_list);
+    Ok(html_response(html, 200).boxed())
+}
+
+/// Build JSON tree of the directory, descending up to `depth` levels.
+fn dir_tree(root: &Path, url_path: &Path, depth: usize) -> io::Result<json::Value> {
+    let mut entries = Vec::new();
+    for item in get_contents(&root.join(url_path), url_path)? {
+        let mut entry = vec![
+            ("name", item.name.as_str().into()),
+            ("path", item.path.as_str().into()),
+            ("type", if item.is_dir { "dir" } else { "file" }.into()),
+            ("mtime", item.mtime.into()),
+            ("size", item.size.into()),
+        ];
+        if item.is_dir && depth > 1 {
+            let children = dir_tree(root, &url_path.join(&item.name), depth - 1)?;
+            entry.push(("children", children));
+        }
+        entries.push(json::Value::object(entry));
+    }
+    Ok(json::Value::Array(entries))
+}
+
+/// The task index `index` from a JSON number, unless it is negative or fractional.
+fn task_index(index: f64) -> Option<usize> {
+    (index >= 0.0 && index.fract() == 0.0).then_some(index as usize)
+}
+
+/// Check or uncheck a task list item in a markdown file.
+///
+/// The body is a JSON object with the URL `path` of the file, the `index` of
+/// the task in the document and whether it should be `checked`. Requiring JSON
+/// keeps other sites from posting here without a CORS preflight.
+fn api_task(request: &mut Request, config: &AppConfig) -> Response<Cursor<Vec<u8>>> {
+    if !config.markdown.editable_tasks {
+        return api_error("task editing is disabled, see --edit-tasks", 403);
     }
+    if !is_json(request) {
+        return api_error("expected a JSON body", 415);
+    }
+    let mut body = String::new();
+    if let Err(e) = request.as_reader().read_to_string(&mut body) {
+        return api_error(&format!("cannot read body: {}", e), 400);
+    }
+    let value = match json::parse(&body) {
+        Ok(v) => v,
+        Err(e) => return api_error(&e.to_string(), 400),
+    };
+    let (Some(path), Some(index), Some(checked)) = (
+        value.get("path").and_then(json::Value::as_str),
+        value.get("index").and_then(json::Value::as_f64),
+        value.get("checked").and_then(json::Value::as_bool),
+    ) else {
+        return api_error("expected \"path\", \"index\" and \"checked\" fields", 400);
+    };
+
+    let root = match env::current_dir() {
+        Ok(r) => r,
+        Err(e) => return api_error(&e.to_string(), 500),
+    };
+    let Some(index) = task_index(index) else {
+        return api_error("\"index\" must be a whole number from 0", 400);
+    };
+    // Directory pages show their README.md or index.md.
+    let Some(file) = page_file(&root, path) else {
+        return api_error("forbidden", 403);
+    };
+    if !matches!(
+        file.extension().and_then(OsStr::to_str),
+        Some("md" | "markdown")
+    ) {
+        return api_error("not a markdown file", 400);
+    }
+    let mut md = match fs::read_to_string(&file) {
+        Ok(md) => md,
+        Err(e) => return api_error(&e.to_string(), 404),
+    };
+    // Task indices in the page only match the file when nothing is spliced in.
+    if include::expand(&md, &file, &root) != md {
+        return api_error("cannot edit tasks in a file with includes", 409);
+    }
+    let markers = markdown::task_markers(&md, &config.markdown);
+    let Some(marker) = markers.get(index) else {
+        return api_error("no such task", 404);
+    };
+    md.replace_range(marker.clone(), if checked { "[x]" } else { "[ ]" });
+    if let Err(e) = fs::write(&file, md) {
+        return api_error(&e.to_string(), 500);
+    }
+    info!(
+        "task {} in {} is now {}",
+        index,
+        path,
+        if checked { "done" } else { "open" }
+    );
+    json_response(&json::Value::object([("checked", checked.into())]), 200)
 }
 
-fn serve_file(request: &Request) -> io::Result<Response<Cursor<Vec<u8>>>> {
+/// Scroll the pages showing a file to a line of its source, for editors.
+///
+/// The body is a JSON object with the `path` of the file, on disk or as
+/// served, and the `line` to show, counting from 1.
+fn api_scroll(request: &mut Request, config: &AppConfig) -> Response<Cursor<Vec<u8>>> {
+    if !config.reload {
+        return api_error("scrolling pages needs live reload, see --reload", 403);
+    }
+    if !is_json(request) {
+        return api_error("expected a JSON body", 415);
+    }
+    let mut body = String::new();
+    if let Err(e) = request.as_reader().read_to_string(&mut body) {
+        return api_error(&format!("cannot read body: {}", e), 400);
+    }
+    let value = match json::parse(&body) {
+        Ok(v) => v,
+        Err(e) => return api_error(&e.to_string(), 400),
+    };
+    let (Some(path), Some(line)) = (
+        value.get("path").and_then(json::Value::as_str),
+        value.get("line").and_then(json::Value::as_f64),
+    ) else {
+        return api_error("expected \"path\" and \"line\" fields", 400);
+    };
+
+    let root = match env::current_dir() {
+        Ok(r) => r,
+        Err(e) => return api_error(&e.to_string(), 500),
+    };
+    let url_path = match Path::new(path).is_file() {
+        true => served_url_path(&root, path),
+        false => Some(path.to_string()),
+    };
+    let Some(file) = url_path.and_then(|url_path| page_file(&root, &url_path)) else {
+        return api_error("forbidden", 403);
+    };
+    let pages = sse::broadcast("scroll", &(line.max(1.0) as usize).to_string(), |page| {
+        page == file
+    });
+    json_response(&json::Value::object([("pages", pages.into())]), 200)
+}
+
+/// Return the JSON tree of a directory under the root.
+///
+/// Query parameters are `path` (defaults to `/`) and `depth` (defaults to 1).
+fn api_tree(request: &Request) -> Response<Cursor<Vec<u8>>> {
+    const MAX_DEPTH: usize = 16;
+
+    let (_, query) = split_url(request.url());
+    let path = query_param(query, "path").unwrap_or_else(|| "/".to_string());
+    let depth = match query_param(query, "depth").map(|d| d.parse::<usize>()) {
+        None => 1,
+        Some(Ok(d)) => d.clamp(1, MAX_DEPTH),
+        Some(Err(_)) => return api_error("invalid depth", 400),
+    };
+
+    let root = match env::current_dir() {
+        Ok(r) => r,
+        Err(e) => return api_error(&e.to_string(), 500),
+    };
+    let Some(dir) = resolve_path(&root, &path) else {
+        return api_error("forbidden", 403);
+    };
+    if !dir.is_dir() {
+        return api_error("not a directory", 404);
+    }
+    let url_path = dir
+        .strip_prefix(&root)
+        .expect("resolved path should be under root");
+
+    match dir_tree(&root, url_path, depth) {
+        Ok(tree) => json_response(&tree, 200),
+        Err(e) => api_error(&e.to_string(), 500),
+    }
+}
+
+fn serve_file(request: &Request, config: &AppConfig) -> io::Result<ResponseBox> {
     let cwd = env::current_dir()?;
 
-    let url = percent_decode(request.url().as_bytes()).decode_utf8_lossy();
-    let relative_path = Path::new(url.as_ref())
-        .strip_prefix("/")
-        .expect("url should have / prefix");
-    let absolute_path = cwd.join(relative_path);
+    let (url_path, query) = split_url(request.url());
+    let Some(absolute_path) = resolve_path(&cwd, url_path) else {
+        info!("forbidden: {}", request.url());
+        return Ok(forbidden_response().boxed());
+    };
+    let relative_path = absolute_path
+        .strip_prefix(&cwd)
+        .expect("resolved path should be under root");
 
     let title = absolute_path
         .file_name()
         .and_then(OsStr::to_str)
         .unwrap_or("mdopen");
 
+    if let Some((archive, inner)) = find_archive(&absolute_path) {
+        // The archive itself can still be downloaded.
+        let whole = inner.is_empty() && query_param(query, "download").is_some();
+        if !whole {
+            return serve_archive(request, archive, &inner, relative_path, title, config);
+        }
+    }
+
     if !absolute_path.exists() {
         info!("not found: {}", request.url());
-        return Ok(not_found_response());
+        return Ok(not_found_response().boxed());
     }
 
-    if absolute_path.is_dir() {
-        let entries = fs::read_dir(&absolute_path)?;
-
-        let mut listing = String::new();
+    let show_gallery = query_param(query, "gallery").is_some();
+    let show_list = query_param(query, "list").is_some();
+    let show_listing = query_param(query, "listing").is_some() || show_gallery || show_list;
 
-        for entry in entries {
-            let Ok(entry) = entry else {
-                continue;
-            };
-            let entry_abs_path = entry.path();
-            let entry_name = entry_abs_path
-                .file_name()
-                .expect("filepath")
-                .to_string_lossy()
-                .to_string();
-            let href = relative_path
-                .join(&entry_name)
-                .to_string_lossy()
-                .to_string();
-            _ = write!(listing, "<li><a href='/{}'>{}</a></li>", &href, &entry_name);
+    if absolute_path.is_dir() && !show_listing {
+        if let Some(index) = DIR_INDEX_FILES
+            .iter()
+            .map(|name| absolute_path.join(name))
+            .find(|path| path.is_file())
+        {
+            // Relative links in the index file only work under a trailing slash.
+            if !url_path.ends_with('/') {
+                let location = if query.is_empty() {
+                    format!("{}/", url_path)
+                } else {
+                    format!("{}/?{}", url_path, query)
+                };
+                return Ok(redirect_response(&location).boxed());
+            }
+            let html = cache::page(&index, "index", || {
+                let md = fs::read_to_string(&index)?;
+                let md = include::expand(&md, &index, &cwd);
+                let footer = "<p><a href='?listing'>Show directory listing</a></p>";
+                Ok(markdown_page(
+                    &md,
+                    title,
+                    footer,
+                    Some(links::Base::new(&cwd, &index)),
+                    config,
+                ))
+            })?;
+            return Ok(html_response(html, 200).boxed());
         }
+    }
 
-        if listing.is_empty() {
-            listing.push_str("Nothing to see here");
-        }
-        let listing = format!("<h1>Directory</h1><ul>{}</ul>", listing);
-        let html = render(INDEX, [("title", title), ("body", &listing)]).unwrap();
-        return Ok(html_response(html, 200));
+    if absolute_path.is_dir() {
+        let items = get_contents(&absolute_path, relative_path)?;
+        let html = directory_page(title, &items, show_gallery, show_list);
+        return Ok(html_response(html, 200).boxed());
     }
 
     let ext = relative_path
@@ -129,96 +1256,834 @@ fn serve_file(request: &Request) -> io::Result<Response<Cursor<Vec<u8>>>> {
         .and_then(|s| s.to_str())
         .unwrap_or_default();
 
-    let mut mime = mime_type(ext);
+    let raw = query_param(query, "raw").is_some();
+    let download = query_param(query, "download").is_some();
 
-    let data = fs::read(&absolute_path)?;
+    if matches!(ext, "md" | "markdown") && !raw && !download {
+        let html = cache::page(&absolute_path, "", || {
+            let data = fs::read(&absolute_path)?;
+            let md = include::expand(&String::from_utf8_lossy(&data), &absolute_path, &cwd);
+            Ok(markdown_page(
+                &md,
+                title,
+                "<p><a href='?raw'>View source</a></p>",
+                Some(links::Base::new(&cwd, &absolute_path)),
+                config,
+            ))
+        })?;
+        return Ok(html_response(html, 200).boxed());
+    }
 
-    let data = match ext {
-        "md" | "markdown" => {
-            mime = Some("text/html");
+    if ext == "ipynb" && !raw && !download {
+        let data = fs::read(&absolute_path)?;
+        let source = String::from_utf8_lossy(&data);
 
-            let md = String::from_utf8_lossy(&data).to_string();
+        let body = match metrics::time_render(|| notebook::to_html(&source, &config.markdown)) {
+            Ok(body) => format!("{}<p><a href='?raw'>View source</a></p>", body),
+            Err(e) => {
+                error!("cannot render notebook {}: {}", relative_path.display(), e);
+                format!("<h1>Cannot render notebook</h1><p>{}</p>", e)
+            }
+        };
 
-            let body = markdown::to_html(&md);
+        let html = html_page(title, &body, &config.syntax_theme);
+        return Ok(html_response(html, 200).boxed());
+    }
 
-            let html = render(INDEX, [("title", title), ("body", &body)]).unwrap();
-            html.into()
+    #[cfg(feature = "asciidoc")]
+    if matches!(ext, "adoc" | "asciidoc") && !raw && !download {
+        let source = fs::read_to_string(&absolute_path)?;
+        let html = external_page(&config.asciidoc_command, &source, title, config);
+        return Ok(html_response(html, 200).boxed());
+    }
+
+    if ext == "rst" && !raw && !download {
+        let source = fs::read_to_string(&absolute_path)?;
+        let html = external_page(&config.rst_command, &source, title, config);
+        return Ok(html_response(html, 200).boxed());
+    }
+
+    let mut file = File::open(&absolute_path)?;
+    let mut head = Vec::with_capacity(512);
+    (&mut file).take(512).read_to_end(&mut head)?;
+    file.rewind()?;
+
+    // Data, scripts and stylesheets loaded by HTML pages must be served as
+    // they are; only pages opened in the browser get a viewer.
+    let is_navigation = request
+        .headers()
+        .iter()
+        .any(|h| h.field.equiv("Accept") && h.value.as_str().contains("text/html"));
+    if matches!(ext, "json" | "yaml" | "yml") && is_navigation && !raw && !download {
+        let data = fs::read(&absolute_path)?;
+        let body = match ext {
+            "json" => viewer::json(&data),
+            _ => viewer::yaml(&data),
+        };
+        let body = format!("{}<p><a href='?raw'>View source</a></p>", body);
+        let html = html_page(title, &body, &config.syntax_theme);
+        return Ok(html_response(html, 200).boxed());
+    }
+
+    if let Some(language) = viewer::source_language(ext) {
+        if is_navigation && !raw && !download {
+            let data = fs::read(&absolute_path)?;
+            let body = viewer::source(&data, language);
+            let body = format!("{}<p><a href='?raw'>View source</a></p>", body);
+            let html = html_page(title, &body, &config.syntax_theme);
+            return Ok(html_response(html, 200).boxed());
         }
-        _ => data,
-    };
+    }
 
-    let resp = Response::from_data(data).with_status_code(200);
-    let resp = if let Some(mime) = mime {
-        resp.with_header(Header::from_bytes(&b"Content-Type"[..], mime).unwrap())
+    let is_text = matches!(ext, "txt" | "log") || (ext.is_empty() && mime::is_text(&head));
+    if is_text && is_navigation && !raw && !download {
+        let data = fs::read(&absolute_path)?;
+        let body = viewer::text(&data);
+        let html = html_page(title, &body, &config.syntax_theme);
+        return Ok(html_response(html, 200).boxed());
+    }
+
+    let mime = if raw
+        && matches!(
+            ext,
+            "md" | "markdown" | "ipynb" | "adoc" | "asciidoc" | "rst"
+        ) {
+        // Browsers download text/markdown instead of displaying it.
+        Some("text/plain; charset=utf-8")
     } else {
-        resp
+        mime::from_extension(ext).or_else(|| mime::sniff(&head))
     };
 
+    if mime.is_none() && !raw && !download {
+        let file_size = file.metadata()?.len();
+        let page = query_param(query, "page")
+            .and_then(|p| p.parse::<u64>().ok())
+            .unwrap_or(0)
+            .min(file_size.saturating_sub(1) / viewer::HEXDUMP_PAGE_SIZE);
+        let mut chunk = Vec::new();
+        file.seek(SeekFrom::Start(page * viewer::HEXDUMP_PAGE_SIZE))?;
+        file.by_ref()
+            .take(viewer::HEXDUMP_PAGE_SIZE)
+            .read_to_end(&mut chunk)?;
+        let body = viewer::hexdump(&chunk, page, file_size);
+        let html = html_page(title, &body, &config.syntax_theme);
+        return Ok(html_response(html, 200).boxed());
+    }
+
+    if config.reload && matches!(ext, "html" | "htm") && !raw && !download {
+        let data = fs::read(&absolute_path)?;
+        let html = inject_reload(&String::from_utf8_lossy(&data));
+        return Ok(html_response(html, 200).boxed());
+    }
+
+    let resp = file_response(request, file, mime.unwrap_or("application/octet-stream"))?;
+    if download {
+        return Ok(resp.with_header(content_disposition(title)));
+    }
     Ok(resp)
 }
 
+/// Render a page from HTML produced by an external converter, with live
+/// reload like markdown pages.
+fn external_page(command: &str, source: &str, title: &str, config: &AppConfig) -> String {
+    let body = match metrics::time_render(|| external::convert(command, source)) {
+        Ok(body) => format!("{}<p><a href='?raw'>View source</a></p>", body),
+        Err(e) => {
+            error!("cannot render {}: {}", title, e);
+            let mut body = String::from("<h1>Cannot render document</h1><pre>");
+            _ = escape_html(&mut body, &e.to_string());
+            body.push_str("</pre>");
+            body
+        }
+    };
+    html_page_with_head(title, &live_head(config), &body, &config.syntax_theme)
+}
+
+/// Parse a single `bytes=start-end` range of a file with `len` bytes.
+///
+/// Returns `Ok(None)` for ranges that should be ignored, like malformed
+/// ones, and `Err(())` for ranges that cannot be satisfied.
+fn parse_range(value: &str, len: u64) -> Result<Option<(u64, u64)>, ()> {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    // Multiple ranges would need a multipart response, send the whole file.
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let Some((start, end)) = spec.split_once('-') else {
+        return Ok(None);
+    };
+    let number = |s: &str| s.parse::<u64>().ok();
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let Some(suffix) = number(suffix) else {
+                return Ok(None);
+            };
+            if suffix == 0 {
+                return Err(());
+            }
+            (len.saturating_sub(suffix), len.saturating_sub(1))
+        }
+        (start, end) => {
+            // An open range goes to the end of the file.
+            let end = if end.is_empty() {
+                Some(u64::MAX)
+            } else {
+                number(end)
+            };
+            let (Some(start), Some(end)) = (number(start), end) else {
+                return Ok(None);
+            };
+            (start, end.min(len.saturating_sub(1)))
+        }
+    };
+    if start >= len || start > end {
+        return Err(());
+    }
+    Ok(Some((start, end)))
+}
+
+/// Stream a file from disk, honoring a `Range` request header.
+fn file_response(request: &Request, mut file: File, mime: &str) -> io::Result<ResponseBox> {
+    let len = file.metadata()?.len();
+    let range = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Range"))
+        .map(|h| parse_range(h.value.as_str(), len));
+
+    let content_type = Header::from_bytes(&b"Content-Type"[..], mime).unwrap();
+    let accept_ranges = Header::from_bytes(&b"Accept-Ranges"[..], &b"bytes"[..]).unwrap();
+
+    let resp = match range {
+        Some(Ok(Some((start, end)))) => {
+            file.seek(SeekFrom::Start(start))?;
+            let content_range = format!("bytes {}-{}/{}", start, end, len);
+            let length = end - start + 1;
+            Response::empty(206)
+                .with_data(file.take(length), Some(length as usize))
+                .with_header(
+                    Header::from_bytes(&b"Content-Range"[..], content_range.as_bytes()).unwrap(),
+                )
+                .boxed()
+        }
+        Some(Err(())) => {
+            let content_range = format!("bytes */{}", len);
+            Response::empty(416)
+                .with_header(
+                    Header::from_bytes(&b"Content-Range"[..], content_range.as_bytes()).unwrap(),
+                )
+                .boxed()
+        }
+        Some(Ok(None)) | None => Response::empty(200)
+            .with_data(file, Some(len as usize))
+            .boxed(),
+    };
+    Ok(resp.with_header(content_type).with_header(accept_ranges))
+}
+
 /// Construct HTML response for request.
-fn handle(request: &Request) -> Response<Cursor<Vec<u8>>> {
+fn handle(request: &mut Request, config: &AppConfig) -> ResponseBox {
+    if let Some(response) = try_api(request, config) {
+        return response.boxed();
+    };
+
     if request.method() != &Method::Get {
         info!("method not allowed: {} {}", request.method(), request.url());
-        return html_response("<h1>405 Method Not Allowed</h1>", 405);
+        return html_response("<h1>405 Method Not Allowed</h1>", 405).boxed();
     }
 
-    if let Some(response) = try_asset_file(request) {
-        return response;
+    if let Some(response) = try_asset_file(request, config) {
+        return response.boxed();
+    };
+
+    if let Some(response) = try_stdin(request, config) {
+        return response.boxed();
+    };
+
+    if let Some(response) = try_health(request) {
+        return response.boxed();
+    };
+
+    if let Some(response) = try_metrics(request) {
+        return response.boxed();
+    };
+
+    if let Some(response) = try_feed(request, config) {
+        return response.boxed();
     };
 
-    match serve_file(request) {
+    if let Some(response) = try_sitemap(request, config) {
+        return response.boxed();
+    };
+
+    match serve_file(request, config) {
         Ok(r) => r,
         Err(err) => {
             error!("cannot serve file: {}", err);
-            internal_error_response()
+            internal_error_response().boxed()
         }
     }
 }
 
-fn open_browser(browser: &Option<String>, url: &str) -> io::Result<()> {
+pub fn open_browser(browser: &Option<String>, url: &str) -> io::Result<()> {
     match browser {
         Some(ref browser) => open::with(url, browser),
         None => open::that(url),
     }
 }
 
+/// Browser URL of a path served on `port`.
+pub fn file_url(port: u16, url_path: &str) -> String {
+    format!("http://localhost:{}{}", port, url_path)
+}
+
+/// Document of `changed` modified last, leaving out removed ones.
+fn last_saved(changed: &[PathBuf]) -> Option<&PathBuf> {
+    changed
+        .iter()
+        .filter(|path| {
+            let ext = path.extension().and_then(OsStr::to_str);
+            matches!(ext, Some("md" | "markdown" | "rst"))
+                || (cfg!(feature = "asciidoc") && matches!(ext, Some("adoc" | "asciidoc")))
+        })
+        .filter_map(|path| Some((fs::metadata(path).ok()?.modified().ok()?, path)))
+        .max()
+        .map(|(_, path)| path)
+}
+
+/// URL path of `file`, relative to the served directory `root` or absolute,
+/// or `None` if it is outside of `root`.
+pub fn served_url_path(root: &Path, file: &str) -> Option<String> {
+    let path = root.join(file);
+    // Resolves `..` and symlinks of files that exist.
+    let path = path.canonicalize().unwrap_or(path);
+    let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    let relative = path.strip_prefix(&root).ok()?;
+    if relative.components().any(|c| c == Component::ParentDir) {
+        return None;
+    }
+    Some(wikilink::url_path(Path::new(""), relative))
+}
+
+/// Directory to serve instead of the current one, `cwd`, when some of `files`
+/// are outside of it: the directory they are all in, if there is one.
+fn files_root(cwd: &Path, files: &[String]) -> Option<PathBuf> {
+    let files: Vec<&String> = files.iter().filter(|file| *file != "-").collect();
+    if files
+        .iter()
+        .all(|file| served_url_path(cwd, file).is_some())
+    {
+        return None;
+    }
+    // The directories of new files exist even when the files don't.
+    let mut parents = files.iter().map(|file| {
+        let parent = cwd.join(file).parent()?.to_path_buf();
+        Some(parent.canonicalize().unwrap_or(parent))
+    });
+    let first = parents.next()??;
+    parents
+        .all(|parent| parent.as_ref() == Some(&first))
+        .then_some(first)
+}
+
+/// Ask an mdopen instance already listening on `addr` to open `files`.
+///
+/// Returns `false` if whatever listens there is not mdopen.
+fn forward_to_running_instance(addr: SocketAddr, files: &[String]) -> bool {
+    let mut addr = addr;
+    if addr.ip().is_unspecified() {
+        addr.set_ip(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    }
+
+    let is_mdopen = client::request(addr, "GET", HEALTH_URL, None)
+        .ok()
+        .filter(|(status, _)| *status == 200)
+        .and_then(|(_, body)| json::parse(&body).ok())
+        .is_some_and(|health| health.get("name").and_then(json::Value::as_str) == Some("mdopen"));
+    if !is_mdopen {
+        return false;
+    }
+    info!("mdopen is already running at http://{}", addr);
+
+    let cwd = env::current_dir().unwrap_or_default();
+    let mut paths = Vec::new();
+    for file in files {
+        if file == "-" {
+            error!("cannot pass stdin to the running instance");
+            continue;
+        }
+        paths.push(cwd.join(file).to_string_lossy().to_string());
+    }
+    if paths.is_empty() {
+        return true;
+    }
+
+    let command = json::Value::object([("command", "open-file".into()), ("paths", paths.into())]);
+    if let Err(e) = control::send(addr, &command) {
+        error!("running instance cannot open files: {}", e);
+    }
+    true
+}
+
 fn main() {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
-    let args = cli::Args::parse();
+    let mut args = cli::Args::parse();
+
+    // Files outside of the current directory can only be served, and watched
+    // for live reload, from the directory they are in.
+    let serving = args.ctl.is_none() && !args.render && args.export_watch.is_none();
+    let cwd = env::current_dir().unwrap_or_default();
+    if let Some(root) = serving.then(|| files_root(&cwd, &args.files)).flatten() {
+        for file in args.files.iter_mut().filter(|file| *file != "-") {
+            *file = cwd.join(&*file).to_string_lossy().to_string();
+        }
+        if let Err(e) = env::set_current_dir(&root) {
+            error!("cannot serve {}: {}", root.display(), e);
+            return;
+        }
+        info!("serving {}, which has the files", root.display());
+    }
+
+    if args.files.iter().any(|f| f == "-") {
+        let mut md = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut md) {
+            error!("cannot read stdin: {}", e);
+            return;
+        }
+        _ = STDIN_MARKDOWN.set(md);
+    }
+
+    let syntax_theme_css = match &args.syntax_theme {
+        cli::SyntaxTheme::File(path) => match theme::load(path) {
+            Ok(css) => Some(css),
+            Err(e) => {
+                error!("cannot load syntax theme {}: {}", path.display(), e);
+                return;
+            }
+        },
+        cli::SyntaxTheme::Named { .. } => None,
+    };
 
     let port = args.port;
-    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port);
+    let addr = SocketAddr::new(args.host, port);
+
+    let config = AppConfig {
+        port,
+        browser: args.browser,
+        #[cfg(feature = "asciidoc")]
+        asciidoc_command: args.asciidoc_command,
+        rst_command: args.rst_command,
+        toc: args.toc,
+        syntax_theme: args.syntax_theme,
+        syntax_theme_css,
+        syntax_dir: args.syntax_dir,
+        reading_time: args.reading_time,
+        slides: args.slides,
+        reload: args.reload,
+        follow: args.follow,
+        feed: args.feed,
+        sitemap: args.sitemap,
+        site_url: args.site_url,
+        markdown: markdown::Options {
+            smart_punctuation: !args.no_smart_punctuation,
+            footnotes: !args.no_footnotes,
+            tables: !args.no_tables,
+            tasklists: !args.no_tasklists,
+            gfm: !args.no_gfm,
+            math: !args.no_math,
+            sub_sup: args.sub_sup,
+            breaks: args.breaks,
+            mermaid: !args.no_mermaid,
+            dot_command: args.dot_command,
+            plantuml_command: args.plantuml_command,
+            plantuml_server: args.plantuml_server,
+            server_math: args.server_math,
+            wikilink_root: args
+                .wikilinks
+                .then(|| env::current_dir().expect("cannot get current dir")),
+            links: None,
+            number_headings: args.number_headings,
+            editable_tasks: args.edit_tasks,
+            line_numbers: args.line_numbers,
+            sanitize: args.sanitize.then(|| {
+                let mut policy = sanitize::Policy::default();
+                policy.allow(args.sanitize_allow);
+                policy
+            }),
+            bibliography: args.bibliography,
+            lang_aliases: args.lang_aliases,
+            detect_lang: args.detect_lang,
+            source_lines: false,
+        },
+    };
+
+    if let Some(ctl_args) = args.ctl {
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port);
+        process::exit(control::run(addr, &ctl_args));
+    }
+
+    if args.render {
+        process::exit(export::run(config, &args.files, &args.export));
+    }
+
+    if let Some((src, out)) = &args.export_watch {
+        process::exit(site::watch(&config, src, out, args.export.minify));
+    }
 
     let server = match Server::http(addr) {
         Ok(s) => s,
         Err(e) => {
+            if forward_to_running_instance(addr, &args.files) {
+                return;
+            }
             error!("cannot start server: {}", e);
             return;
         }
     };
 
     info!("serving at http://{}", addr);
+    STARTED.get_or_init(Instant::now);
+
+    if let Some(timeout) = args.idle_timeout {
+        exit_when_idle(timeout);
+    }
+    if args.exit_on_close {
+        exit_when_closed();
+    }
+
+    if config.reload {
+        let root = env::current_dir().unwrap_or_default();
+        let ignore = args.watch_ignore.clone();
+        cache::enable();
+        let follow = config.follow;
+        watch::spawn(root.clone(), ignore, args.watch_interval, move |changed| {
+            cache::clear();
+            for path in changed {
+                debug!("changed {}", path.display());
+                sse::broadcast("change", &wikilink::url_path(&root, path), |page| {
+                    depends_on(page, path, &root)
+                });
+            }
+            if let Some(path) = follow.then(|| last_saved(changed)).flatten() {
+                sse::broadcast_all("follow", &wikilink::url_path(&root, path));
+            }
+        });
+    }
+
+    #[cfg(feature = "mdns")]
+    if let std::net::IpAddr::V4(ip) = args.host {
+        mdns::advertise(ip, port);
+    }
 
     if !args.files.is_empty() {
+        let browser = config.browser.clone();
+        let root = env::current_dir().unwrap_or_default();
         thread::spawn(move || {
             for file in args.files.into_iter() {
-                let url = format!("http://localhost:{}/{}", &port, &file);
+                let url = if file == "-" {
+                    file_url(port, STDIN_URL)
+                } else if let Some(url_path) = served_url_path(&root, &file) {
+                    file_url(port, &url_path)
+                } else {
+                    error!(
+                        "cannot open {}: it is outside of the served directory {}, run mdopen from a directory containing all the files",
+                        file,
+                        root.display()
+                    );
+                    continue;
+                };
                 info!("opening {}", &url);
-                if let Err(e) = open_browser(&args.browser, &url) {
+                if let Err(e) = open_browser(&browser, &url) {
                     error!("cannot open browser: {}", e);
                 }
             }
         });
     }
 
-    for request in server.incoming_requests() {
+    for mut request in server.incoming_requests() {
         debug!("{} {}", request.method(), request.url());
-        let resp = handle(&request);
+        touch_activity();
+        if let Some(page) = request.url().strip_prefix(RELOAD_PREFIX) {
+            if config.reload {
+                let (page, _) = split_url(page);
+                let root = env::current_dir().unwrap_or_default();
+                let file = page_file(&root, &format!("/{}", page));
+                metrics::record_response(200);
+                sse::serve(request, file);
+                continue;
+            }
+        }
+        let resp = handle(&mut request, &config);
+        metrics::record_response(resp.status_code().0);
         if let Err(e) = request.respond(resp) {
             error!("cannot send response: {}", e);
         };
+        if control::SHUTDOWN.load(Ordering::Relaxed) {
+            sse::shutdown();
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Configuration with every option off.
+    pub fn config() -> AppConfig {
+        AppConfig {
+            port: 0,
+            browser: None,
+            #[cfg(feature = "asciidoc")]
+            asciidoc_command: String::new(),
+            rst_command: String::new(),
+            toc: None,
+            syntax_theme: cli::SyntaxTheme::default(),
+            syntax_theme_css: None,
+            syntax_dir: None,
+            reading_time: false,
+            slides: false,
+            reload: false,
+            follow: false,
+            feed: false,
+            sitemap: false,
+            site_url: None,
+            markdown: markdown::Options::default(),
+        }
+    }
+
+    fn root() -> PathBuf {
+        env::temp_dir().join("mdopen-test-root")
+    }
+
+    #[test]
+    fn files_are_opened_under_the_root() {
+        let root = env::temp_dir().join("mdopen-test-open");
+        _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("docs/a b.md"), "").unwrap();
+        let absolute = root.join("docs/a b.md");
+
+        assert_eq!(
+            served_url_path(&root, "docs/../docs/a b.md").as_deref(),
+            Some("/docs/a%20b.md")
+        );
+        assert_eq!(
+            served_url_path(&root, &absolute.to_string_lossy()).as_deref(),
+            Some("/docs/a%20b.md")
+        );
+        assert_eq!(served_url_path(&root, "new.md").as_deref(), Some("/new.md"));
+        assert_eq!(served_url_path(&root.join("docs"), "../x.md"), None);
+
+        let docs = root.join("docs").canonicalize().unwrap();
+        let files = |files: &[&str]| files.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+        let elsewhere = root.join("elsewhere");
+        fs::create_dir_all(&elsewhere).unwrap();
+        assert_eq!(files_root(&root, &files(&["docs/a b.md", "-"])), None);
+        assert_eq!(
+            files_root(&elsewhere, &files(&["../docs/a b.md", "../docs/new.md"])),
+            Some(docs)
+        );
+        assert_eq!(
+            files_root(&elsewhere, &files(&["../docs/a b.md", "../x.md"])),
+            None
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn pages_depend_on_their_includes() {
+        let root = env::temp_dir().join("mdopen-test-depends");
+        _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let root = root.canonicalize().unwrap();
+        let page = root.join("index.md");
+        fs::write(&page, "{{#include part.md}}").unwrap();
+        fs::write(root.join("part.md"), "part").unwrap();
+        fs::write(root.join("other.md"), "other").unwrap();
+
+        assert!(depends_on(&page, &page, &root));
+        assert!(depends_on(&page, &root.join("part.md"), &root));
+        assert!(depends_on(&page, &root.join("logo.png"), &root));
+        assert!(!depends_on(&page, &root.join("other.md"), &root));
+        assert!(depends_on(
+            &root.join("a.zip/b.md"),
+            &root.join("a.zip"),
+            &root
+        ));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn markdown_saved_last_is_followed() {
+        let root = env::temp_dir().join("mdopen-test-follow");
+        _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let saved = |name: &str, secs: u64| {
+            let path = root.join(name);
+            let time = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+            fs::File::create(&path).unwrap().set_modified(time).unwrap();
+            path
+        };
+        let old = saved("old.md", 1);
+        let new = saved("new.md", 2);
+        let image = saved("logo.png", 3);
+        let removed = root.join("removed.md");
+
+        let changed = [image, new.clone(), old, removed];
+        assert_eq!(last_saved(&changed), Some(&new));
+        assert_eq!(last_saved(&changed[..1]), None);
+
+        let rst = saved("guide.rst", 4);
+        let changed = [new, rst.clone()];
+        assert_eq!(last_saved(&changed), Some(&rst));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn health_and_render_requests() {
+        let health = health().to_string();
+        assert!(health.starts_with(&format!(
+            r#"{{"name":"mdopen","version":"{}","uptime":"#,
+            cli::VERSION
+        )));
+        assert!(health.ends_with(r#","reload_clients":0}"#));
+        assert!(health.contains(r#","features":["#));
+
+        let raw = || render_request("# Hi".to_string(), false);
+        assert_eq!(raw(), Ok(("# Hi".into(), "mdopen".into(), false)));
+        let body = r##"{"markdown": "# Hi", "title": "T", "page": true}"##;
+        assert_eq!(
+            render_request(body.to_string(), true),
+            Ok(("# Hi".into(), "T".into(), true))
+        );
+        assert_eq!(
+            render_request(r#"{"page": true}"#.to_string(), true),
+            Err("missing string field \"markdown\"".to_string())
+        );
+        assert!(render_request("# Hi".to_string(), true).is_err());
+    }
+
+    #[test]
+    fn byte_ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Ok(Some((0, 99))));
+        assert_eq!(parse_range("bytes=990-2000", 1000), Ok(Some((990, 999))));
+        assert_eq!(parse_range("bytes=500-", 1000), Ok(Some((500, 999))));
+        assert_eq!(parse_range("bytes=-100", 1000), Ok(Some((900, 999))));
+        assert_eq!(parse_range("bytes=-2000", 1000), Ok(Some((0, 999))));
+        assert_eq!(parse_range("bytes=1000-", 1000), Err(()));
+        assert_eq!(parse_range("bytes=5-4", 1000), Err(()));
+        assert_eq!(parse_range("bytes=-0", 1000), Err(()));
+        assert_eq!(parse_range("bytes=0-", 0), Err(()));
+        assert_eq!(parse_range("bytes=-1", 0), Err(()));
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), Ok(None));
+        assert_eq!(parse_range("bytes=a-", 1000), Ok(None));
+        assert_eq!(parse_range("items=0-1", 1000), Ok(None));
+    }
+
+    #[test]
+    fn reload_is_injected_before_the_body_end() {
+        let html = inject_reload("<html><body><p>Hi</p></BODY></html>");
+        assert!(html.starts_with("<html><body><p>Hi</p><script data-prefix=\"/__mdopen_reload/\">"));
+        assert!(html.ends_with("})();\n</script>\n</BODY></html>"));
+        let fragment = inject_reload("<p>Hi</p>");
+        assert!(fragment.starts_with("<p>Hi</p><script"));
+        assert!(fragment.ends_with("</script>\n"));
+    }
+
+    #[test]
+    fn resolve_path_inside_root() {
+        let root = root();
+        assert_eq!(resolve_path(&root, "/"), Some(root.clone()));
+        assert_eq!(resolve_path(&root, "/a/b.md"), Some(root.join("a/b.md")));
+        assert_eq!(resolve_path(&root, "/a/../b.md"), Some(root.join("b.md")));
+        assert_eq!(
+            resolve_path(&root, "/./a/%20b.md"),
+            Some(root.join("a/ b.md"))
+        );
+    }
+
+    #[test]
+    fn resolve_path_rejects_traversal() {
+        let root = root();
+        assert_eq!(resolve_path(&root, "/.."), None);
+        assert_eq!(resolve_path(&root, "/../../etc/passwd"), None);
+        assert_eq!(resolve_path(&root, "/a/../../etc/passwd"), None);
+    }
+
+    #[test]
+    fn resolve_path_rejects_encoded_traversal() {
+        let root = root();
+        assert_eq!(resolve_path(&root, "/%2e%2e/etc/passwd"), None);
+        assert_eq!(resolve_path(&root, "/%2E%2E%2Fetc%2Fpasswd"), None);
+        assert_eq!(resolve_path(&root, "/a%2f..%2f..%2fetc"), None);
+        assert_eq!(resolve_path(&root, "/..%2f..%2fetc%2fpasswd"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolve_path_rejects_symlink_escape() {
+        let root = env::temp_dir().join("mdopen-test-symlink");
+        _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        std::os::unix::fs::symlink("/etc", root.join("etc")).unwrap();
+
+        assert_eq!(resolve_path(&root, "/etc/passwd"), None);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn page_template_renders() {
+        let html = html_page("a < b", "<p>body</p>", &SyntaxTheme::default());
+        assert!(html.contains("<title>a &lt; b</title>"));
+        assert!(html.contains("<p>body</p>"));
+        assert!(html.contains("styles/github-dark.min.css"));
+    }
+
+    #[test]
+    fn listing_names_are_escaped() {
+        let name = "<img src=x onerror=alert(1)>.txt";
+        let items = [DirItem {
+            name: name.to_string(),
+            path: format!("/a.zip/{}", name),
+            is_dir: false,
+            size: 0,
+            mtime: None,
+        }];
+        let html = directory_page("a.zip", &items, false, false);
+        assert!(!html.contains("<img"));
+        assert!(html.contains(
+            "<a href='/a.zip/%3Cimg%20src=x%20onerror=alert(1)%3E.txt'>&lt;img src=x onerror=alert(1)&gt;.txt</a>"
+        ));
+    }
+
+    #[test]
+    fn gallery_names_are_escaped() {
+        let items = [DirItem {
+            name: "it's #1?.png".to_string(),
+            path: "/it's #1?.png".to_string(),
+            is_dir: false,
+            size: 0,
+            mtime: None,
+        }];
+        let html = gallery(&items);
+        assert!(html.contains("<img src='/it&#39;s%20%231%3F.png' alt='it&#39;s #1?.png'"));
+        assert!(html.contains("<a href='/it&#39;s%20%231%3F.png'>it&#39;s #1?.png</a>"));
+    }
+
+    #[test]
+    fn task_indices_are_whole_numbers() {
+        assert_eq!(task_index(0.0), Some(0));
+        assert_eq!(task_index(3.0), Some(3));
+        assert_eq!(task_index(-1.0), None);
+        assert_eq!(task_index(1.5), None);
+        assert_eq!(task_index(f64::NAN), None);
     }
 }
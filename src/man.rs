@@ -0,0 +1,251 @@
+//! Conversion of markdown to a troff man page, for
+//! `mdopen render --format man`.
+//!
+//! A level 1 heading at the start names the page, like `mdopen(1)` or
+//! `mdopen(1) -- preview markdown files`, as in ronn. Level 2 headings are
+//! sections and deeper ones subsections. Frontmatter is left out.
+
+use pulldown_cmark::{Alignment, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+
+/// Parse a page name like `mdopen(1) -- preview markdown`.
+fn page_name(heading: &str) -> Option<(&str, &str, Option<&str>)> {
+    let (name, description) = match heading.split_once(" -- ") {
+        Some((name, description)) => (name.trim(), Some(description.trim())),
+        None => (heading.trim(), None),
+    };
+    let (name, section) = name.strip_suffix(')')?.split_once('(')?;
+    (!name.is_empty() && !section.is_empty()).then_some((name, section, description))
+}
+
+/// Man page being written, keeping track of the start of lines.
+#[derive(Default)]
+struct Writer {
+    out: String,
+    /// Fonts of the enclosing emphasis, strong and code spans.
+    fonts: Vec<&'static str>,
+}
+
+impl Writer {
+    fn newline(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+    }
+
+    fn last_line(&self) -> &str {
+        self.out
+            .trim_end_matches('\n')
+            .rsplit('\n')
+            .next()
+            .unwrap_or_default()
+    }
+
+    /// Write a request like `.SH` on a line of its own.
+    fn request(&mut self, request: &str) {
+        self.newline();
+        self.out.push_str(request);
+        self.out.push('\n');
+    }
+
+    fn text(&mut self, text: &str) {
+        for c in text.chars() {
+            // A dot or quote at the start of a line would be a request.
+            let line_start = self.out.is_empty() || self.out.ends_with('\n');
+            if line_start && (c == '.' || c == '\'') {
+                self.out.push_str("\\&");
+            }
+            match c {
+                '\\' => self.out.push_str("\\e"),
+                '-' => self.out.push_str("\\-"),
+                _ => self.out.push(c),
+            }
+        }
+    }
+
+    fn push_font(&mut self, font: &'static str) {
+        self.fonts.push(font);
+        self.out.push_str(&format!("\\f{}", font));
+    }
+
+    fn pop_font(&mut self) {
+        self.fonts.pop();
+        let font = self.fonts.last().copied().unwrap_or("R");
+        self.out.push_str(&format!("\\f{}", font));
+    }
+}
+
+/// Convert `md` to a man page, named `name` unless its heading says otherwise.
+pub fn from_markdown(md: &str, name: &str) -> String {
+    let mut w = Writer::default();
+    let mut heading: Option<(HeadingLevel, String)> = None;
+    let mut titled = false;
+    let mut lists = Vec::<Option<u64>>::new();
+    let mut links = Vec::<String>::new();
+    let mut table_cell = 0;
+    let mut in_metadata = false;
+
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_YAML_STYLE_METADATA_BLOCKS
+        | Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS;
+    for event in Parser::new_ext(md, options) {
+        // Frontmatter is not part of the page.
+        match &event {
+            Event::Start(Tag::MetadataBlock(_)) => in_metadata = true,
+            Event::End(TagEnd::MetadataBlock(_)) => in_metadata = false,
+            _ if in_metadata => continue,
+            _ => {}
+        }
+        if let Some((level, text)) = &mut heading {
+            match event {
+                Event::Text(t) | Event::Code(t) => text.push_str(&t),
+                Event::End(TagEnd::Heading(_)) => {
+                    let (level, text) = (*level, text.clone());
+                    heading = None;
+                    match level {
+                        // `.TH` must come first, so later headings can't name the page.
+                        HeadingLevel::H1 if w.out.is_empty() => {
+                            titled = true;
+                            let (name, section, description) = page_name(&text).unwrap_or((
+                                name,
+                                "1",
+                                Some(text.as_str()).filter(|t| !t.is_empty()),
+                            ));
+                            w.request(&format!(".TH \"{}\" \"{}\"", name.to_uppercase(), section));
+                            if let Some(description) = description {
+                                w.request(".SH NAME");
+                                w.text(&format!("{} - {}", name, description));
+                            }
+                        }
+                        HeadingLevel::H1 | HeadingLevel::H2 => {
+                            w.request(".SH");
+                            w.text(&text.to_uppercase());
+                        }
+                        _ => {
+                            w.request(".SS");
+                            w.text(&text);
+                        }
+                    }
+                    w.newline();
+                }
+                _ => {}
+            }
+            continue;
+        }
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => heading = Some((level, String::new())),
+            Event::Start(Tag::Paragraph) => match lists.is_empty() {
+                true => w.request(".PP"),
+                // The first paragraph of an item follows its `.IP`.
+                false if w.last_line().starts_with(".IP") => {}
+                false => w.request(".IP"),
+            },
+            Event::End(TagEnd::Paragraph) => w.newline(),
+            Event::Start(Tag::CodeBlock(_)) => {
+                w.request(".PP");
+                w.request(".RS 4");
+                w.request(".nf");
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                w.request(".fi");
+                w.request(".RE");
+            }
+            Event::Start(Tag::BlockQuote(_)) => w.request(".RS 4"),
+            Event::End(TagEnd::BlockQuote(_)) => w.request(".RE"),
+            Event::Start(Tag::List(start)) => {
+                if !lists.is_empty() {
+                    w.request(".RS 4");
+                }
+                lists.push(start);
+            }
+            Event::End(TagEnd::List(_)) => {
+                lists.pop();
+                if !lists.is_empty() {
+                    w.request(".RE");
+                }
+            }
+            Event::Start(Tag::Item) => match lists.last_mut() {
+                Some(Some(number)) => {
+                    w.request(&format!(".IP {}. 4", number));
+                    *number += 1;
+                }
+                _ => w.request(".IP \\(bu 2"),
+            },
+            Event::Start(Tag::Emphasis) => w.push_font("I"),
+            Event::Start(Tag::Strong) => w.push_font("B"),
+            Event::End(TagEnd::Emphasis | TagEnd::Strong) => w.pop_font(),
+            Event::Code(code) => {
+                w.push_font("B");
+                w.text(&code);
+                w.pop_font();
+            }
+            Event::Start(Tag::Link { dest_url, .. }) => links.push(dest_url.to_string()),
+            Event::End(TagEnd::Link) => {
+                let url = links.pop().unwrap_or_default();
+                // Autolinks already show their address.
+                let shown = w.out.ends_with(&url.replace('-', "\\-"));
+                if !url.starts_with('#') && !shown {
+                    w.text(&format!(" <{}>", url));
+                }
+            }
+            Event::Start(Tag::Table(alignments)) => {
+                w.request(".TS");
+                w.request("allbox tab(\t);");
+                let columns: Vec<_> = alignments
+                    .iter()
+                    .map(|alignment| match alignment {
+                        Alignment::Center => "c",
+                        Alignment::Right => "r",
+                        _ => "l",
+                    })
+                    .collect();
+                w.request(&format!("{}.", columns.join(" ")));
+            }
+            Event::End(TagEnd::Table) => w.request(".TE"),
+            Event::Start(Tag::TableHead | Tag::TableRow) => table_cell = 0,
+            Event::End(TagEnd::TableHead | TagEnd::TableRow) => w.newline(),
+            Event::Start(Tag::TableCell) => {
+                if table_cell > 0 {
+                    w.out.push('\t');
+                }
+                table_cell += 1;
+            }
+            Event::Text(text) => w.text(&text),
+            Event::SoftBreak => w.newline(),
+            Event::HardBreak => w.request(".br"),
+            Event::Rule => w.request(".sp"),
+            _ => {}
+        }
+    }
+    if !titled {
+        w.out = format!(".TH \"{}\" \"1\"\n{}", name.to_uppercase(), w.out);
+    }
+    w.newline();
+    w.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markdown_becomes_roff() {
+        let man = from_markdown(
+            "# mdopen(1) -- preview markdown\n\n## Synopsis\n\n`mdopen` [*FILES*]\n\n### Options\n\n- `-p PORT`: port\n- see <https://x.org>\n\n```\n.hidden\n```\n",
+            "x",
+        );
+        assert_eq!(
+            man,
+            ".TH \"MDOPEN\" \"1\"\n.SH NAME\nmdopen \\- preview markdown\n.SH\nSYNOPSIS\n.PP\n\\fBmdopen\\fR [\\fIFILES\\fR]\n.SS\nOptions\n.IP \\(bu 2\n\\fB\\-p PORT\\fR: port\n.IP \\(bu 2\nsee https://x.org\n.PP\n.RS 4\n.nf\n\\&.hidden\n.fi\n.RE\n"
+        );
+        assert!(from_markdown("Some text.\n", "tool")
+            .starts_with(".TH \"TOOL\" \"1\"\n.PP\nSome text.\n"));
+        assert_eq!(
+            from_markdown("---\ntitle: hello\n---\n# tool(8)\n\ntext\n", "x"),
+            ".TH \"TOOL\" \"8\"\n.PP\ntext\n"
+        );
+        assert_eq!(
+            from_markdown("text\n\n# Usage\n", "tool"),
+            ".TH \"TOOL\" \"1\"\n.PP\ntext\n.SH\nUSAGE\n"
+        );
+    }
+}
@@ -1,55 +1,980 @@
-use pulldown_cmark::{html::push_html, CowStr, Event, Tag, TagEnd};
+use crate::{
+    abbr, citation, container, detect, diagram, fence, footnote, frontmatter, image, links, mathml,
+    sanitize, spoiler, wikilink,
+};
+use pulldown_cmark::{
+    html::push_html, CodeBlockKind, CowStr, Event, MetadataBlockKind, Parser, Tag, TagEnd,
+};
+use pulldown_cmark_escape::{escape_href, escape_html};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::PathBuf;
 
-fn to_tag_anchor(name: &str) -> String {
-    name.to_lowercase()
+/// Switches for optional markdown extensions.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Turn quotes and dashes into typographic ones.
+    pub smart_punctuation: bool,
+    pub footnotes: bool,
+    pub tables: bool,
+    pub tasklists: bool,
+    /// GitHub's extensions, like `> [!NOTE]` alerts.
+    pub gfm: bool,
+    /// `$inline$` and `$$display$$` math.
+    pub math: bool,
+    /// `~subscript~` and `^superscript^`, instead of single tilde strikethrough.
+    pub sub_sup: bool,
+    /// Render single newlines in paragraphs as line breaks.
+    pub breaks: bool,
+    /// Render `mermaid` code blocks as diagrams.
+    pub mermaid: bool,
+    /// Command rendering `dot` code blocks to SVG.
+    pub dot_command: String,
+    /// Command rendering `plantuml` code blocks to SVG.
+    pub plantuml_command: String,
+    /// PlantUML server to link diagrams from, instead of running the command.
+    pub plantuml_server: Option<String>,
+    /// Directory to resolve `[[Page]]` links against, if they are enabled.
+    pub wikilink_root: Option<PathBuf>,
+    /// Render math to MathML instead of leaving it to KaTeX in the browser.
+    pub server_math: bool,
+    /// Where the rendered file is, to mark relative links to missing files.
+    pub links: Option<links::Base>,
+    /// Prefix headings with section numbers like `1.2.`.
+    pub number_headings: bool,
+    /// Render task list checkboxes that can be toggled.
+    pub editable_tasks: bool,
+    /// Show line numbers in all code blocks, not just `linenos` ones.
+    pub line_numbers: bool,
+    /// Clean raw HTML in the markdown, for untrusted documents.
+    pub sanitize: Option<sanitize::Policy>,
+    /// Bibliography for documents without one in their frontmatter.
+    pub bibliography: Option<PathBuf>,
+    /// Code block languages to use instead of others, like `vim` for `viml`.
+    pub lang_aliases: Vec<(String, String)>,
+    /// Guess the language of code blocks without one.
+    pub detect_lang: bool,
+    /// Mark blocks with the source line they start at, for scroll sync.
+    pub source_lines: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            smart_punctuation: true,
+            footnotes: true,
+            tables: true,
+            tasklists: true,
+            gfm: true,
+            math: true,
+            sub_sup: false,
+            breaks: false,
+            mermaid: true,
+            dot_command: String::from("dot -Tsvg"),
+            plantuml_command: String::from("plantuml -tsvg -pipe"),
+            plantuml_server: None,
+            wikilink_root: None,
+            server_math: false,
+            links: None,
+            number_headings: false,
+            editable_tasks: false,
+            line_numbers: false,
+            sanitize: None,
+            bibliography: None,
+            lang_aliases: Vec::new(),
+            detect_lang: false,
+            source_lines: false,
+        }
+    }
+}
+
+/// Heading of a rendered document.
+pub struct Heading {
+    pub level: u8,
+    /// Anchor of the heading.
+    pub id: String,
+    pub text: String,
+}
+
+/// Markdown document rendered to HTML.
+pub struct Document {
+    pub html: String,
+    /// Frontmatter fields, in order of appearance.
+    pub metadata: Vec<(String, String)>,
+    /// Headings in document order.
+    pub headings: Vec<Heading>,
+    /// Words of prose, leaving out code blocks and frontmatter.
+    pub words: usize,
+}
+
+impl Document {
+    /// Get the `title` field of the frontmatter.
+    pub fn title(&self) -> Option<&str> {
+        self.metadata
+            .iter()
+            .find(|(key, _)| key == "title")
+            .map(|(_, value)| value.as_str())
+            .filter(|title| !title.is_empty())
+    }
+
+    /// Estimated minutes to read the document, at 200 words per minute.
+    pub fn reading_minutes(&self) -> usize {
+        self.words.div_ceil(200).max(1)
+    }
+}
+
+/// Counts words across text events, which can split a word in several.
+#[derive(Default)]
+struct WordCount {
+    words: usize,
+    in_word: bool,
+}
+
+impl WordCount {
+    fn add(&mut self, text: &str) {
+        for c in text.chars() {
+            if c.is_whitespace() {
+                self.in_word = false;
+            } else if !self.in_word {
+                self.in_word = true;
+                self.words += 1;
+            }
+        }
+    }
+
+    fn split(&mut self) {
+        self.in_word = false;
+    }
+}
+
+/// Returns true for combining marks, which GitHub keeps in anchors.
+///
+/// Only covers the common blocks and the Indic viramas, which are not alphabetic.
+fn is_mark(c: char) -> bool {
+    matches!(c as u32,
+        0x0300..=0x036F | 0x1AB0..=0x1AFF | 0x1DC0..=0x1DFF | 0x20D0..=0x20FF
+        | 0xFE00..=0xFE0F | 0xFE20..=0xFE2F)
+        || (('\u{0900}'..='\u{0DFF}').contains(&c) && c as u32 % 0x80 == 0x4D)
+}
+
+/// Make an anchor from heading text like GitHub: lowercase the text, drop
+/// punctuation and symbols, and turn spaces into dashes.
+pub fn slugify(text: &str) -> String {
+    text.to_lowercase()
         .chars()
-        .filter(|c| c.is_alphanumeric() || *c == ' ' || *c == '-')
+        .filter(|&c| c.is_alphanumeric() || is_mark(c) || matches!(c, ' ' | '-' | '_'))
         .map(|c| if c == ' ' { '-' } else { c })
         .collect()
 }
 
-pub fn to_html(md: &str) -> String {
-    use pulldown_cmark::{Options, Parser};
+/// Generates heading anchors, making them unique within a document.
+#[derive(Default)]
+struct Slugger {
+    /// Number of times each anchor was generated, by anchor without suffix.
+    occurrences: HashMap<String, usize>,
+}
 
-    let mut options = Options::empty();
-    options.insert(Options::ENABLE_STRIKETHROUGH);
-    options.insert(Options::ENABLE_TABLES);
-    options.insert(Options::ENABLE_FOOTNOTES);
-    options.insert(Options::ENABLE_STRIKETHROUGH);
-    options.insert(Options::ENABLE_TASKLISTS);
-    options.insert(Options::ENABLE_SMART_PUNCTUATION);
-    options.insert(Options::ENABLE_MATH);
-    options.insert(Options::ENABLE_GFM);
-    options.insert(Options::ENABLE_MATH);
+impl Slugger {
+    /// Get anchor for heading text, appending `-1`, `-2`, ... to duplicates like GitHub.
+    fn slug(&mut self, text: &str) -> String {
+        let base = slugify(text);
+        let mut slug = base.clone();
+        while self.occurrences.contains_key(&slug) {
+            let count = self.occurrences.get_mut(&base).unwrap();
+            *count += 1;
+            slug = format!("{}-{}", base, count);
+        }
+        self.occurrences.insert(slug.clone(), 0);
+        slug
+    }
+}
 
-    let parser = Parser::new_ext(md, options);
+fn anchor_html(anchor: &str) -> String {
+    format!(
+        r##"<a id="{anchor}" class="anchor" href="#{anchor}">
+<svg class="octicon octicon-link" viewBox="0 0 16 16" version="1.1" width="16" height="16" aria-hidden="true"><path d="m7.775 3.275 1.25-1.25a3.5 3.5 0 1 1 4.95 4.95l-2.5 2.5a3.5 3.5 0 0 1-4.95 0 .751.751 0 0 1 .018-1.042.751.751 0 0 1 1.042-.018 1.998 1.998 0 0 0 2.83 0l2.5-2.5a2.002 2.002 0 0 0-2.83-2.83l-1.25 1.25a.751.751 0 0 1-1.042-.018.751.751 0 0 1-.018-1.042Zm-4.69 9.64a1.998 1.998 0 0 0 2.83 0l1.25-1.25a.751.751 0 0 1 1.042.018.751.751 0 0 1 .018 1.042l-1.25 1.25a3.5 3.5 0 1 1-4.95-4.95l2.5-2.5a3.5 3.5 0 0 1 4.95 0 .751.751 0 0 1-.018 1.042.751.751 0 0 1-1.042.018 1.998 1.998 0 0 0-2.83 0l-2.5 2.5a1.998 1.998 0 0 0 0 2.83Z"></path></svg>
+</a>"##
+    )
+}
 
-    let mut inside_heading_level = false;
+/// Give each heading an anchor made from all of its text, collecting the headings.
+///
+/// Heading contents are buffered, since text can be split by emphasis or code.
+fn anchor_headings<'a>(
+    events: impl Iterator<Item = Event<'a>>,
+    headings: &mut Vec<Heading>,
+) -> Vec<Event<'a>> {
+    let mut slugger = Slugger::default();
+    let mut out = Vec::new();
+    let mut start = None;
+    let mut content = Vec::new();
+    for event in events {
+        match event {
+            Event::Start(Tag::Heading { .. }) => start = Some(event),
+            Event::End(TagEnd::Heading(_)) => {
+                let Some(Event::Start(Tag::Heading {
+                    level,
+                    id,
+                    classes,
+                    attrs,
+                })) = start.take()
+                else {
+                    out.push(event);
+                    continue;
+                };
+                let text: String = content
+                    .iter()
+                    .filter_map(|event| match event {
+                        Event::Text(text) | Event::Code(text) => Some(text.as_ref()),
+                        _ => None,
+                    })
+                    .collect();
+                let anchor = match &id {
+                    Some(id) => id.to_string(),
+                    None => slugger.slug(&text),
+                };
+                out.push(Event::Start(Tag::Heading {
+                    level,
+                    id,
+                    classes,
+                    attrs,
+                }));
+                out.push(Event::Html(anchor_html(&anchor).into()));
+                out.append(&mut content);
+                out.push(event);
+                headings.push(Heading {
+                    level: level as u8,
+                    id: anchor,
+                    text,
+                });
+            }
+            event if start.is_some() => content.push(event),
+            event => out.push(event),
+        }
+    }
+    out
+}
 
-    let parser = parser.map(|event| match event {
-        Event::Start(Tag::Heading { level, id, classes, attrs }) => {
-            inside_heading_level = true;
-            Event::Start(Tag::Heading { level, id, classes, attrs })
+/// Turn `~sub~` and `^sup^` into subscripts and superscripts, like pandoc.
+///
+/// Single tildes are parsed as strikethrough unless they are inside a word,
+/// so they are told apart from `~~double~~` ones by looking at the source.
+fn sub_sup<'a>(
+    events: impl Iterator<Item = (Event<'a>, Range<usize>)>,
+    md: &str,
+) -> Vec<Event<'a>> {
+    let mut out = Vec::new();
+    let mut subscripts = Vec::new();
+    let mut in_code = false;
+    // Text is split around punctuation, so scripts are found in merged text.
+    let mut text = String::new();
+    for (event, range) in events {
+        if let Event::Text(t) = &event {
+            if !in_code {
+                text.push_str(t);
+                continue;
+            }
         }
-        Event::End(TagEnd::Heading(level)) => {
-            inside_heading_level = false;
-            Event::End(TagEnd::Heading(level))
+        push_scripts(&mut out, &mut text);
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => {
+                in_code = true;
+                out.push(event);
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                in_code = false;
+                out.push(event);
+            }
+            Event::Start(Tag::Strikethrough) => {
+                let single = !md[range].starts_with("~~");
+                subscripts.push(single);
+                out.push(match single {
+                    true => Event::InlineHtml("<sub>".into()),
+                    false => event,
+                });
+            }
+            Event::End(TagEnd::Strikethrough) => out.push(match subscripts.pop() {
+                Some(true) => Event::InlineHtml("</sub>".into()),
+                _ => event,
+            }),
+            event => out.push(event),
         }
-        Event::Text(text) => {
-            if inside_heading_level {
-                let anchor = to_tag_anchor(&text);
-                Event::Html(CowStr::from(format!(r##"<a id="{anchor}" class="anchor" href="#{anchor}">
-<svg class="octicon octicon-link" viewBox="0 0 16 16" version="1.1" width="16" height="16" aria-hidden="true"><path d="m7.775 3.275 1.25-1.25a3.5 3.5 0 1 1 4.95 4.95l-2.5 2.5a3.5 3.5 0 0 1-4.95 0 .751.751 0 0 1 .018-1.042.751.751 0 0 1 1.042-.018 1.998 1.998 0 0 0 2.83 0l2.5-2.5a2.002 2.002 0 0 0-2.83-2.83l-1.25 1.25a.751.751 0 0 1-1.042-.018.751.751 0 0 1-.018-1.042Zm-4.69 9.64a1.998 1.998 0 0 0 2.83 0l1.25-1.25a.751.751 0 0 1 1.042.018.751.751 0 0 1 .018 1.042l-1.25 1.25a3.5 3.5 0 1 1-4.95-4.95l2.5-2.5a3.5 3.5 0 0 1 4.95 0 .751.751 0 0 1-.018 1.042.751.751 0 0 1-1.042.018 1.998 1.998 0 0 0-2.83 0l-2.5 2.5a1.998 1.998 0 0 0 0 2.83Z"></path></svg>
-</a>{text}"##)))
-            } else {
-                Event::Text(text)
+    }
+    push_scripts(&mut out, &mut text);
+    out
+}
+
+/// Push `text` with `^sup^` and `~sub~` spans, which cannot contain spaces,
+/// and clear it.
+fn push_scripts(out: &mut Vec<Event>, text: &mut String) {
+    if text.is_empty() {
+        return;
+    }
+    let mut rest = text.as_str();
+    while let Some(start) = rest.find(['^', '~']) {
+        let delimiter = rest[start..].chars().next().unwrap();
+        let inner = &rest[start + 1..];
+        let Some(len) = inner.find(delimiter).filter(|&len| len > 0) else {
+            out.push(Event::Text(rest[..start + 1].to_string().into()));
+            rest = inner;
+            continue;
+        };
+        if inner[..len].contains(char::is_whitespace) {
+            out.push(Event::Text(rest[..start + 1].to_string().into()));
+            rest = inner;
+            continue;
+        }
+        if start > 0 {
+            out.push(Event::Text(rest[..start].to_string().into()));
+        }
+        let tag = if delimiter == '^' { "sup" } else { "sub" };
+        out.push(Event::InlineHtml(format!("<{}>", tag).into()));
+        out.push(Event::Text(inner[..len].to_string().into()));
+        out.push(Event::InlineHtml(format!("</{}>", tag).into()));
+        rest = &inner[len + 1..];
+    }
+    if !rest.is_empty() {
+        out.push(Event::Text(rest.to_string().into()));
+    }
+    text.clear();
+}
+
+/// Prefix headings with hierarchical section numbers, counting from the
+/// highest heading level in the document.
+fn number_headings<'a>(events: Vec<Event<'a>>, headings: &mut [Heading]) -> Vec<Event<'a>> {
+    let Some(top) = headings.iter().map(|h| h.level).min() else {
+        return events;
+    };
+    let mut counters = [0usize; 6];
+    let mut numbers = Vec::with_capacity(headings.len());
+    for heading in headings.iter_mut() {
+        let depth = usize::from(heading.level - top);
+        counters[depth] += 1;
+        counters[depth + 1..].fill(0);
+        let number: String = counters[..=depth]
+            .iter()
+            .map(|n| format!("{}.", n))
+            .collect();
+        heading.text = format!("{} {}", number, heading.text);
+        numbers.push(number);
+    }
+
+    let mut numbers = numbers.into_iter();
+    let mut out = Vec::with_capacity(events.len() + headings.len());
+    let mut events = events.into_iter();
+    while let Some(event) = events.next() {
+        let heading = matches!(event, Event::Start(Tag::Heading { .. }));
+        out.push(event);
+        if heading {
+            // The anchor comes first.
+            out.extend(events.next());
+            if let Some(number) = numbers.next() {
+                let html = format!("<span class=\"heading-number\">{}</span> ", number);
+                out.push(Event::InlineHtml(html.into()));
+            }
+        }
+    }
+    out
+}
+
+/// Returns `video` or `audio` if the URL points to a media file.
+fn media_element(url: &str) -> Option<&'static str> {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let ext = path.rsplit_once('.')?.1.to_ascii_lowercase();
+    match ext.as_str() {
+        "mp4" | "m4v" | "webm" | "ogv" | "mov" => Some("video"),
+        "mp3" | "m4a" | "ogg" | "oga" | "opus" | "wav" | "flac" => Some("audio"),
+        _ => None,
+    }
+}
+
+fn media_start_tag(element: &str, url: &str, title: &str) -> String {
+    let mut html = format!("<{} controls preload=\"metadata\" src=\"", element);
+    _ = escape_href(&mut html, url);
+    html.push('"');
+    if !title.is_empty() {
+        html.push_str(" title=\"");
+        _ = escape_html(&mut html, title);
+        html.push('"');
+    }
+    html.push('>');
+    html
+}
+
+pub fn to_html(md: &str, options: &Options) -> String {
+    render(md, options).html
+}
+
+/// Render headings as a nested list of links to their anchors.
+pub fn toc_html(headings: &[Heading]) -> String {
+    if headings.is_empty() {
+        return String::new();
+    }
+    let mut html = String::new();
+    // Levels of the currently open lists.
+    let mut open = Vec::<u8>::new();
+    for heading in headings {
+        while open.last().is_some_and(|&level| level > heading.level) {
+            open.pop();
+            html.push_str("</li></ul>");
+        }
+        if open.last() == Some(&heading.level) {
+            html.push_str("</li>");
+        } else {
+            open.push(heading.level);
+            html.push_str("<ul>");
+        }
+        html.push_str("<li><a href=\"#");
+        _ = escape_href(&mut html, &heading.id);
+        html.push_str("\">");
+        _ = escape_html(&mut html, &heading.text);
+        html.push_str("</a>");
+    }
+    for _ in open {
+        html.push_str("</li></ul>");
+    }
+    html
+}
+
+pub fn parser_options(options: &Options) -> pulldown_cmark::Options {
+    use pulldown_cmark::Options as ParserOptions;
+
+    let mut parser_options = ParserOptions::empty();
+    parser_options.insert(ParserOptions::ENABLE_STRIKETHROUGH);
+    parser_options.insert(ParserOptions::ENABLE_DEFINITION_LIST);
+    parser_options.insert(ParserOptions::ENABLE_YAML_STYLE_METADATA_BLOCKS);
+    parser_options.insert(ParserOptions::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS);
+    parser_options.set(ParserOptions::ENABLE_TABLES, options.tables);
+    parser_options.set(ParserOptions::ENABLE_FOOTNOTES, options.footnotes);
+    parser_options.set(ParserOptions::ENABLE_TASKLISTS, options.tasklists);
+    parser_options.set(
+        ParserOptions::ENABLE_SMART_PUNCTUATION,
+        options.smart_punctuation,
+    );
+    parser_options.set(ParserOptions::ENABLE_MATH, options.math);
+    parser_options.set(ParserOptions::ENABLE_GFM, options.gfm);
+    parser_options
+}
+
+/// Expand containers and take out abbreviation definitions, as is done before
+/// rendering, with a function from offsets in the result back to `md`.
+fn preprocess(md: &str) -> (String, abbr::Abbreviations, impl Fn(usize) -> usize) {
+    let (md, containers) = container::expand(md);
+    let (md, abbreviations, definitions) = abbr::definitions(&md);
+    let source = move |offset| containers.source(definitions.source(offset));
+    (md.into_owned(), abbreviations, source)
+}
+
+/// Byte ranges of the `[ ]` and `[x]` task list markers in `md`, in the order
+/// they are rendered.
+pub fn task_markers(md: &str, options: &Options) -> Vec<Range<usize>> {
+    let (md, _, source) = preprocess(md);
+    Parser::new_ext(&md, parser_options(options))
+        .into_offset_iter()
+        .filter(|(event, _)| matches!(event, Event::TaskListMarker(_)))
+        .map(|(_, range)| source(range.start)..source(range.start) + range.len())
+        .collect()
+}
+
+/// Frontmatter fields of `md`, which can only be at its start.
+pub fn frontmatter_fields(md: &str, options: &Options) -> Vec<(String, String)> {
+    let mut events = Parser::new_ext(md, parser_options(options));
+    let Some(Event::Start(Tag::MetadataBlock(kind))) = events.next() else {
+        return Vec::new();
+    };
+    match (kind, events.next()) {
+        (MetadataBlockKind::PlusesStyle, Some(Event::Text(text))) => frontmatter::parse_toml(&text),
+        (_, Some(Event::Text(text))) => frontmatter::parse_yaml(&text),
+        _ => Vec::new(),
+    }
+}
+
+/// Whether `tag` starts a block marked with its source line.
+fn is_marked_block(tag: &Tag) -> bool {
+    matches!(
+        tag,
+        Tag::Paragraph | Tag::Heading { .. } | Tag::Item | Tag::CodeBlock(_) | Tag::Table(_)
+    )
+}
+
+/// Lines of `original` that the marked blocks of `md`, made from it by
+/// [`preprocess`], start at, in document order. `source` maps offsets in `md`
+/// back to `original`.
+fn block_lines(
+    md: &str,
+    original: &str,
+    source: impl Fn(usize) -> usize,
+    options: &Options,
+) -> Vec<usize> {
+    let mut line = 1;
+    let mut counted = 0;
+    Parser::new_ext(md, parser_options(options))
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            Event::Start(tag) if is_marked_block(&tag) => {
+                let start = source(range.start).max(counted);
+                line += original.as_bytes()[counted..start]
+                    .iter()
+                    .filter(|&&b| b == b'\n')
+                    .count();
+                counted = start;
+                Some(line)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether the paragraph whose events follow its start is only a `[TOC]` marker.
+fn is_toc_paragraph(events: &[Event]) -> bool {
+    let text: Option<String> = events
+        .iter()
+        .take_while(|event| !matches!(event, Event::End(TagEnd::Paragraph)))
+        .map(|event| match event {
+            Event::Text(text) => Some(text.as_ref()),
+            _ => None,
+        })
+        .collect();
+    matches!(text.as_deref(), Some("[TOC]" | "[toc]"))
+}
+
+/// Mark blocks with a `data-source-line` element, with the lines of [`block_lines`].
+///
+/// Paragraphs, headings and list items get an empty `span` inside, so their
+/// layout doesn't change; code blocks and tables, whose markup is generated,
+/// get a hidden `div` just before. `[TOC]` paragraphs are left as they are, so
+/// they are still found and replaced.
+fn mark_source_lines<'a>(
+    events: impl Iterator<Item = Event<'a>>,
+    lines: Vec<usize>,
+) -> Vec<Event<'a>> {
+    let events: Vec<_> = events.collect();
+    let mut lines = lines.into_iter();
+    let mut out = Vec::new();
+    for (i, event) in events.iter().enumerate() {
+        let event = event.clone();
+        let Event::Start(tag) = &event else {
+            out.push(event);
+            continue;
+        };
+        let Some(line) = is_marked_block(tag).then(|| lines.next()).flatten() else {
+            out.push(event);
+            continue;
+        };
+        if matches!(tag, Tag::Paragraph) && is_toc_paragraph(&events[i + 1..]) {
+            out.push(event);
+            continue;
+        }
+        match tag {
+            Tag::CodeBlock(_) | Tag::Table(_) => {
+                let marker = format!("<div data-source-line=\"{}\" hidden></div>\n", line);
+                out.push(Event::Html(marker.into()));
+                out.push(event);
+            }
+            _ => {
+                let marker = format!("<span data-source-line=\"{}\"></span>", line);
+                out.push(event);
+                out.push(Event::InlineHtml(marker.into()));
             }
         }
-        _ => event,
+    }
+    out
+}
+
+/// Render markdown, collecting its frontmatter.
+pub fn render(original: &str, options: &Options) -> Document {
+    let (md, abbreviations, source) = preprocess(original);
+    let parser = Parser::new_ext(&md, parser_options(options));
+    let parser: Box<dyn Iterator<Item = Event>> = if options.sub_sup {
+        Box::new(sub_sup(parser.into_offset_iter(), &md).into_iter())
+    } else {
+        Box::new(parser)
+    };
+    let parser: Box<dyn Iterator<Item = Event>> = match &options.sanitize {
+        Some(policy) => Box::new(sanitize::events(parser, policy)),
+        None => parser,
+    };
+    let parser: Box<dyn Iterator<Item = Event>> = match &options.wikilink_root {
+        Some(root) => Box::new(wikilink::expand(parser, root).into_iter()),
+        None => parser,
+    };
+    let bibliography = citation::find(
+        &frontmatter_fields(&md, options),
+        options.bibliography.as_deref(),
+        options.links.as_ref(),
+    );
+    let parser: Box<dyn Iterator<Item = Event>> = match &bibliography {
+        Some(bibliography) => Box::new(citation::expand(parser, bibliography).into_iter()),
+        None => parser,
+    };
+    let parser = spoiler::expand(parser);
+    let parser = if options.source_lines {
+        mark_source_lines(
+            parser.into_iter(),
+            block_lines(&md, original, source, options),
+        )
+    } else {
+        parser
+    };
+    let mut headings = Vec::<Heading>::new();
+    let parser = anchor_headings(parser.into_iter(), &mut headings);
+    let parser = if options.number_headings {
+        number_headings(parser, &mut headings)
+    } else {
+        parser
+    };
+
+    // Elements that replaced currently open images, to close them properly.
+    let mut images = Vec::<Option<&str>>::new();
+    let mut metadata_kind = None;
+    let mut metadata = Vec::new();
+    let mut inside_mermaid = false;
+    // Info string and source of the code block being read.
+    let mut code_block = Option::<(CowStr, String)>::None;
+    // Index of the next task list marker, for editable tasks.
+    let mut tasks = 0;
+    let mut words = WordCount::default();
+
+    let parser = parser.into_iter().map(|event| match event {
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => {
+            let element = media_element(&dest_url);
+            images.push(element);
+            match element {
+                Some(element) => Event::Html(media_start_tag(element, &dest_url, &title).into()),
+                None => Event::Start(Tag::Image {
+                    link_type,
+                    dest_url,
+                    title,
+                    id,
+                }),
+            }
+        }
+        Event::End(TagEnd::Image) => match images.pop().flatten() {
+            Some(element) => Event::Html(format!("</{}>", element).into()),
+            None => Event::End(TagEnd::Image),
+        },
+        Event::Start(Tag::Link { ref dest_url, .. })
+            if options
+                .links
+                .as_ref()
+                .is_some_and(|base| base.is_broken(dest_url)) =>
+        {
+            let mut html = String::from("<a class=\"broken-link\" href=\"");
+            _ = escape_href(&mut html, dest_url);
+            html.push_str("\" title=\"Broken link: file not found\">");
+            Event::InlineHtml(html.into())
+        }
+        Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info)))
+            if options.mermaid && fence::Info::parse(&info).language == Some("mermaid") =>
+        {
+            inside_mermaid = true;
+            Event::Html("<pre class=\"mermaid\">".into())
+        }
+        Event::Start(Tag::CodeBlock(kind)) => {
+            let info = match kind {
+                CodeBlockKind::Fenced(info) => info,
+                CodeBlockKind::Indented => "".into(),
+            };
+            code_block = Some((info, String::new()));
+            Event::Html("".into())
+        }
+        Event::Text(text) if code_block.is_some() => {
+            if let Some((_, source)) = &mut code_block {
+                source.push_str(&text);
+            }
+            Event::Html("".into())
+        }
+        Event::End(TagEnd::CodeBlock) if code_block.is_some() => {
+            let (info, source) = code_block.take().unwrap();
+            let mut info = fence::resolve_alias(&info, &options.lang_aliases);
+            if options.detect_lang && fence::Info::parse(&info).language.is_none() {
+                if let Some(language) = detect::language(&source) {
+                    info = format!("{} {}", language, info).into();
+                }
+            }
+            let html = match fence::Info::parse(&info).language {
+                Some("dot" | "graphviz") => diagram::graphviz(&options.dot_command, &source),
+                Some("plantuml" | "puml") => match &options.plantuml_server {
+                    Some(server) => diagram::plantuml_image(server, &source),
+                    None => diagram::plantuml(&options.plantuml_command, &source),
+                },
+                _ => fence::to_html(&info, &source, options.line_numbers),
+            };
+            Event::Html(html.into())
+        }
+        Event::Text(text) if inside_mermaid => {
+            let mut html = String::new();
+            _ = escape_html(&mut html, &text);
+            Event::Html(html.into())
+        }
+        Event::End(TagEnd::CodeBlock) if inside_mermaid => {
+            inside_mermaid = false;
+            Event::Html("</pre>\n".into())
+        }
+        Event::TaskListMarker(checked) if options.editable_tasks => {
+            let html = format!(
+                "<input type=\"checkbox\" class=\"task-toggle\" data-task=\"{}\"{}> ",
+                tasks,
+                if checked { " checked" } else { "" }
+            );
+            tasks += 1;
+            Event::InlineHtml(html.into())
+        }
+        Event::SoftBreak if options.breaks => Event::HardBreak,
+        Event::InlineMath(tex) if options.server_math => {
+            Event::InlineHtml(mathml::to_mathml(&tex, false).into())
+        }
+        Event::DisplayMath(tex) if options.server_math => {
+            Event::InlineHtml(mathml::to_mathml(&tex, true).into())
+        }
+        Event::Start(Tag::MetadataBlock(kind)) => {
+            metadata_kind = Some(kind);
+            event
+        }
+        Event::End(TagEnd::MetadataBlock(_)) => {
+            metadata_kind = None;
+            event
+        }
+        // Metadata text is not written out by the HTML renderer.
+        Event::Text(text) if metadata_kind.is_some() => {
+            metadata.extend(match metadata_kind {
+                Some(MetadataBlockKind::PlusesStyle) => frontmatter::parse_toml(&text),
+                _ => frontmatter::parse_yaml(&text),
+            });
+            Event::Text(text)
+        }
+        Event::Text(ref text) | Event::Code(ref text) => {
+            words.add(text);
+            event
+        }
+        // Inline markup can be in the middle of a word, blocks cannot.
+        Event::Start(Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link { .. })
+        | Event::End(TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough | TagEnd::Link)
+        | Event::InlineHtml(_) => event,
+        _ => {
+            words.split();
+            event
+        }
     });
 
+    let parser = image::attributes(parser);
+    let parser = match abbreviations.is_empty() {
+        true => parser,
+        false => abbr::expand(parser.into_iter(), &abbreviations),
+    };
+    let parser = footnote::collect(parser.into_iter());
+
     let mut html_output = String::new();
-    push_html(&mut html_output, parser);
+    push_html(&mut html_output, parser.into_iter());
+
+    // Markers stand alone in a paragraph or an HTML block, never in code.
+    for marker in ["<p>[TOC]</p>", "<p>[toc]</p>", "<!-- toc -->"] {
+        if html_output.contains(marker) {
+            let toc = format!("<nav class=\"toc\">{}</nav>", toc_html(&headings));
+            html_output = html_output.replace(marker, &toc);
+        }
+    }
+
+    Document {
+        html: frontmatter::to_html(&metadata) + &html_output,
+        metadata,
+        headings,
+        words: words.words,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn anchors(md: &str) -> Vec<String> {
+        render(md, &Options::default())
+            .headings
+            .into_iter()
+            .map(|h| h.id)
+            .collect()
+    }
+
+    #[test]
+    fn duplicate_headings_get_suffixes() {
+        assert_eq!(
+            anchors("# Example\n## Example\n### Example"),
+            ["example", "example-1", "example-2"]
+        );
+    }
+
+    #[test]
+    fn suffixes_skip_taken_anchors() {
+        // Same results as github-slugger.
+        assert_eq!(
+            anchors("# Example 1\n# Example\n# Example\n# Example-1"),
+            ["example-1", "example", "example-2", "example-1-1"]
+        );
+    }
+
+    #[test]
+    fn anchor_ids_in_html_are_unique() {
+        let html = render("# Usage\n# Usage", &Options::default()).html;
+        assert!(html.contains(r#"id="usage""#));
+        assert!(html.contains(r#"id="usage-1""#));
+    }
+
+    #[test]
+    fn slugs_match_github() {
+        let cases = [
+            ("Hello World", "hello-world"),
+            ("What's new?", "whats-new"),
+            ("C++ & Rust", "c--rust"),
+            ("snake_case and kebab-case", "snake_case-and-kebab-case"),
+            ("v1.2.3 (beta)", "v123-beta"),
+            ("Ünïcödé Tïtle", "ünïcödé-tïtle"),
+            ("日本語の見出し", "日本語の見出し"),
+            ("Привет, мир!", "привет-мир"),
+            ("emoji 🎉 party", "emoji--party"),
+            ("  padded  ", "--padded--"),
+        ];
+        for (text, slug) in cases {
+            assert_eq!(slugify(text), slug, "slug of {:?}", text);
+        }
+    }
+
+    #[test]
+    fn anchors_use_all_heading_text() {
+        assert_eq!(
+            anchors("# Hello *big* `world()`\n## **Bold** start"),
+            ["hello-big-world", "bold-start"]
+        );
+    }
+
+    #[test]
+    fn definition_lists() {
+        let html = to_html("Term\n: Definition\n", &Options::default());
+        assert!(html.contains("<dl>"));
+        assert!(html.contains("<dt>Term</dt>"));
+        assert!(html.contains("<dd>Definition</dd>"));
+    }
+
+    #[test]
+    fn headings_are_numbered_from_the_top_level() {
+        let options = Options {
+            number_headings: true,
+            ..Options::default()
+        };
+        let doc = render("## A\n### B\n### C\n## D\n#### E", &options);
+        let texts: Vec<_> = doc.headings.iter().map(|h| h.text.as_str()).collect();
+        assert_eq!(texts, ["1. A", "1.1. B", "1.2. C", "2. D", "2.0.1. E"]);
+        assert_eq!(doc.headings[1].id, "b");
+        assert!(doc
+            .html
+            .contains("<span class=\"heading-number\">1.1.</span> B"));
+    }
+
+    #[test]
+    fn task_markers_match_rendered_checkboxes() {
+        let md = "---\ntitle: x\n---\n- [ ] a\n- [x] b\n\n```\n- [ ] code\n```\n";
+        let markers: Vec<_> = task_markers(md, &Options::default())
+            .into_iter()
+            .map(|r| &md[r])
+            .collect();
+        assert_eq!(markers, ["[ ]", "[x]"]);
+
+        let md = "!!! note\n    - [ ] a\n\n*[B]: Bee\n- [x] b\n";
+        let markers: Vec<_> = task_markers(md, &Options::default())
+            .into_iter()
+            .map(|r| (r.start, &md[r]))
+            .collect();
+        assert_eq!(markers, [(15, "[ ]"), (34, "[x]")]);
+
+        let options = Options {
+            editable_tasks: true,
+            ..Options::default()
+        };
+        let html = to_html(md, &options);
+        assert!(html.contains(r#"data-task="1" checked>"#));
+        assert!(!html.contains(r#"data-task="2""#));
+    }
+
+    #[test]
+    fn extensions_can_be_turned_off() {
+        let md = "\"quoted\" -- text\n\n| a |\n|---|\n| b |\n";
+        assert!(to_html(md, &Options::default()).contains("\u{201c}quoted\u{201d} \u{2013} text"));
+        assert!(to_html(md, &Options::default()).contains("<table>"));
+
+        let options = Options {
+            smart_punctuation: false,
+            tables: false,
+            ..Options::default()
+        };
+        let html = to_html(md, &options);
+        assert!(html.contains("&quot;quoted&quot; -- text") || html.contains("\"quoted\" -- text"));
+        assert!(!html.contains("<table>"));
+    }
+
+    #[test]
+    fn subscripts_and_superscripts() {
+        let options = Options {
+            sub_sup: true,
+            ..Options::default()
+        };
+        let html = to_html("H~2~O, 2^10^, ~~gone~~, ~low~, a ^ b ^ c, `x^2^`", &options);
+        assert!(html.contains(
+            "H<sub>2</sub>O, 2<sup>10</sup>, <del>gone</del>, <sub>low</sub>, a ^ b ^ c, <code>x^2^</code>"
+        ));
+    }
+
+    #[test]
+    fn words_are_counted_outside_code_blocks() {
+        let md = "---\ntitle: Not counted\n---\n# Two words\n\nSome *emph*asis, `code` and [a link](x).\n\n```\nnot counted\n```\n";
+        let doc = render(md, &Options::default());
+        assert_eq!(doc.words, 8);
+        assert_eq!(doc.reading_minutes(), 1);
+    }
+
+    #[test]
+    fn soft_breaks_can_be_line_breaks() {
+        let options = Options {
+            breaks: true,
+            ..Options::default()
+        };
+        assert_eq!(to_html("a\nb\n", &options), "<p>a<br />\nb</p>\n");
+        assert_eq!(to_html("a\nb\n", &Options::default()), "<p>a\nb</p>\n");
+    }
+
+    #[test]
+    fn blocks_are_marked_with_their_source_line() {
+        let options = Options {
+            source_lines: true,
+            ..Options::default()
+        };
+        let html = render("# A\n\ntext\n\n- item\n\n```\ncode\n```\n", &options).html;
+        let lines: Vec<&str> = html
+            .match_indices("data-source-line=\"")
+            .map(|(i, m)| &html[i + m.len()..][..1])
+            .collect();
+        assert_eq!(lines, ["1", "3", "5", "7"]);
+        assert!(html.contains("<p><span data-source-line=\"3\"></span>text</p>"));
 
-    return html_output;
+        let lines = |md| {
+            let html = render(md, &options).html;
+            html.match_indices("data-source-line=\"")
+                .map(|(i, m)| html[i + m.len()..].split('"').next().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert!(render("[TOC]\n\n# A\n", &options)
+            .html
+            .contains("<nav class=\"toc\">"));
+        assert_eq!(lines(":::details More\ninside\n:::\n\nafter\n"), ["2", "5"]);
+        assert_eq!(
+            lines("*[HTML]: Hyper\n\nHTML\n\n!!! note\n    a\n\nb\n"),
+            ["3", "6", "8"]
+        );
+        assert!(!render("text", &Options::default())
+            .html
+            .contains("data-source-line"));
+    }
 }
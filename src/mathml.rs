@@ -0,0 +1,664 @@
+//! Conversion of LaTeX math to MathML, for rendering formulas without KaTeX.
+//!
+//! Covers the commonly used subset: scripts, fractions, roots, Greek letters,
+//! operators and relations, accents, fonts, `\left`/`\right`, and matrix-like
+//! environments. Unknown commands are shown as errors in place.
+
+use pulldown_cmark_escape::escape_html;
+
+fn escape(text: &str) -> String {
+    let mut out = String::new();
+    _ = escape_html(&mut out, text);
+    out
+}
+
+/// MathML element and content of a symbol command like `\alpha` or `\leq`.
+fn symbol(name: &str) -> Option<(&'static str, &'static str)> {
+    let symbol = match name {
+        // Greek letters
+        "alpha" => ("mi", "α"),
+        "beta" => ("mi", "β"),
+        "gamma" => ("mi", "γ"),
+        "delta" => ("mi", "δ"),
+        "epsilon" => ("mi", "ϵ"),
+        "varepsilon" => ("mi", "ε"),
+        "zeta" => ("mi", "ζ"),
+        "eta" => ("mi", "η"),
+        "theta" => ("mi", "θ"),
+        "vartheta" => ("mi", "ϑ"),
+        "iota" => ("mi", "ι"),
+        "kappa" => ("mi", "κ"),
+        "lambda" => ("mi", "λ"),
+        "mu" => ("mi", "μ"),
+        "nu" => ("mi", "ν"),
+        "xi" => ("mi", "ξ"),
+        "pi" => ("mi", "π"),
+        "varpi" => ("mi", "ϖ"),
+        "rho" => ("mi", "ρ"),
+        "varrho" => ("mi", "ϱ"),
+        "sigma" => ("mi", "σ"),
+        "varsigma" => ("mi", "ς"),
+        "tau" => ("mi", "τ"),
+        "upsilon" => ("mi", "υ"),
+        "phi" => ("mi", "ϕ"),
+        "varphi" => ("mi", "φ"),
+        "chi" => ("mi", "χ"),
+        "psi" => ("mi", "ψ"),
+        "omega" => ("mi", "ω"),
+        "Gamma" => ("mi", "Γ"),
+        "Delta" => ("mi", "Δ"),
+        "Theta" => ("mi", "Θ"),
+        "Lambda" => ("mi", "Λ"),
+        "Xi" => ("mi", "Ξ"),
+        "Pi" => ("mi", "Π"),
+        "Sigma" => ("mi", "Σ"),
+        "Upsilon" => ("mi", "Υ"),
+        "Phi" => ("mi", "Φ"),
+        "Psi" => ("mi", "Ψ"),
+        "Omega" => ("mi", "Ω"),
+        // other identifiers
+        "infty" => ("mi", "∞"),
+        "partial" => ("mi", "∂"),
+        "nabla" => ("mi", "∇"),
+        "hbar" => ("mi", "ℏ"),
+        "ell" => ("mi", "ℓ"),
+        "emptyset" | "varnothing" => ("mi", "∅"),
+        "aleph" => ("mi", "ℵ"),
+        // big operators
+        "sum" => ("mo", "∑"),
+        "prod" => ("mo", "∏"),
+        "coprod" => ("mo", "∐"),
+        "int" => ("mo", "∫"),
+        "iint" => ("mo", "∬"),
+        "iiint" => ("mo", "∭"),
+        "oint" => ("mo", "∮"),
+        "bigcup" => ("mo", "⋃"),
+        "bigcap" => ("mo", "⋂"),
+        "bigoplus" => ("mo", "⨁"),
+        "bigotimes" => ("mo", "⨂"),
+        // binary operators
+        "pm" => ("mo", "±"),
+        "mp" => ("mo", "∓"),
+        "times" => ("mo", "×"),
+        "div" => ("mo", "÷"),
+        "cdot" => ("mo", "⋅"),
+        "ast" => ("mo", "∗"),
+        "star" => ("mo", "⋆"),
+        "circ" => ("mo", "∘"),
+        "bullet" => ("mo", "∙"),
+        "oplus" => ("mo", "⊕"),
+        "otimes" => ("mo", "⊗"),
+        "cup" => ("mo", "∪"),
+        "cap" => ("mo", "∩"),
+        "setminus" => ("mo", "∖"),
+        "wedge" | "land" => ("mo", "∧"),
+        "vee" | "lor" => ("mo", "∨"),
+        "neg" | "lnot" => ("mo", "¬"),
+        // relations
+        "leq" | "le" => ("mo", "≤"),
+        "geq" | "ge" => ("mo", "≥"),
+        "neq" | "ne" => ("mo", "≠"),
+        "ll" => ("mo", "≪"),
+        "gg" => ("mo", "≫"),
+        "approx" => ("mo", "≈"),
+        "equiv" => ("mo", "≡"),
+        "sim" => ("mo", "∼"),
+        "simeq" => ("mo", "≃"),
+        "cong" => ("mo", "≅"),
+        "propto" => ("mo", "∝"),
+        "in" => ("mo", "∈"),
+        "notin" => ("mo", "∉"),
+        "ni" => ("mo", "∋"),
+        "subset" => ("mo", "⊂"),
+        "supset" => ("mo", "⊃"),
+        "subseteq" => ("mo", "⊆"),
+        "supseteq" => ("mo", "⊇"),
+        "perp" => ("mo", "⊥"),
+        "parallel" => ("mo", "∥"),
+        "mid" => ("mo", "∣"),
+        "forall" => ("mo", "∀"),
+        "exists" => ("mo", "∃"),
+        // arrows
+        "to" | "rightarrow" => ("mo", "→"),
+        "leftarrow" | "gets" => ("mo", "←"),
+        "leftrightarrow" => ("mo", "↔"),
+        "Rightarrow" | "implies" => ("mo", "⇒"),
+        "Leftarrow" => ("mo", "⇐"),
+        "Leftrightarrow" | "iff" => ("mo", "⇔"),
+        "mapsto" => ("mo", "↦"),
+        "uparrow" => ("mo", "↑"),
+        "downarrow" => ("mo", "↓"),
+        // punctuation and delimiters
+        "ldots" | "dots" => ("mo", "…"),
+        "cdots" => ("mo", "⋯"),
+        "vdots" => ("mo", "⋮"),
+        "ddots" => ("mo", "⋱"),
+        "langle" => ("mo", "⟨"),
+        "rangle" => ("mo", "⟩"),
+        "lfloor" => ("mo", "⌊"),
+        "rfloor" => ("mo", "⌋"),
+        "lceil" => ("mo", "⌈"),
+        "rceil" => ("mo", "⌉"),
+        "{" | "lbrace" => ("mo", "{"),
+        "}" | "rbrace" => ("mo", "}"),
+        "|" => ("mo", "‖"),
+        "vert" => ("mo", "|"),
+        "Vert" => ("mo", "‖"),
+        "prime" => ("mo", "′"),
+        "%" => ("mo", "%"),
+        "$" => ("mo", "$"),
+        "#" => ("mo", "#"),
+        "&" => ("mo", "&amp;"),
+        "_" => ("mo", "_"),
+        _ => return None,
+    };
+    Some(symbol)
+}
+
+/// Functions written upright, like `\sin`.
+const FUNCTIONS: &[&str] = &[
+    "sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan", "sinh", "cosh", "tanh",
+    "log", "ln", "lg", "exp", "lim", "liminf", "limsup", "max", "min", "sup", "inf", "det", "gcd",
+    "deg", "dim", "ker", "arg", "hom", "Pr",
+];
+
+/// Operators which take their limits below and above in display math.
+const LIMIT_OPERATORS: &[&str] = &[
+    "sum",
+    "prod",
+    "coprod",
+    "bigcup",
+    "bigcap",
+    "bigoplus",
+    "bigotimes",
+    "lim",
+    "liminf",
+    "limsup",
+    "max",
+    "min",
+    "sup",
+    "inf",
+    "det",
+    "gcd",
+];
+
+fn accent(name: &str) -> Option<&'static str> {
+    let accent = match name {
+        "hat" | "widehat" => "^",
+        "bar" | "overline" => "‾",
+        "vec" => "→",
+        "dot" => "˙",
+        "ddot" => "¨",
+        "tilde" | "widetilde" => "~",
+        "check" => "ˇ",
+        "breve" => "˘",
+        "acute" => "´",
+        "grave" => "`",
+        "overrightarrow" => "→",
+        "overleftarrow" => "←",
+        _ => return None,
+    };
+    Some(accent)
+}
+
+/// Map a letter to its double-struck, calligraphic, or fraktur form.
+fn styled_letter(style: &str, c: char) -> char {
+    let special = match (style, c) {
+        ("mathbb", 'C') => Some('ℂ'),
+        ("mathbb", 'H') => Some('ℍ'),
+        ("mathbb", 'N') => Some('ℕ'),
+        ("mathbb", 'P') => Some('ℙ'),
+        ("mathbb", 'Q') => Some('ℚ'),
+        ("mathbb", 'R') => Some('ℝ'),
+        ("mathbb", 'Z') => Some('ℤ'),
+        ("mathcal", 'B') => Some('ℬ'),
+        ("mathcal", 'E') => Some('ℰ'),
+        ("mathcal", 'F') => Some('ℱ'),
+        ("mathcal", 'H') => Some('ℋ'),
+        ("mathcal", 'I') => Some('ℐ'),
+        ("mathcal", 'L') => Some('ℒ'),
+        ("mathcal", 'M') => Some('ℳ'),
+        ("mathcal", 'R') => Some('ℛ'),
+        ("mathfrak", 'C') => Some('ℭ'),
+        ("mathfrak", 'H') => Some('ℌ'),
+        ("mathfrak", 'I') => Some('ℑ'),
+        ("mathfrak", 'R') => Some('ℜ'),
+        ("mathfrak", 'Z') => Some('ℨ'),
+        _ => None,
+    };
+    if let Some(c) = special {
+        return c;
+    }
+    // Capital letters of the Mathematical Alphanumeric Symbols block.
+    let base = match style {
+        "mathbb" => 0x1D538,
+        "mathcal" => 0x1D49C,
+        "mathfrak" => 0x1D504,
+        _ => return c,
+    };
+    if c.is_ascii_uppercase() {
+        char::from_u32(base + (c as u32 - 'A' as u32)).unwrap_or(c)
+    } else {
+        c
+    }
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+    display: bool,
+    /// Whether `]` ends the current row, as in the index of `\sqrt[n]{x}`.
+    in_brackets: bool,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.bump();
+        }
+    }
+
+    fn at_command(&self, name: &str) -> bool {
+        self.src[self.pos..]
+            .strip_prefix('\\')
+            .and_then(|rest| rest.strip_prefix(name))
+            .is_some_and(|rest| {
+                !name.starts_with(|c: char| c.is_ascii_alphabetic())
+                    || !rest.starts_with(|c: char| c.is_ascii_alphabetic())
+            })
+    }
+
+    /// Read the name of a command after the backslash.
+    fn command_name(&mut self) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+            self.bump();
+        }
+        if self.pos == start {
+            self.bump();
+        }
+        self.src[start..self.pos].to_string()
+    }
+
+    /// Read the raw contents of a `{...}` group, as for `\text`.
+    fn raw_group(&mut self) -> String {
+        self.skip_whitespace();
+        if self.peek() != Some('{') {
+            return self.bump().map(String::from).unwrap_or_default();
+        }
+        self.bump();
+        let start = self.pos;
+        let mut depth = 0;
+        while let Some(c) = self.peek() {
+            match c {
+                '{' => depth += 1,
+                '}' if depth == 0 => break,
+                '}' => depth -= 1,
+                '\\' => {
+                    self.bump();
+                }
+                _ => {}
+            }
+            self.bump();
+        }
+        let raw = self.src[start..self.pos].to_string();
+        self.bump();
+        raw
+    }
+
+    /// Parse a sequence of atoms up to a closing brace, `&`, `\\`, `\right`, or `\end`.
+    fn row(&mut self) -> String {
+        let mut out = String::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                None | Some('}' | '&') => break,
+                Some(']') if self.in_brackets => break,
+                _ if self.at_command("\\")
+                    || self.at_command("right")
+                    || self.at_command("end") =>
+                {
+                    break
+                }
+                _ => out.push_str(&self.scripted()),
+            }
+        }
+        out
+    }
+
+    /// Parse an atom with its subscript and superscript.
+    fn scripted(&mut self) -> String {
+        let limits = LIMIT_OPERATORS.iter().any(|op| self.at_command(op)) && self.display;
+        let mut base = self.atom();
+        if base.is_empty() {
+            base.push_str("<mrow></mrow>");
+        }
+        let mut sub = None;
+        let mut sup = None;
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some('_') if sub.is_none() => {
+                    self.bump();
+                    sub = Some(self.argument());
+                }
+                Some('^') if sup.is_none() => {
+                    self.bump();
+                    sup = Some(self.argument());
+                }
+                Some('\'') => {
+                    // Primes are superscripts.
+                    self.bump();
+                    let prime = "<mo>′</mo>".to_string();
+                    sup = Some(match sup {
+                        Some(sup) => format!("<mrow>{}{}</mrow>", sup, prime),
+                        None => prime,
+                    });
+                }
+                _ => break,
+            }
+        }
+        let (under, over, both) = if limits {
+            ("munder", "mover", "munderover")
+        } else {
+            ("msub", "msup", "msubsup")
+        };
+        match (sub, sup) {
+            (None, None) => base,
+            (Some(sub), None) => format!("<{under}>{base}{sub}</{under}>"),
+            (None, Some(sup)) => format!("<{over}>{base}{sup}</{over}>"),
+            (Some(sub), Some(sup)) => format!("<{both}>{base}{sub}{sup}</{both}>"),
+        }
+    }
+
+    /// Parse a single argument of a command or script, as one element.
+    fn argument(&mut self) -> String {
+        self.skip_whitespace();
+        if self.peek() == Some('{') {
+            self.atom()
+        } else if self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            // `x^23` is x squared times 3.
+            let c = self.bump().unwrap();
+            format!("<mn>{}</mn>", c)
+        } else {
+            self.atom()
+        }
+    }
+
+    fn atom(&mut self) -> String {
+        self.skip_whitespace();
+        let Some(c) = self.bump() else {
+            return String::new();
+        };
+        match c {
+            '{' => {
+                let inner = self.row();
+                if self.peek() == Some('}') {
+                    self.bump();
+                }
+                format!("<mrow>{}</mrow>", inner)
+            }
+            '\\' => {
+                let name = self.command_name();
+                self.command(&name)
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                let start = self.pos - 1;
+                while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '.') {
+                    self.bump();
+                }
+                format!("<mn>{}</mn>", &self.src[start..self.pos])
+            }
+            c if c.is_alphabetic() => format!("<mi>{}</mi>", c),
+            '-' => "<mo>−</mo>".to_string(),
+            '*' => "<mo>∗</mo>".to_string(),
+            '~' => "<mspace width=\"0.25em\"></mspace>".to_string(),
+            c => format!("<mo>{}</mo>", escape(&c.to_string())),
+        }
+    }
+
+    /// Parse a delimiter after `\left` or `\right`.
+    fn delimiter(&mut self) -> String {
+        self.skip_whitespace();
+        let delimiter = match self.bump() {
+            Some('.') | None => return String::new(),
+            Some('\\') => {
+                let name = self.command_name();
+                match symbol(&name) {
+                    Some((_, content)) => content.to_string(),
+                    None => return String::new(),
+                }
+            }
+            Some(c) => escape(&c.to_string()),
+        };
+        format!("<mo stretchy=\"true\">{}</mo>", delimiter)
+    }
+
+    fn command(&mut self, name: &str) -> String {
+        if let Some((element, content)) = symbol(name) {
+            return format!("<{element}>{content}</{element}>");
+        }
+        if FUNCTIONS.contains(&name) {
+            return format!("<mi>{}</mi>", name);
+        }
+        if let Some(accent) = accent(name) {
+            let base = self.argument();
+            return format!("<mover accent=\"true\">{}<mo>{}</mo></mover>", base, accent);
+        }
+        match name {
+            "frac" | "dfrac" | "tfrac" => {
+                let numerator = self.argument();
+                let denominator = self.argument();
+                format!("<mfrac>{}{}</mfrac>", numerator, denominator)
+            }
+            "binom" => {
+                let n = self.argument();
+                let k = self.argument();
+                format!(
+                    "<mrow><mo>(</mo><mfrac linethickness=\"0\">{}{}</mfrac><mo>)</mo></mrow>",
+                    n, k
+                )
+            }
+            "sqrt" => {
+                self.skip_whitespace();
+                if self.peek() == Some('[') {
+                    self.bump();
+                    let in_brackets = std::mem::replace(&mut self.in_brackets, true);
+                    let index = self.row();
+                    self.in_brackets = in_brackets;
+                    if self.peek() == Some(']') {
+                        self.bump();
+                    }
+                    let base = self.argument();
+                    format!("<mroot>{}<mrow>{}</mrow></mroot>", base, index)
+                } else {
+                    format!("<msqrt>{}</msqrt>", self.argument())
+                }
+            }
+            "underline" => format!("<munder>{}<mo>_</mo></munder>", self.argument()),
+            "text" | "textrm" | "textit" | "textbf" | "mbox" => {
+                format!("<mtext>{}</mtext>", escape(&self.raw_group()))
+            }
+            "operatorname" => format!("<mi>{}</mi>", escape(&self.raw_group())),
+            "mathrm" => format!(
+                "<mi mathvariant=\"normal\">{}</mi>",
+                escape(&self.raw_group())
+            ),
+            "mathbb" | "mathcal" | "mathfrak" => {
+                let letters: String = self
+                    .raw_group()
+                    .chars()
+                    .map(|c| styled_letter(name, c))
+                    .collect();
+                format!("<mi>{}</mi>", escape(&letters))
+            }
+            "mathbf" | "boldsymbol" | "bm" => {
+                format!(
+                    "<mrow style=\"font-weight: bold\">{}</mrow>",
+                    self.argument()
+                )
+            }
+            "mathit" => self.argument(),
+            "left" => {
+                let open = self.delimiter();
+                let inner = self.row();
+                let close = if self.at_command("right") {
+                    self.pos += "\\right".len();
+                    self.delimiter()
+                } else {
+                    String::new()
+                };
+                format!("<mrow>{}{}{}</mrow>", open, inner, close)
+            }
+            "begin" => self.environment(),
+            "," | "thinspace" => "<mspace width=\"0.1667em\"></mspace>".to_string(),
+            ":" | ">" | "medspace" => "<mspace width=\"0.2222em\"></mspace>".to_string(),
+            ";" | "thickspace" => "<mspace width=\"0.2778em\"></mspace>".to_string(),
+            " " => "<mspace width=\"0.25em\"></mspace>".to_string(),
+            "quad" => "<mspace width=\"1em\"></mspace>".to_string(),
+            "qquad" => "<mspace width=\"2em\"></mspace>".to_string(),
+            "!" | "displaystyle" | "textstyle" | "limits" | "nolimits" => String::new(),
+            _ => format!("<merror><mtext>\\{}</mtext></merror>", escape(name)),
+        }
+    }
+
+    /// Parse the body of `\begin{env}` up to the matching `\end{env}`.
+    fn environment(&mut self) -> String {
+        let env = self.raw_group();
+        let env = env.trim_end_matches('*');
+        if env == "array" {
+            // Column specification.
+            self.raw_group();
+        }
+        let mut rows = Vec::new();
+        let mut cells = Vec::new();
+        loop {
+            cells.push(format!("<mtd>{}</mtd>", self.row()));
+            if self.peek() == Some('&') {
+                self.bump();
+            } else if self.at_command("\\") {
+                self.pos += 2;
+                rows.push(format!("<mtr>{}</mtr>", cells.concat()));
+                cells.clear();
+            } else {
+                if self.at_command("end") {
+                    self.pos += "\\end".len();
+                    self.raw_group();
+                } else if self.peek().is_some() {
+                    // Stray closing brace or bracket.
+                    self.bump();
+                    continue;
+                }
+                break;
+            }
+        }
+        if cells.iter().any(|cell| cell != "<mtd></mtd>") {
+            rows.push(format!("<mtr>{}</mtr>", cells.concat()));
+        }
+
+        let align = match env {
+            "cases" => " columnalign=\"left left\"",
+            "aligned" | "align" | "split" => " columnalign=\"right left\"",
+            _ => "",
+        };
+        let table = format!("<mtable{}>{}</mtable>", align, rows.concat());
+        let (open, close) = match env {
+            "pmatrix" => ("(", ")"),
+            "bmatrix" => ("[", "]"),
+            "Bmatrix" => ("{", "}"),
+            "vmatrix" => ("|", "|"),
+            "Vmatrix" => ("‖", "‖"),
+            "cases" => ("{", ""),
+            _ => return table,
+        };
+        let mo = |d: &str| {
+            if d.is_empty() {
+                String::new()
+            } else {
+                format!("<mo stretchy=\"true\">{}</mo>", d)
+            }
+        };
+        format!("<mrow>{}{}{}</mrow>", mo(open), table, mo(close))
+    }
+}
+
+/// Convert LaTeX math to a MathML `<math>` element.
+pub fn to_mathml(tex: &str, display: bool) -> String {
+    let mut parser = Parser {
+        src: tex,
+        pos: 0,
+        display,
+        in_brackets: false,
+    };
+    let mut body = String::new();
+    while parser.pos < tex.len() {
+        body.push_str(&parser.row());
+        // Skip what ended the row at the top level, like an unbalanced brace.
+        if parser.bump() == Some('\\') {
+            parser.command_name();
+        }
+    }
+    format!(
+        "<math xmlns=\"http://www.w3.org/1998/Math/MathML\" display=\"{}\"><semantics><mrow>{}</mrow><annotation encoding=\"application/x-tex\">{}</annotation></semantics></math>",
+        if display { "block" } else { "inline" },
+        body,
+        escape(tex)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(tex: &str) -> String {
+        let math = to_mathml(tex, false);
+        let start = math.find("<semantics><mrow>").unwrap() + "<semantics><mrow>".len();
+        let end = math.find("</mrow><annotation").unwrap();
+        math[start..end].to_string()
+    }
+
+    #[test]
+    fn scripts_and_fractions() {
+        assert_eq!(
+            body("x^2 + y_i"),
+            "<msup><mi>x</mi><mn>2</mn></msup><mo>+</mo><msub><mi>y</mi><mi>i</mi></msub>"
+        );
+        assert_eq!(
+            body(r"\frac{a}{b}"),
+            "<mfrac><mrow><mi>a</mi></mrow><mrow><mi>b</mi></mrow></mfrac>"
+        );
+    }
+
+    #[test]
+    fn commands_and_environments() {
+        assert_eq!(
+            body(r"\alpha \leq \infty"),
+            "<mi>α</mi><mo>≤</mo><mi>∞</mi>"
+        );
+        assert_eq!(
+            body(r"\begin{pmatrix} 1 & 0 \\ 0 & 1 \end{pmatrix}"),
+            "<mrow><mo stretchy=\"true\">(</mo><mtable>\
+             <mtr><mtd><mn>1</mn></mtd><mtd><mn>0</mn></mtd></mtr>\
+             <mtr><mtd><mn>0</mn></mtd><mtd><mn>1</mn></mtd></mtr>\
+             </mtable><mo stretchy=\"true\">)</mo></mrow>"
+        );
+    }
+
+    #[test]
+    fn unknown_commands_are_errors() {
+        assert_eq!(
+            body(r"\nonsense{x}"),
+            "<merror><mtext>\\nonsense</mtext></merror><mrow><mi>x</mi></mrow>"
+        );
+    }
+}
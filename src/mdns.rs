@@ -0,0 +1,393 @@
+//! Minimal mDNS responder that advertises the server as `_http._tcp.local`.
+
+use log::{debug, error, info, warn};
+use std::fs;
+use std::io;
+use std::mem;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::os::fd::FromRawFd;
+use std::thread;
+use std::time::Duration;
+
+const MDNS_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+const SERVICE: &str = "_http._tcp.local";
+const TTL: u32 = 120;
+/// Longest wait before receiving again after an error.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+const CACHE_FLUSH: u16 = 0x8000;
+
+struct Service {
+    instance: String,
+    host: String,
+    ip: Ipv4Addr,
+    port: u16,
+}
+
+/// Start advertising the server on the local network in a background thread.
+pub fn advertise(ip: Ipv4Addr, port: u16) {
+    let ip = if ip.is_unspecified() {
+        match local_ip() {
+            Some(ip) => ip,
+            None => {
+                warn!("mdns: cannot determine local address, not advertising");
+                return;
+            }
+        }
+    } else {
+        ip
+    };
+    if ip.is_loopback() {
+        warn!("mdns: server is bound to loopback, use --host 0.0.0.0 to make it reachable");
+    }
+
+    let name = format!("mdopen-{}", hostname());
+    let service = Service {
+        instance: format!("{}.{}", name, SERVICE),
+        host: format!("{}.local", name),
+        ip,
+        port,
+    };
+
+    let socket = match bind_multicast() {
+        Ok(s) => s,
+        Err(e) => {
+            error!("mdns: cannot bind multicast socket: {}", e);
+            return;
+        }
+    };
+
+    info!("mdns: advertising http://{}:{}", service.host, port);
+
+    thread::spawn(move || respond(socket, service));
+}
+
+fn respond(socket: UdpSocket, service: Service) {
+    let dest = SocketAddr::from((MDNS_ADDR, MDNS_PORT));
+    let announcement = response_packet(&service);
+
+    // RFC 6762 asks for at least two unsolicited announcements one second apart.
+    for _ in 0..2 {
+        if let Err(e) = socket.send_to(&announcement, dest) {
+            error!("mdns: cannot send announcement: {}", e);
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+
+    let mut buf = [0u8; 1500];
+    // Doubled while receiving keeps failing, so the loop doesn't spin.
+    let mut backoff = Duration::from_secs(1);
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(r) => r,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                error!(
+                    "mdns: cannot receive: {}, retrying in {}s",
+                    e,
+                    backoff.as_secs()
+                );
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                continue;
+            }
+        };
+        backoff = Duration::from_secs(1);
+        if !query_matches(&buf[..len], &service) {
+            continue;
+        }
+        debug!("mdns: answering query from {}", from);
+        if let Err(e) = socket.send_to(&announcement, dest) {
+            error!("mdns: cannot send response: {}", e);
+        }
+    }
+}
+
+fn hostname() -> String {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .filter_map(|p| fs::read_to_string(p).ok())
+        .map(|s| s.trim().split('.').next().unwrap_or_default().to_string())
+        .find(|s| !s.is_empty())
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+/// Find the address of the interface with the default route.
+fn local_ip() -> Option<Ipv4Addr> {
+    // Connecting a UDP socket sends no packets but selects the outgoing interface.
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect((Ipv4Addr::new(192, 0, 2, 1), 9)).ok()?;
+    match socket.local_addr().ok()? {
+        SocketAddr::V4(addr) => Some(*addr.ip()),
+        SocketAddr::V6(_) => None,
+    }
+}
+
+/// Bind to the mDNS port, sharing it with any system responder.
+fn bind_multicast() -> io::Result<UdpSocket> {
+    let socket = unsafe {
+        let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Take ownership right away so the descriptor is closed on error.
+        let socket = UdpSocket::from_raw_fd(fd);
+        let on: libc::c_int = 1;
+        for opt in [libc::SO_REUSEADDR, libc::SO_REUSEPORT] {
+            let ret = libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                opt,
+                &on as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            );
+            if ret < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        let mut addr: libc::sockaddr_in = mem::zeroed();
+        addr.sin_family = libc::AF_INET as libc::sa_family_t;
+        addr.sin_port = MDNS_PORT.to_be();
+        addr.sin_addr.s_addr = u32::from(Ipv4Addr::UNSPECIFIED).to_be();
+        let ret = libc::bind(
+            fd,
+            &addr as *const libc::sockaddr_in as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        );
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        socket
+    };
+    socket.join_multicast_v4(&MDNS_ADDR, &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_ttl_v4(255)?;
+    Ok(socket)
+}
+
+/// Returns true if the packet is a query asking about our service.
+fn query_matches(packet: &[u8], service: &Service) -> bool {
+    if packet.len() < 12 || packet[2] & 0x80 != 0 {
+        return false;
+    }
+    let questions = u16::from_be_bytes([packet[4], packet[5]]);
+    let mut pos = 12;
+    for _ in 0..questions {
+        let Some((name, next)) = read_name(packet, pos) else {
+            return false;
+        };
+        if next + 4 > packet.len() {
+            return false;
+        }
+        let qtype = u16::from_be_bytes([packet[next], packet[next + 1]]);
+        pos = next + 4;
+
+        let wanted = match qtype {
+            TYPE_PTR => name.eq_ignore_ascii_case(SERVICE),
+            TYPE_SRV | TYPE_TXT => name.eq_ignore_ascii_case(&service.instance),
+            TYPE_A => name.eq_ignore_ascii_case(&service.host),
+            TYPE_ANY => [SERVICE, &service.instance, &service.host]
+                .iter()
+                .any(|n| name.eq_ignore_ascii_case(n)),
+            _ => false,
+        };
+        if wanted {
+            return true;
+        }
+    }
+    false
+}
+
+/// Read a possibly compressed domain name, returning it with the position after it.
+fn read_name(packet: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // Bound the number of pointer jumps to reject loops.
+    for _ in 0..64 {
+        let len = *packet.get(pos)? as usize;
+        if len == 0 {
+            return Some((labels.join("."), end.unwrap_or(pos + 1)));
+        }
+        if len & 0xc0 == 0xc0 {
+            let ptr = ((len & 0x3f) << 8) | *packet.get(pos + 1)? as usize;
+            end.get_or_insert(pos + 2);
+            pos = ptr;
+            continue;
+        }
+        let label = packet.get(pos + 1..pos + 1 + len)?;
+        labels.push(String::from_utf8_lossy(label).to_string());
+        pos += 1 + len;
+    }
+    None
+}
+
+fn response_packet(service: &Service) -> Vec<u8> {
+    let mut p = Vec::with_capacity(256);
+    // id, flags (response, authoritative), qdcount, ancount, nscount, arcount
+    p.extend_from_slice(&[0, 0, 0x84, 0, 0, 0, 0, 4, 0, 0, 0, 0]);
+
+    let mut rdata = Vec::new();
+    write_name(&mut rdata, &service.instance);
+    write_record(&mut p, SERVICE, TYPE_PTR, CLASS_IN, &rdata);
+
+    let mut rdata = vec![0, 0, 0, 0];
+    rdata.extend_from_slice(&service.port.to_be_bytes());
+    write_name(&mut rdata, &service.host);
+    write_record(
+        &mut p,
+        &service.instance,
+        TYPE_SRV,
+        CLASS_IN | CACHE_FLUSH,
+        &rdata,
+    );
+
+    let txt = "path=/";
+    let mut rdata = vec![txt.len() as u8];
+    rdata.extend_from_slice(txt.as_bytes());
+    write_record(
+        &mut p,
+        &service.instance,
+        TYPE_TXT,
+        CLASS_IN | CACHE_FLUSH,
+        &rdata,
+    );
+
+    write_record(
+        &mut p,
+        &service.host,
+        TYPE_A,
+        CLASS_IN | CACHE_FLUSH,
+        &service.ip.octets(),
+    );
+
+    p
+}
+
+fn write_record(p: &mut Vec<u8>, name: &str, rtype: u16, class: u16, rdata: &[u8]) {
+    write_name(p, name);
+    p.extend_from_slice(&rtype.to_be_bytes());
+    p.extend_from_slice(&class.to_be_bytes());
+    p.extend_from_slice(&TTL.to_be_bytes());
+    p.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    p.extend_from_slice(rdata);
+}
+
+fn write_name(p: &mut Vec<u8>, name: &str) {
+    for label in name.split('.') {
+        p.push(label.len() as u8);
+        p.extend_from_slice(label.as_bytes());
+    }
+    p.push(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service() -> Service {
+        Service {
+            instance: format!("mdopen-box.{}", SERVICE),
+            host: "mdopen-box.local".to_string(),
+            ip: Ipv4Addr::new(192, 168, 1, 2),
+            port: 5032,
+        }
+    }
+
+    /// Query packet asking the questions of `questions`, as names already
+    /// written with [`write_name`] or pointers, and their types.
+    fn query(questions: &[(&[u8], u16)]) -> Vec<u8> {
+        let mut p = vec![0, 0, 0, 0, 0, questions.len() as u8, 0, 0, 0, 0, 0, 0];
+        for (name, qtype) in questions {
+            p.extend_from_slice(name);
+            p.extend_from_slice(&qtype.to_be_bytes());
+            p.extend_from_slice(&CLASS_IN.to_be_bytes());
+        }
+        p
+    }
+
+    fn name(name: &str) -> Vec<u8> {
+        let mut p = Vec::new();
+        write_name(&mut p, name);
+        p
+    }
+
+    #[test]
+    fn response_has_the_service_records() {
+        let service = service();
+        let p = response_packet(&service);
+        assert_eq!(&p[..12], &[0, 0, 0x84, 0, 0, 0, 0, 4, 0, 0, 0, 0]);
+
+        let mut records = Vec::new();
+        let mut pos = 12;
+        for _ in 0..4 {
+            let (name, next) = read_name(&p, pos).unwrap();
+            let rtype = u16::from_be_bytes([p[next], p[next + 1]]);
+            let len = u16::from_be_bytes([p[next + 8], p[next + 9]]) as usize;
+            records.push((name, rtype, p[next + 10..next + 10 + len].to_vec()));
+            pos = next + 10 + len;
+        }
+        assert_eq!(pos, p.len());
+        assert_eq!(records[0].0, SERVICE);
+        assert_eq!(records[0].1, TYPE_PTR);
+        assert_eq!(read_name(&records[0].2, 0).unwrap().0, service.instance);
+        assert_eq!(
+            (records[1].0.as_str(), records[1].1),
+            (service.instance.as_str(), TYPE_SRV)
+        );
+        assert_eq!(&records[1].2[4..6], &5032u16.to_be_bytes());
+        assert_eq!(records[2].2, b"\x06path=/");
+        assert_eq!(
+            (records[3].0.as_str(), records[3].1),
+            ("mdopen-box.local", TYPE_A)
+        );
+        assert_eq!(records[3].2, [192, 168, 1, 2]);
+    }
+
+    #[test]
+    fn queries_for_the_service_match() {
+        let service = service();
+        assert!(query_matches(
+            &query(&[(&name(SERVICE), TYPE_PTR)]),
+            &service
+        ));
+        assert!(query_matches(
+            &query(&[(&name("MDOPEN-BOX.local"), TYPE_A)]),
+            &service
+        ));
+        assert!(!query_matches(
+            &query(&[(&name(SERVICE), TYPE_A)]),
+            &service
+        ));
+        assert!(!query_matches(
+            &query(&[(&name("other.local"), TYPE_ANY)]),
+            &service
+        ));
+        // Responses are not questions.
+        let mut response = query(&[(&name(SERVICE), TYPE_PTR)]);
+        response[2] = 0x84;
+        assert!(!query_matches(&response, &service));
+        assert!(!query_matches(
+            &query(&[(b"\x05_http", TYPE_PTR)]),
+            &service
+        ));
+
+        // The second question ends with a pointer to `local` in the first.
+        let mut host = b"\x0amdopen-box".to_vec();
+        host.extend_from_slice(&[0xc0, 12 + 1 + "_http".len() as u8 + 1 + "_tcp".len() as u8]);
+        let packet = query(&[(&name(SERVICE), TYPE_SRV), (&host, TYPE_A)]);
+        assert_eq!(
+            read_name(&packet, 12 + name(SERVICE).len() + 4),
+            Some(("mdopen-box.local".to_string(), packet.len() - 4))
+        );
+        assert!(query_matches(&packet, &service));
+
+        // Pointers to themselves are rejected.
+        assert_eq!(read_name(&[0xc0, 0], 0), None);
+    }
+}
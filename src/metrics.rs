@@ -0,0 +1,102 @@
+//! Server counters exposed in the Prometheus text format.
+
+use crate::sse;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Number of responses sent, by status code.
+static RESPONSES: Mutex<BTreeMap<u16, u64>> = Mutex::new(BTreeMap::new());
+
+/// Total number of markdown renders.
+static RENDERS: AtomicU64 = AtomicU64::new(0);
+
+/// Total time spent rendering markdown, in microseconds.
+static RENDER_MICROS: AtomicU64 = AtomicU64::new(0);
+
+/// Total number of pages served from the cache.
+static CACHE_HITS: AtomicU64 = AtomicU64::new(0);
+
+/// Count a response with the given status.
+pub fn record_response(status: u16) {
+    let mut responses = RESPONSES.lock().unwrap();
+    *responses.entry(status).or_default() += 1;
+}
+
+/// Run a render function and record how long it took.
+pub fn time_render<T>(f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    record_render(start.elapsed());
+    result
+}
+
+fn record_render(elapsed: Duration) {
+    RENDERS.fetch_add(1, Ordering::Relaxed);
+    RENDER_MICROS.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+}
+
+/// Count a page served from the cache instead of rendered.
+pub fn record_cache_hit() {
+    CACHE_HITS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Render all metrics in the Prometheus text exposition format.
+pub fn render() -> String {
+    let mut out = String::new();
+
+    out.push_str("# HELP mdopen_http_responses_total HTTP responses sent, by status code.\n");
+    out.push_str("# TYPE mdopen_http_responses_total counter\n");
+    for (status, count) in RESPONSES.lock().unwrap().iter() {
+        _ = writeln!(
+            out,
+            "mdopen_http_responses_total{{status=\"{}\"}} {}",
+            status, count
+        );
+    }
+
+    let renders = RENDERS.load(Ordering::Relaxed);
+    let micros = RENDER_MICROS.load(Ordering::Relaxed);
+    out.push_str("# HELP mdopen_render_duration_seconds Time spent rendering markdown.\n");
+    out.push_str("# TYPE mdopen_render_duration_seconds summary\n");
+    _ = writeln!(
+        out,
+        "mdopen_render_duration_seconds_sum {}",
+        micros as f64 / 1e6
+    );
+    _ = writeln!(out, "mdopen_render_duration_seconds_count {}", renders);
+
+    out.push_str(
+        "# HELP mdopen_page_cache_hits_total Pages served without rendering them again.\n",
+    );
+    out.push_str("# TYPE mdopen_page_cache_hits_total counter\n");
+    _ = writeln!(
+        out,
+        "mdopen_page_cache_hits_total {}",
+        CACHE_HITS.load(Ordering::Relaxed)
+    );
+
+    out.push_str("# HELP mdopen_reload_connections Pages connected for live reload.\n");
+    out.push_str("# TYPE mdopen_reload_connections gauge\n");
+    _ = writeln!(out, "mdopen_reload_connections {}", sse::clients());
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_have_a_type_each() {
+        record_response(404);
+        let text = render();
+        assert!(text.contains("mdopen_http_responses_total{status=\"404\"} "));
+        assert!(text.contains("\nmdopen_reload_connections 0\n"));
+        let types = text.lines().filter(|l| l.starts_with("# TYPE ")).count();
+        let helps = text.lines().filter(|l| l.starts_with("# HELP ")).count();
+        assert_eq!((types, helps), (4, 4));
+    }
+}
@@ -0,0 +1,168 @@
+//! Content type detection from file extensions and file contents.
+
+/// Get content type from extension.
+pub fn from_extension(ext: &str) -> Option<&'static str> {
+    let mime = match ext.to_ascii_lowercase().as_str() {
+        // text
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "txt" | "log" => "text/plain; charset=utf-8",
+        "md" | "markdown" => "text/markdown; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "tsv" => "text/tab-separated-values; charset=utf-8",
+        "xml" => "application/xml",
+        "json" | "map" => "application/json",
+        "jsonld" => "application/ld+json",
+        "yaml" | "yml" => "application/yaml",
+        "toml" => "application/toml",
+        "ics" => "text/calendar; charset=utf-8",
+        "vtt" => "text/vtt; charset=utf-8",
+        // images
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "bmp" => "image/bmp",
+        "tif" | "tiff" => "image/tiff",
+        // fonts
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        // audio and video
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "ogv" => "video/ogg",
+        "mov" => "video/quicktime",
+        "mp3" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "ogg" | "oga" => "audio/ogg",
+        "opus" => "audio/opus",
+        "wav" => "audio/wav",
+        "flac" => "audio/flac",
+        // documents and archives
+        "pdf" => "application/pdf",
+        "wasm" => "application/wasm",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "epub" => "application/epub+zip",
+        _ => return None,
+    };
+    Some(mime)
+}
+
+/// Guess content type from the first bytes of a file.
+pub fn sniff(data: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"\0asm", "application/wasm"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"wOFF", "font/woff"),
+        (b"wOF2", "font/woff2"),
+        (b"ID3", "audio/mpeg"),
+        (b"OggS", "audio/ogg"),
+        (b"fLaC", "audio/flac"),
+        (b"\x1a\x45\xdf\xa3", "video/webm"),
+        (b"\0\0\x01\0", "image/x-icon"),
+        (b"BM", "image/bmp"),
+    ];
+
+    if let Some((_, mime)) = SIGNATURES.iter().find(|(sig, _)| data.starts_with(sig)) {
+        return Some(mime);
+    }
+
+    // RIFF and ISO media containers carry the format name at a fixed offset.
+    match (data.get(..4), data.get(8..12)) {
+        (Some(b"RIFF"), Some(b"WEBP")) => return Some("image/webp"),
+        (Some(b"RIFF"), Some(b"WAVE")) => return Some("audio/wav"),
+        _ => {}
+    }
+    if data.get(4..8) == Some(b"ftyp") {
+        return match data.get(8..12) {
+            Some(b"avif" | b"avis") => Some("image/avif"),
+            Some(b"M4A ") => Some("audio/mp4"),
+            Some(b"qt  ") => Some("video/quicktime"),
+            _ => Some("video/mp4"),
+        };
+    }
+
+    let head = &data[..data.len().min(512)];
+    if is_text(head) {
+        let text = String::from_utf8_lossy(head);
+        let text = text.trim_start().to_ascii_lowercase();
+        if text.starts_with("<svg") || (text.starts_with("<?xml") && text.contains("<svg")) {
+            return Some("image/svg+xml");
+        }
+        if text.starts_with("<!doctype html") || text.starts_with("<html") {
+            return Some("text/html; charset=utf-8");
+        }
+        if text.starts_with("<?xml") {
+            return Some("application/xml");
+        }
+        return Some("text/plain; charset=utf-8");
+    }
+
+    None
+}
+
+/// Returns true if the bytes look like UTF-8 text.
+pub fn is_text(data: &[u8]) -> bool {
+    if data.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(data) {
+        Ok(_) => true,
+        // A multibyte character may be cut off at the end of the sample.
+        Err(e) => e.error_len().is_none() && data.len() - e.valid_up_to() < 4,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn types_are_found_by_extension_then_content() {
+        assert_eq!(from_extension("SVG"), Some("image/svg+xml"));
+        assert_eq!(from_extension("woff2"), Some("font/woff2"));
+        assert_eq!(from_extension("exe"), None);
+
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n...."), Some("image/png"));
+        assert_eq!(sniff(b"RIFF\0\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff(b"RIFF\0\0\0\0WAVEfmt "), Some("audio/wav"));
+        assert_eq!(sniff(b"\0\0\0\x1cftypavif"), Some("image/avif"));
+        assert_eq!(sniff(b"\0\0\0\x1cftypisom"), Some("video/mp4"));
+        assert_eq!(
+            sniff(b"  <?xml version=\"1.0\"?>\n<svg xmlns=\"\">"),
+            Some("image/svg+xml")
+        );
+        assert_eq!(
+            sniff(b"<!DOCTYPE html><p>"),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(
+            sniff(b"<?xml version=\"1.0\"?><feed>"),
+            Some("application/xml")
+        );
+        assert_eq!(
+            sniff("plain é".as_bytes()),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(sniff(b"\x01\x02\0\x03"), None);
+        assert_eq!(sniff(b""), Some("text/plain; charset=utf-8"));
+
+        // A character cut off by the sample is still text, invalid bytes are not.
+        assert!(is_text(&"é".as_bytes()[..1]));
+        assert!(!is_text(b"a\xffb"));
+    }
+}
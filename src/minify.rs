@@ -0,0 +1,213 @@
+//! Removal of whitespace and comments from exported pages, for `--minify`.
+//!
+//! Only what can't change how the page looks is removed: whitespace inside
+//! `<pre>`, `<textarea>` and `<script>` is kept, and whitespace between
+//! inline elements is collapsed to a single space rather than dropped.
+
+/// Elements whose surrounding whitespace is never rendered.
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "body",
+    "br",
+    "dd",
+    "details",
+    "div",
+    "dl",
+    "dt",
+    "figcaption",
+    "figure",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hr",
+    "html",
+    "li",
+    "link",
+    "main",
+    "meta",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "script",
+    "section",
+    "style",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+    "ul",
+];
+
+/// Elements whose content is copied as is.
+const RAW_ELEMENTS: &[&str] = &["pre", "textarea", "script"];
+
+/// Name of the tag starting `tag`, like `p` for `</p>` or `<p class="x">`.
+fn tag_name(tag: &str) -> String {
+    tag.trim_start_matches('<')
+        .trim_start_matches('/')
+        .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+fn is_block(tag: Option<&str>) -> bool {
+    tag.is_some_and(|tag| BLOCK_ELEMENTS.contains(&tag_name(tag).as_str()))
+}
+
+/// Collapse runs of whitespace in text to single spaces.
+fn collapse(text: &str, out: &mut String) {
+    let mut space = false;
+    for c in text.chars() {
+        if c.is_ascii_whitespace() {
+            space = true;
+        } else {
+            if space {
+                out.push(' ');
+                space = false;
+            }
+            out.push(c);
+        }
+    }
+    if space {
+        out.push(' ');
+    }
+}
+
+/// Minify an HTML page, including its stylesheets.
+pub fn html(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    let mut last_tag: Option<&str> = None;
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            // Conditional comments are for old browsers, but are content.
+            let end = comment.find("-->").map_or(comment.len(), |end| end + 3);
+            if comment.starts_with("[if") {
+                out.push_str(&rest[..4 + end]);
+            }
+            rest = &comment[end..];
+            continue;
+        }
+        if rest.starts_with('<') {
+            let end = rest.find('>').map_or(rest.len(), |end| end + 1);
+            let tag = &rest[..end];
+            out.push_str(tag);
+            rest = &rest[end..];
+            last_tag = Some(tag);
+            let name = tag_name(tag);
+            if tag.starts_with("</") {
+                continue;
+            }
+            if RAW_ELEMENTS.contains(&name.as_str()) || name == "style" {
+                let close = format!("</{}", name);
+                let end = rest.to_ascii_lowercase().find(&close).unwrap_or(rest.len());
+                match name.as_str() {
+                    "style" => out.push_str(&css(&rest[..end])),
+                    _ => out.push_str(&rest[..end]),
+                }
+                rest = &rest[end..];
+            }
+            continue;
+        }
+        let end = rest.find('<').unwrap_or(rest.len());
+        let text = &rest[..end];
+        rest = &rest[end..];
+        if text.trim().is_empty() {
+            let next_tag =
+                Some(rest).filter(|rest| rest.starts_with("</") || rest.starts_with('<'));
+            if !is_block(last_tag) && !is_block(next_tag) && last_tag.is_some() && !rest.is_empty()
+            {
+                out.push(' ');
+            }
+            continue;
+        }
+        collapse(text, &mut out);
+    }
+    out
+}
+
+/// Minify a stylesheet.
+pub fn css(css: &str) -> String {
+    let mut out = String::with_capacity(css.len());
+    let mut chars = css.chars().peekable();
+    let mut space = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+                space = true;
+            }
+            '"' | '\'' => {
+                if space && !out.is_empty() && !out.ends_with(['{', '}', ';', ',', ':', '>', '(']) {
+                    out.push(' ');
+                }
+                space = false;
+                out.push(c);
+                let mut escaped = false;
+                for s in chars.by_ref() {
+                    out.push(s);
+                    if s == c && !escaped {
+                        break;
+                    }
+                    escaped = s == '\\' && !escaped;
+                }
+            }
+            c if c.is_whitespace() => space = true,
+            '{' | '}' | ';' | ',' | '>' => {
+                if c == '}' && out.ends_with(';') {
+                    out.pop();
+                }
+                out.push(c);
+                space = false;
+            }
+            _ => {
+                if space && !out.is_empty() && !out.ends_with(['{', '}', ';', ',', ':', '>']) {
+                    out.push(' ');
+                }
+                space = false;
+                out.push(c);
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn whitespace_is_removed() {
+        assert_eq!(
+            html("<!DOCTYPE html>\n<html>\n\t<head>\n\t\t<!-- hi -->\n\t\t<style>\n\t.a > b {\n\t\tmargin: 0 auto; /* x */\n\t\twidth: calc(100% - 8px);\n\t}\n\t</style>\n\t</head>\n<body>\n<p>some\n  <em>text</em> <strong>here</strong></p>\n<pre><code>a\n  b\n</code></pre>\n</body>\n</html>\n"),
+            "<!DOCTYPE html><html><head><style>.a>b{margin:0 auto;width:calc(100% - 8px)}</style></head><body><p>some <em>text</em> <strong>here</strong></p><pre><code>a\n  b\n</code></pre></body></html>"
+        );
+        assert_eq!(
+            css("@media (min-width: 1300px) {\n a::before { content: \"a  b\"; }\n}\n"),
+            "@media (min-width:1300px){a::before{content:\"a  b\"}}"
+        );
+    }
+}
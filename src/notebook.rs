@@ -0,0 +1,149 @@
+//! Rendering of Jupyter notebooks.
+
+use crate::json::{self, Value};
+use crate::markdown;
+use pulldown_cmark_escape::escape_html;
+use std::fmt::Write;
+
+/// Notebook text fields are either a string or an array of lines.
+fn text(value: Option<&Value>) -> String {
+    match value {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Array(lines)) => lines.iter().filter_map(Value::as_str).collect(),
+        _ => String::new(),
+    }
+}
+
+/// Remove ANSI escape sequences, which tracebacks are full of.
+fn strip_ansi(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip CSI sequences up to and including the final byte.
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn push_pre(html: &mut String, class: &str, content: &str) {
+    _ = write!(html, "<pre class=\"{}\">", class);
+    _ = escape_html(&mut *html, content);
+    html.push_str("</pre>");
+}
+
+fn push_output(html: &mut String, output: &Value, options: &markdown::Options) {
+    match output.get("output_type").and_then(Value::as_str) {
+        Some("stream") => {
+            let class = match output.get("name").and_then(Value::as_str) {
+                Some("stderr") => "nb-stream nb-stderr",
+                _ => "nb-stream",
+            };
+            push_pre(html, class, &text(output.get("text")));
+        }
+        Some("execute_result" | "display_data") => {
+            let Some(data) = output.get("data") else {
+                return;
+            };
+            if let Some(png) = data.get("image/png").and_then(Value::as_str) {
+                _ = write!(html, "<img src=\"data:image/png;base64,{}\">", png.trim());
+            } else if let Some(jpeg) = data.get("image/jpeg").and_then(Value::as_str) {
+                _ = write!(html, "<img src=\"data:image/jpeg;base64,{}\">", jpeg.trim());
+            } else if data.get("image/svg+xml").is_some() {
+                html.push_str(&text(data.get("image/svg+xml")));
+            } else if data.get("text/html").is_some() {
+                html.push_str(&text(data.get("text/html")));
+            } else if data.get("text/markdown").is_some() {
+                html.push_str(&markdown::to_html(
+                    &text(data.get("text/markdown")),
+                    options,
+                ));
+            } else if data.get("text/plain").is_some() {
+                push_pre(html, "nb-result", &text(data.get("text/plain")));
+            }
+        }
+        Some("error") => {
+            let traceback = match output.get("traceback") {
+                Some(Value::Array(lines)) => lines
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                _ => text(output.get("evalue")),
+            };
+            push_pre(html, "nb-error", &strip_ansi(&traceback));
+        }
+        _ => {}
+    }
+}
+
+/// Render notebook JSON to HTML.
+pub fn to_html(source: &str, options: &markdown::Options) -> Result<String, json::ParseError> {
+    let notebook = json::parse(source)?;
+
+    let metadata = notebook.get("metadata");
+    let language = metadata
+        .and_then(|m| m.get("language_info"))
+        .and_then(|l| l.get("name"))
+        .or_else(|| {
+            metadata
+                .and_then(|m| m.get("kernelspec"))
+                .and_then(|k| k.get("language"))
+        })
+        .and_then(Value::as_str)
+        .unwrap_or("python");
+
+    let mut html = String::new();
+    let Some(Value::Array(cells)) = notebook.get("cells") else {
+        return Ok(html);
+    };
+
+    for cell in cells {
+        let source = text(cell.get("source"));
+        match cell.get("cell_type").and_then(Value::as_str) {
+            Some("markdown") => {
+                html.push_str("<div class=\"nb-cell nb-markdown\">");
+                html.push_str(&markdown::to_html(&source, options));
+                html.push_str("</div>");
+            }
+            Some("code") => {
+                let count = match cell.get("execution_count") {
+                    Some(Value::Number(n)) => n.to_string(),
+                    _ => " ".to_string(),
+                };
+                _ = write!(
+                    html,
+                    "<div class=\"nb-cell nb-code\"><div class=\"nb-prompt\">In [{}]:</div>",
+                    count
+                );
+                html.push_str("<pre><code class=\"language-");
+                _ = escape_html(&mut html, language);
+                html.push_str("\">");
+                _ = escape_html(&mut html, &source);
+                html.push_str("</code></pre>");
+                if let Some(Value::Array(outputs)) = cell.get("outputs") {
+                    if !outputs.is_empty() {
+                        html.push_str("<div class=\"nb-output\">");
+                        for output in outputs {
+                            push_output(&mut html, output, options);
+                        }
+                        html.push_str("</div>");
+                    }
+                }
+                html.push_str("</div>");
+            }
+            _ => push_pre(&mut html, "nb-cell nb-raw", &source),
+        }
+    }
+
+    Ok(html)
+}
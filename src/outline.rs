@@ -0,0 +1,207 @@
+//! Structure of a markdown document as JSON, for `mdopen render --format
+//! json`: its frontmatter, headings tree, links, images and code blocks, so
+//! other tools can lint or index documents the way mdopen parses them.
+
+use crate::json::Value;
+use crate::{fence, markdown};
+use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag, TagEnd};
+
+/// Heading with the headings nested under it.
+struct Section {
+    level: u8,
+    value: Vec<(&'static str, Value)>,
+    children: Vec<Section>,
+}
+
+impl Section {
+    fn into_value(self) -> Value {
+        let children = self.children.into_iter().map(Section::into_value).collect();
+        let mut value = self.value;
+        value.push(("children", Value::Array(children)));
+        Value::object(value)
+    }
+}
+
+/// Nest flat headings under the previous heading of a lower level.
+fn tree(sections: Vec<Section>) -> Vec<Section> {
+    let mut roots = Vec::new();
+    let mut stack: Vec<Section> = Vec::new();
+    for section in sections {
+        while stack.last().is_some_and(|last| last.level >= section.level) {
+            let done = stack.pop().unwrap();
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(done),
+                None => roots.push(done),
+            }
+        }
+        stack.push(section);
+    }
+    while let Some(done) = stack.pop() {
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(done),
+            None => roots.push(done),
+        }
+    }
+    roots
+}
+
+/// What is being collected until its end tag.
+enum Open {
+    Heading(u8, usize, String),
+    Link(String, usize, String),
+    Image(String, usize, String),
+    Code(Option<String>, usize, String),
+}
+
+/// Structure of `md` rendered with `options`.
+pub fn to_json(md: &str, options: &markdown::Options) -> Value {
+    let doc = markdown::render(md, options);
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(md.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    let line = |offset: usize| line_starts.partition_point(|&start| start <= offset);
+
+    let mut headings = Vec::new();
+    let mut links = Vec::new();
+    let mut images = Vec::new();
+    let mut code_blocks = Vec::new();
+    let mut open = Vec::<Open>::new();
+    let parser = Parser::new_ext(md, markdown::parser_options(options)).into_offset_iter();
+    for (event, range) in parser {
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                open.push(Open::Heading(level as u8, line(range.start), String::new()));
+            }
+            Event::Start(Tag::Link { dest_url, .. }) => {
+                open.push(Open::Link(
+                    dest_url.to_string(),
+                    line(range.start),
+                    String::new(),
+                ));
+            }
+            Event::Start(Tag::Image { dest_url, .. }) => {
+                open.push(Open::Image(
+                    dest_url.to_string(),
+                    line(range.start),
+                    String::new(),
+                ));
+            }
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let info = match kind {
+                    CodeBlockKind::Fenced(info) if !info.is_empty() => Some(info.to_string()),
+                    _ => None,
+                };
+                open.push(Open::Code(info, line(range.start), String::new()));
+            }
+            Event::Text(text) | Event::Code(text) => {
+                for item in &mut open {
+                    match item {
+                        Open::Heading(_, _, s)
+                        | Open::Link(_, _, s)
+                        | Open::Image(_, _, s)
+                        | Open::Code(_, _, s) => s.push_str(&text),
+                    }
+                }
+            }
+            Event::SoftBreak | Event::HardBreak => {
+                for item in &mut open {
+                    if let Open::Link(_, _, s) | Open::Image(_, _, s) = item {
+                        s.push(' ');
+                    }
+                }
+            }
+            Event::End(TagEnd::Heading(_) | TagEnd::Link | TagEnd::Image | TagEnd::CodeBlock) => {
+                match open.pop() {
+                    Some(Open::Heading(level, line, text)) => headings.push((level, line, text)),
+                    Some(Open::Link(url, line, text)) => links.push(Value::object([
+                        ("url", url.into()),
+                        ("text", text.into()),
+                        ("line", line.into()),
+                    ])),
+                    Some(Open::Image(url, line, alt)) => images.push(Value::object([
+                        ("url", url.into()),
+                        ("alt", alt.into()),
+                        ("line", line.into()),
+                    ])),
+                    Some(Open::Code(info, line, code)) => {
+                        let language = info.as_deref().and_then(|info| {
+                            let info = fence::resolve_alias(info, &options.lang_aliases);
+                            fence::Info::parse(&info).language.map(str::to_string)
+                        });
+                        code_blocks.push(Value::object([
+                            ("language", language.into()),
+                            ("info", info.into()),
+                            ("line", line.into()),
+                            ("lines", code.lines().count().into()),
+                        ]));
+                    }
+                    None => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Anchors as in the rendered page, when the headings line up.
+    let ids: Vec<Option<&str>> = match doc.headings.len() == headings.len() {
+        true => doc.headings.iter().map(|h| Some(h.id.as_str())).collect(),
+        false => vec![None; headings.len()],
+    };
+    let sections = headings
+        .into_iter()
+        .zip(ids)
+        .map(|((level, line, text), id)| Section {
+            level,
+            value: vec![
+                ("level", (level as usize).into()),
+                ("text", text.into()),
+                ("id", id.into()),
+                ("line", line.into()),
+            ],
+            children: Vec::new(),
+        })
+        .collect();
+    let frontmatter = doc
+        .metadata
+        .iter()
+        .map(|(key, value)| (key.as_str(), Value::from(value.as_str())));
+
+    Value::object([
+        ("title", doc.title().into()),
+        ("frontmatter", Value::object(frontmatter)),
+        ("words", doc.words.into()),
+        (
+            "headings",
+            Value::Array(
+                tree(sections)
+                    .into_iter()
+                    .map(Section::into_value)
+                    .collect(),
+            ),
+        ),
+        ("links", links.into()),
+        ("images", images.into()),
+        ("code_blocks", code_blocks.into()),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn structure_is_collected() {
+        let md = "---\ntitle: Doc\n---\n# A\n\nSee [the *site*](https://x.org) ![logo](l.png)\n\n## B\n\n```rust,no_run\nfn main() {}\n```\n\n# C\n";
+        let json = to_json(md, &markdown::Options::default());
+        assert_eq!(
+            json.to_string(),
+            concat!(
+                r#"{"title":"Doc","frontmatter":{"title":"Doc"},"words":7,"#,
+                r#""headings":[{"level":1,"text":"A","id":"a","line":4,"children":[{"level":2,"text":"B","id":"b","line":8,"children":[]}]},{"level":1,"text":"C","id":"c","line":14,"children":[]}],"#,
+                r#""links":[{"url":"https://x.org","text":"the site","line":6}],"#,
+                r#""images":[{"url":"l.png","alt":"logo","line":6}],"#,
+                r#""code_blocks":[{"language":"rust","info":"rust,no_run","line":10,"lines":1}]}"#
+            )
+        );
+    }
+}
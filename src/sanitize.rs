@@ -0,0 +1,412 @@
+//! Removal of unsafe raw HTML from untrusted markdown.
+//!
+//! Only HTML written in the markdown source is cleaned; markup generated by
+//! mdopen itself is trusted. Disallowed tags are dropped but their text is
+//! kept, except for tags like `script` whose content is dropped too.
+
+use pulldown_cmark::{CowStr, Event, Tag};
+
+/// Tags kept by default, the same as ammonia's defaults.
+const DEFAULT_TAGS: &[&str] = &[
+    "a",
+    "abbr",
+    "acronym",
+    "area",
+    "article",
+    "aside",
+    "b",
+    "bdi",
+    "bdo",
+    "blockquote",
+    "br",
+    "caption",
+    "center",
+    "cite",
+    "code",
+    "col",
+    "colgroup",
+    "data",
+    "dd",
+    "del",
+    "details",
+    "dfn",
+    "div",
+    "dl",
+    "dt",
+    "em",
+    "figcaption",
+    "figure",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hgroup",
+    "hr",
+    "i",
+    "img",
+    "ins",
+    "kbd",
+    "li",
+    "map",
+    "mark",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "q",
+    "rp",
+    "rt",
+    "rtc",
+    "ruby",
+    "s",
+    "samp",
+    "small",
+    "span",
+    "strike",
+    "strong",
+    "sub",
+    "summary",
+    "sup",
+    "table",
+    "tbody",
+    "td",
+    "th",
+    "thead",
+    "time",
+    "tr",
+    "tt",
+    "u",
+    "ul",
+    "var",
+    "wbr",
+];
+
+/// Attributes kept on any allowed tag.
+const ATTRIBUTES: &[&str] = &[
+    "align", "alt", "cite", "colspan", "datetime", "dir", "height", "href", "lang", "open",
+    "rowspan", "src", "start", "title", "width",
+];
+
+/// Tags whose content is dropped along with them.
+const CONTENT_TAGS: &[&str] = &[
+    "script", "style", "noscript", "iframe", "template", "textarea",
+];
+
+/// Which tags survive sanitizing.
+#[derive(Debug, Clone)]
+pub struct Policy {
+    tags: Vec<String>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            tags: DEFAULT_TAGS.iter().map(|tag| tag.to_string()).collect(),
+        }
+    }
+}
+
+impl Policy {
+    /// Allow more tags besides the defaults.
+    pub fn allow(&mut self, tags: impl IntoIterator<Item = String>) {
+        self.tags
+            .extend(tags.into_iter().map(|tag| tag.to_lowercase()));
+    }
+
+    fn allows(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+}
+
+/// Whether a link target is relative or uses a harmless scheme.
+///
+/// Anything that might be an entity-encoded `javascript:` is rejected.
+pub fn is_safe_url(url: &str) -> bool {
+    let url: String = url
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_lowercase();
+    if ["http://", "https://", "mailto:"]
+        .iter()
+        .any(|scheme| url.starts_with(scheme))
+    {
+        return true;
+    }
+    let scheme_part = url.split(['/', '?', '#']).next().unwrap_or_default();
+    !scheme_part.contains([':', '&'])
+}
+
+/// Clean raw HTML and link targets in markdown events.
+pub fn events<'a>(
+    events: impl Iterator<Item = Event<'a>>,
+    policy: &Policy,
+) -> impl Iterator<Item = Event<'a>> {
+    let mut cleaner = Cleaner {
+        policy: policy.clone(),
+        skipping: None,
+    };
+    events.filter_map(move |event| match event {
+        Event::Html(html) => Some(Event::Html(cleaner.clean(&html).into())),
+        Event::InlineHtml(html) => Some(Event::InlineHtml(cleaner.clean(&html).into())),
+        Event::Text(_) if cleaner.skipping.is_some() => None,
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Some(Event::Start(Tag::Link {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        })),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Some(Event::Start(Tag::Image {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        })),
+        _ => Some(event),
+    })
+}
+
+fn safe_url(url: CowStr) -> CowStr {
+    if is_safe_url(&url) {
+        url
+    } else {
+        CowStr::Borrowed("")
+    }
+}
+
+struct Cleaner {
+    policy: Policy,
+    /// Tag whose content is being dropped, which can span several events.
+    skipping: Option<String>,
+}
+
+impl Cleaner {
+    fn clean(&mut self, html: &str) -> String {
+        let mut out = String::with_capacity(html.len());
+        let mut rest = html;
+        while let Some(start) = rest.find('<') {
+            if self.skipping.is_none() {
+                out.push_str(&rest[..start]);
+            }
+            rest = &rest[start..];
+
+            if let Some(comment) = rest.strip_prefix("<!--") {
+                rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+                continue;
+            }
+            // Declarations like `<!DOCTYPE html>` and `<?xml ?>`.
+            if rest.starts_with("<!") || rest.starts_with("<?") {
+                rest = rest.find('>').map_or("", |end| &rest[end + 1..]);
+                continue;
+            }
+            let Some((tag, len)) = parse_tag(rest) else {
+                if self.skipping.is_none() {
+                    out.push_str("&lt;");
+                }
+                rest = &rest[1..];
+                continue;
+            };
+            rest = &rest[len..];
+
+            if let Some(skipping) = &self.skipping {
+                if tag.closing && &tag.name == skipping {
+                    self.skipping = None;
+                }
+                continue;
+            }
+            if CONTENT_TAGS.contains(&tag.name.as_str()) && !self.policy.allows(&tag.name) {
+                if !tag.closing && !tag.self_closing {
+                    self.skipping = Some(tag.name);
+                }
+                continue;
+            }
+            if self.policy.allows(&tag.name) {
+                tag.write(&mut out);
+            }
+        }
+        if self.skipping.is_none() {
+            out.push_str(rest);
+        }
+        out
+    }
+}
+
+struct HtmlTag {
+    name: String,
+    closing: bool,
+    self_closing: bool,
+    attributes: Vec<(String, Option<String>)>,
+}
+
+impl HtmlTag {
+    fn write(&self, out: &mut String) {
+        out.push('<');
+        if self.closing {
+            out.push('/');
+        }
+        out.push_str(&self.name);
+        for (name, value) in &self.attributes {
+            if !ATTRIBUTES.contains(&name.as_str()) {
+                continue;
+            }
+            if matches!(name.as_str(), "href" | "src" | "cite")
+                && !is_safe_url(value.as_deref().unwrap_or_default())
+            {
+                continue;
+            }
+            out.push(' ');
+            out.push_str(name);
+            if let Some(value) = value {
+                out.push_str("=\"");
+                out.push_str(&value.replace('"', "&quot;").replace('<', "&lt;"));
+                out.push('"');
+            }
+        }
+        if self.self_closing {
+            out.push_str(" /");
+        }
+        out.push('>');
+    }
+}
+
+/// Parse a tag at the start of `html`, returning it and its length.
+fn parse_tag(html: &str) -> Option<(HtmlTag, usize)> {
+    let bytes = html.as_bytes();
+    let mut i = 1;
+    let closing = bytes.get(i) == Some(&b'/');
+    if closing {
+        i += 1;
+    }
+    let name_start = i;
+    while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'-') {
+        i += 1;
+    }
+    if i == name_start || !bytes[name_start].is_ascii_alphabetic() {
+        return None;
+    }
+    let mut tag = HtmlTag {
+        name: html[name_start..i].to_ascii_lowercase(),
+        closing,
+        self_closing: false,
+        attributes: Vec::new(),
+    };
+
+    loop {
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        match bytes.get(i)? {
+            b'>' => return Some((tag, i + 1)),
+            b'/' if bytes.get(i + 1) == Some(&b'>') => {
+                tag.self_closing = true;
+                return Some((tag, i + 2));
+            }
+            _ => {}
+        }
+        let name_start = i;
+        while i < bytes.len() && !b" \t\r\n=>/".contains(&bytes[i]) {
+            i += 1;
+        }
+        if i == name_start {
+            // A stray `/`.
+            i += 1;
+            continue;
+        }
+        let name = html[name_start..i].to_ascii_lowercase();
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if bytes.get(i) != Some(&b'=') {
+            tag.attributes.push((name, None));
+            continue;
+        }
+        i += 1;
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        let value = match bytes.get(i)? {
+            quote @ (b'"' | b'\'') => {
+                let end = i + 1 + html[i + 1..].find(*quote as char)?;
+                let value = &html[i + 1..end];
+                i = end + 1;
+                value
+            }
+            _ => {
+                let start = i;
+                while i < bytes.len() && !bytes[i].is_ascii_whitespace() && bytes[i] != b'>' {
+                    i += 1;
+                }
+                &html[start..i]
+            }
+        };
+        tag.attributes.push((name, Some(value.to_string())));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clean(html: &str) -> String {
+        Cleaner {
+            policy: Policy::default(),
+            skipping: None,
+        }
+        .clean(html)
+    }
+
+    #[test]
+    fn unsafe_markup_is_removed() {
+        assert_eq!(
+            clean(r#"<p onclick="x()" title='a "b"'>hi</p><script>alert(1)</script>!"#),
+            r#"<p title="a &quot;b&quot;">hi</p>!"#
+        );
+        assert_eq!(clean("<a href=\"javascript:x()\">a</a>"), "<a>a</a>");
+        assert_eq!(clean("<a href='java&#115;cript:x()'>a</a>"), "<a>a</a>");
+        assert_eq!(
+            clean("<img src=x.png onerror=alert(1)>"),
+            "<img src=\"x.png\">"
+        );
+        assert_eq!(
+            clean("<!-- c --><iframe src=x></iframe><b>b</b>"),
+            "<b>b</b>"
+        );
+        assert_eq!(clean("1 < 2"), "1 &lt; 2");
+    }
+
+    #[test]
+    fn safe_urls() {
+        for url in [
+            "https://a.b",
+            "/x",
+            "x.md#a:b",
+            "#top",
+            "?q=a:b",
+            "MAILTO:a@b",
+        ] {
+            assert!(is_safe_url(url), "{}", url);
+        }
+        for url in [
+            "javascript:x",
+            " JavaScript:x",
+            "data:text/html,x",
+            "vbscript:x",
+        ] {
+            assert!(!is_safe_url(url), "{}", url);
+        }
+    }
+}
@@ -0,0 +1,517 @@
+//! Export of a directory of markdown files as a static site, for
+//! `mdopen --export-watch SRC OUT`.
+//!
+//! Every markdown file becomes an HTML page at the same place under the
+//! output directory, and other files are copied. Files are only written when
+//! their source is newer, so a rebuild after an edit is quick, and are
+//! rendered on every core.
+//!
+//! With a `SUMMARY.md` the site is a book, like one made by mdBook: pages get
+//! a sidebar listing the chapters and links to the previous and next one, and
+//! the summary becomes the landing page.
+
+use crate::export::{inline_styles, rewrite_urls};
+use crate::summary::{self, Chapter};
+use crate::wikilink::SEGMENT;
+use crate::{feed, include, links, markdown_page, minify, sitemap, AppConfig};
+use log::{error, info};
+use percent_encoding::utf8_percent_encode;
+use pulldown_cmark_escape::escape_html;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// How often the source directory is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Start of the content in rendered pages, where the book sidebar goes.
+const PAGE_START: &str = "<div class=\"markdown-body\">";
+
+fn is_markdown(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("md" | "markdown")
+    )
+}
+
+/// Point a relative link to a markdown file at its exported page.
+fn html_link(url: &str) -> Option<String> {
+    if url.starts_with("//") || url.starts_with('#') {
+        return None;
+    }
+    if let Some(colon) = url.find(':') {
+        if !url[..colon].contains(['/', '?', '#']) {
+            return None;
+        }
+    }
+    let end = url.find(['?', '#']).unwrap_or(url.len());
+    let stem = url[..end]
+        .strip_suffix(".md")
+        .or(url[..end].strip_suffix(".markdown"))?;
+    Some(format!("{}.html{}", stem, &url[end..]))
+}
+
+/// Files under `dir`, leaving out hidden ones and the output directory.
+fn source_files(dir: &Path, out: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if hidden || path == out {
+            continue;
+        }
+        if path.is_dir() {
+            source_files(&path, out, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    _ = escape_html(&mut escaped, text);
+    escaped
+}
+
+/// URL of `target` from the page `page`, both relative to the output directory.
+fn relative_url(page: &Path, target: &Path) -> String {
+    let from: Vec<_> = page
+        .parent()
+        .map_or(vec![], |dir| dir.components().collect());
+    let to: Vec<_> = target.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let segments: Vec<_> = to[common..]
+        .iter()
+        .map(|c| utf8_percent_encode(&c.as_os_str().to_string_lossy(), SEGMENT).to_string())
+        .collect();
+    "../".repeat(from.len() - common) + &segments.join("/")
+}
+
+/// Chapters listed in the summary of the source directory.
+struct Book {
+    title: Option<String>,
+    chapters: Vec<Chapter>,
+    /// When the summary was read, to notice changes to it.
+    modified: SystemTime,
+}
+
+/// Site being exported, remembering what it wrote.
+pub struct Site<'a> {
+    config: &'a AppConfig,
+    src: PathBuf,
+    out: PathBuf,
+    /// Files written to `out`, to remove them when their source is gone.
+    written: HashSet<PathBuf>,
+    book: Option<Book>,
+    /// Minify the exported pages.
+    pub minify: bool,
+}
+
+impl<'a> Site<'a> {
+    pub fn new(config: &'a AppConfig, src: &Path, out: &Path) -> io::Result<Self> {
+        fs::create_dir_all(out)?;
+        Ok(Site {
+            config,
+            src: src.canonicalize()?,
+            out: out.canonicalize()?,
+            written: HashSet::new(),
+            book: None,
+            minify: false,
+        })
+    }
+
+    fn summary_path(&self) -> PathBuf {
+        self.src.join("SUMMARY.md")
+    }
+
+    /// Read the summary again if it changed, returning whether it did.
+    fn update_book(&mut self) -> bool {
+        let summary = self.summary_path();
+        let modified = modified(&summary);
+        if modified == self.book.as_ref().map(|book| book.modified) {
+            return false;
+        }
+        let md = fs::read_to_string(&summary).unwrap_or_default();
+        self.book = modified.map(|modified| {
+            let summary = summary::parse(&md, &self.src);
+            let chapters = summary
+                .chapters
+                .into_iter()
+                .map(|chapter| Chapter {
+                    // Like the paths of the source files.
+                    path: chapter.path.map(|path| path.canonicalize().unwrap_or(path)),
+                    ..chapter
+                })
+                .collect();
+            Book {
+                title: summary.title,
+                chapters,
+                modified,
+            }
+        });
+        true
+    }
+
+    /// Where the export of the source file `path` goes.
+    fn output_path(&self, path: &Path) -> PathBuf {
+        let relative = path.strip_prefix(&self.src).unwrap_or(path);
+        if self.book.is_some() && path == self.summary_path() && !self.src.join("index.md").exists()
+        {
+            return self.out.join("index.html");
+        }
+        let target = self.out.join(relative);
+        match is_markdown(path) {
+            true => target.with_extension("html"),
+            false => target,
+        }
+    }
+
+    /// Sidebar listing the chapters, and links to the previous and next one,
+    /// for the page of `path` exported to `target`.
+    fn book_nav(&self, book: &Book, path: &Path, target: &Path) -> (String, String) {
+        let page = target.strip_prefix(&self.out).unwrap_or(target);
+        let href = |path: &Path| {
+            let target = self.output_path(path);
+            relative_url(page, target.strip_prefix(&self.out).unwrap_or(&target))
+        };
+        // Chapters outside the source directory aren't exported.
+        let exported = |chapter: &Chapter| {
+            chapter
+                .path
+                .as_deref()
+                .filter(|path| path.starts_with(&self.src))
+                .map(href)
+        };
+        let summary = self.summary_path();
+        let title = book.title.as_deref().unwrap_or("Contents");
+        let current = book
+            .chapters
+            .iter()
+            .position(|chapter| chapter.path.as_deref() == Some(path));
+
+        let list = summary::list_html(&book.chapters, |i, chapter| match exported(chapter) {
+            Some(href) if Some(i) == current => format!(
+                "<a class=\"active\" href=\"{}\" aria-current=\"page\">{}</a>",
+                href,
+                escape(&chapter.title)
+            ),
+            Some(href) => format!("<a href=\"{}\">{}</a>", href, escape(&chapter.title)),
+            None => format!("<span class=\"draft\">{}</span>", escape(&chapter.title)),
+        });
+        let sidebar = match path == summary {
+            true => String::new(),
+            false => format!(
+                "<nav class=\"book-nav\"><details open><summary><a href=\"{}\">{}</a></summary>{}</details></nav>",
+                href(&summary),
+                escape(title),
+                list
+            ),
+        };
+
+        let pages: Vec<(String, &str)> = book
+            .chapters
+            .iter()
+            .filter_map(|chapter| Some((exported(chapter)?, chapter.title.as_str())))
+            .collect();
+        let (prev, next) = match current {
+            _ if path == summary => (None, pages.first()),
+            // A chapter can be listed twice, only the first one counts.
+            Some(i) => {
+                let before = book.chapters[..i]
+                    .iter()
+                    .filter(|chapter| exported(chapter).is_some())
+                    .count();
+                let prev = match before {
+                    0 => Some((href(&summary), title)),
+                    _ => pages.get(before - 1).cloned(),
+                };
+                (prev, pages.get(before + 1))
+            }
+            None => (None, None),
+        };
+        let mut pager = String::new();
+        if let Some((href, title)) = prev {
+            pager.push_str(&format!(
+                "<a rel=\"prev\" href=\"{}\">← {}</a>",
+                href,
+                escape(title)
+            ));
+        }
+        if let Some((href, title)) = next {
+            pager.push_str(&format!(
+                "<a rel=\"next\" href=\"{}\">{} →</a>",
+                href,
+                escape(title)
+            ));
+        }
+        if !pager.is_empty() {
+            pager = format!("<nav class=\"book-pager\">{}</nav>", pager);
+        }
+        (sidebar, pager)
+    }
+
+    fn export_file(&self, path: &Path, target: &Path) -> io::Result<()> {
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if !is_markdown(path) {
+            return fs::copy(path, target).map(|_| ());
+        }
+        let md = include::expand(&fs::read_to_string(path)?, path, &self.src);
+        let title = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let (sidebar, pager) = match &self.book {
+            Some(book) => self.book_nav(book, path, target),
+            None => Default::default(),
+        };
+        let base = links::Base::new(&self.src, path);
+        let html = markdown_page(&md, &title, &pager, Some(base), self.config);
+        let html = html.replacen(PAGE_START, &format!("{}{}", PAGE_START, sidebar), 1);
+        let html = rewrite_urls(&html, "a", "href", html_link);
+        // The output is served by anything, not by mdopen.
+        let html = inline_styles(&html, self.config.syntax_theme_css.as_deref());
+        match self.minify {
+            true => fs::write(target, minify::html(&html)),
+            false => fs::write(target, html),
+        }
+    }
+
+    /// Export `(source, target)` pairs on a thread for each core, returning
+    /// the result of each pair in order.
+    fn export_files(&self, files: &[(PathBuf, PathBuf)]) -> Vec<io::Result<()>> {
+        let workers = thread::available_parallelism()
+            .map_or(1, usize::from)
+            .min(files.len());
+        if workers <= 1 {
+            return files
+                .iter()
+                .map(|(path, target)| self.export_file(path, target))
+                .collect();
+        }
+        let next = AtomicUsize::new(0);
+        let mut results: Vec<(usize, io::Result<()>)> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut done = Vec::new();
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            let Some((path, target)) = files.get(i) else {
+                                break done;
+                            };
+                            done.push((i, self.export_file(path, target)));
+                        }
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                // A worker that panicked would leave its files unreported.
+                .flat_map(|handle| handle.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+                .collect()
+        });
+        results.sort_by_key(|(i, _)| *i);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+
+    /// Export the files changed since they were last exported, returning
+    /// how many were written.
+    pub fn build(&mut self) -> io::Result<usize> {
+        let mut files = Vec::new();
+        source_files(&self.src, &self.out, &mut files)?;
+        // Every page lists the chapters.
+        let book_changed = self.update_book();
+        let mut count = 0;
+        let mut targets = HashSet::new();
+        let mut stale = Vec::new();
+        for path in files {
+            let target = self.output_path(&path);
+            targets.insert(target.clone());
+            let is_stale = match (modified(&path), modified(&target)) {
+                _ if book_changed && is_markdown(&path) => true,
+                (Some(source), Some(output)) => {
+                    source > output
+                        || (is_markdown(&path)
+                            && include::included(&path, &self.src)
+                                .iter()
+                                .any(|file| modified(file).is_some_and(|time| time > output)))
+                }
+                _ => true,
+            };
+            if is_stale {
+                stale.push((path, target));
+            }
+        }
+        let results = self.export_files(&stale);
+        for ((path, target), result) in stale.into_iter().zip(results) {
+            match result {
+                Ok(()) => {
+                    info!("wrote {}", target.display());
+                    self.written.insert(target);
+                    count += 1;
+                }
+                Err(e) => error!("cannot export {}: {}", path.display(), e),
+            }
+        }
+        let mut removed = false;
+        for target in self.written.difference(&targets) {
+            info!("removing {}", target.display());
+            _ = fs::remove_file(target);
+            removed = true;
+        }
+        self.written.retain(|target| targets.contains(target));
+        self.write_indexes(count > 0 || removed)?;
+        Ok(count)
+    }
+
+    /// Write the feed and indexes of the site that are turned on, when pages
+    /// changed or they are missing.
+    fn write_indexes(&self, changed: bool) -> io::Result<()> {
+        let config = self.config;
+        let base = match &config.site_url {
+            Some(url) if !url.ends_with('/') => format!("{}/", url),
+            Some(url) => url.clone(),
+            None => String::new(),
+        };
+        // Indexes are at the top of the site.
+        let url = |path: &Path| {
+            let target = self.output_path(path);
+            let page = target.strip_prefix(&self.out).unwrap_or(&target);
+            relative_url(Path::new(feed::FEED_FILE), page)
+        };
+        let write = |file: &str, content: &dyn Fn() -> String| {
+            let path = self.out.join(file);
+            match changed || !path.exists() {
+                true => fs::write(path, content()),
+                false => Ok(()),
+            }
+        };
+        let (src, options) = (&self.src, &config.markdown);
+        if config.feed {
+            write(feed::FEED_FILE, &|| feed::atom(src, options, &base, url))?;
+        }
+        if config.sitemap {
+            write(sitemap::SITEMAP_FILE, &|| {
+                sitemap::sitemap(src, options, &base, url)
+            })?;
+            write(sitemap::LLMS_FILE, &|| {
+                sitemap::llms_txt(src, options, &base, url)
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Export `src` to `out` whenever something changes, until killed.
+pub fn watch(config: &AppConfig, src: &Path, out: &Path, minify: bool) -> i32 {
+    let mut site = match Site::new(config, src, out) {
+        Ok(site) => site,
+        Err(e) => {
+            eprintln!(
+                "cannot export {} to {}: {}",
+                src.display(),
+                out.display(),
+                e
+            );
+            return 1;
+        }
+    };
+    site.minify = minify;
+    info!("exporting {} to {}", src.display(), out.display());
+    loop {
+        if let Err(e) = site.build() {
+            error!("cannot export {}: {}", src.display(), e);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::config;
+    use std::env;
+
+    #[test]
+    fn changed_files_are_exported() {
+        let dir = env::temp_dir().join("mdopen-test-site");
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src/img")).unwrap();
+        fs::write(
+            dir.join("src/a.md"),
+            "[b](b.md#x) [web](https://x.md)\n\n{{#include b.md}}\n",
+        )
+        .unwrap();
+        fs::write(dir.join("src/b.md"), "# B\n").unwrap();
+        fs::write(dir.join("src/img/p.png"), "png").unwrap();
+        let config = config();
+
+        let mut site = Site::new(&config, &dir.join("src"), &dir.join("out")).unwrap();
+        assert_eq!(site.build().unwrap(), 3);
+        let page = fs::read_to_string(dir.join("out/a.html")).unwrap();
+        assert!(page.contains("<a href=\"b.html#x\">b</a> <a href=\"https://x.md\">web</a>"));
+        assert!(page.contains("<style>"));
+        assert!(dir.join("out/img/p.png").is_file());
+        assert_eq!(site.build().unwrap(), 0);
+
+        // Pages including a changed file are written again too.
+        let later = SystemTime::now() + Duration::from_secs(10);
+        let b = fs::File::options().write(true).open(dir.join("src/b.md"));
+        b.unwrap().set_modified(later).unwrap();
+        assert_eq!(site.build().unwrap(), 2);
+
+        fs::remove_file(dir.join("src/img/p.png")).unwrap();
+        site.build().unwrap();
+        assert!(!dir.join("out/img/p.png").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn books_have_navigation() {
+        let dir = env::temp_dir().join("mdopen-test-book");
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("guide")).unwrap();
+        fs::write(
+            dir.join("SUMMARY.md"),
+            "# Book\n\n- [Intro](intro.md)\n- [Guide](guide/use.md)\n  - [Later]()\n",
+        )
+        .unwrap();
+        fs::write(dir.join("intro.md"), "# Intro\n").unwrap();
+        fs::write(dir.join("guide/use.md"), "# Use\n").unwrap();
+        let config = config();
+
+        let mut site = Site::new(&config, &dir, &dir.join("out")).unwrap();
+        assert_eq!(site.build().unwrap(), 3);
+        let landing = fs::read_to_string(dir.join("out/index.html")).unwrap();
+        assert!(!landing.contains("<nav class=\"book-nav\""));
+        assert!(landing.contains("<a rel=\"next\" href=\"intro.html\">Intro →</a>"));
+        let page = fs::read_to_string(dir.join("out/guide/use.html")).unwrap();
+        assert!(page.contains(
+            "<nav class=\"book-nav\"><details open><summary><a href=\"../index.html\">Book</a></summary><ol><li><a href=\"../intro.html\">Intro</a></li><li><a class=\"active\" href=\"use.html\" aria-current=\"page\">Guide</a><ol><li><span class=\"draft\">Later</span></li></ol></li></ol></details></nav>"
+        ));
+        assert!(page.contains(
+            "<nav class=\"book-pager\"><a rel=\"prev\" href=\"../intro.html\">← Intro</a></nav>"
+        ));
+
+        // Changing the summary updates every page.
+        fs::write(dir.join("SUMMARY.md"), "# Other\n\n- [Intro](intro.md)\n").unwrap();
+        assert_eq!(site.build().unwrap(), 3);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
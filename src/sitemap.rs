@@ -0,0 +1,164 @@
+//! Indexes of the markdown files in a directory for crawlers and tools, for
+//! `--sitemap`: a `sitemap.xml`, and an `llms.txt` listing each document with
+//! its title and description, as proposed at <https://llmstxt.org>.
+
+use crate::feed::{self, Entry};
+use crate::markdown;
+use pulldown_cmark::{Event, Parser, Tag, TagEnd};
+use pulldown_cmark_escape::escape_html;
+use std::path::Path;
+
+/// Path of the sitemap, served and exported.
+pub const SITEMAP_FILE: &str = "sitemap.xml";
+
+/// Path of the index for language models, served and exported.
+pub const LLMS_FILE: &str = "llms.txt";
+
+/// Longest description in `llms.txt`, in characters.
+const MAX_DESCRIPTION: usize = 200;
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    _ = escape_html(&mut escaped, text);
+    escaped
+}
+
+/// Sitemap of the markdown files under `root`.
+///
+/// `base` is the URL of the directory, and `url` gives the URL of the page
+/// of a file relative to it.
+pub fn sitemap(
+    root: &Path,
+    options: &markdown::Options,
+    base: &str,
+    url: impl Fn(&Path) -> String,
+) -> String {
+    let mut entries = feed::entries(root, options);
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for entry in entries {
+        xml.push_str(&format!(
+            "<url><loc>{}</loc><lastmod>{}</lastmod></url>\n",
+            escape(&format!("{}{}", base, url(&entry.path))),
+            humantime::format_rfc3339_seconds(entry.updated),
+        ));
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+/// Text of the first paragraph of `md`.
+fn first_paragraph(md: &str, options: &markdown::Options) -> String {
+    let mut text = String::new();
+    let mut in_paragraph = false;
+    for event in Parser::new_ext(md, markdown::parser_options(options)) {
+        match event {
+            Event::Start(Tag::Paragraph) => in_paragraph = true,
+            Event::End(TagEnd::Paragraph) if in_paragraph => break,
+            Event::Text(t) | Event::Code(t) if in_paragraph => text.push_str(&t),
+            Event::SoftBreak | Event::HardBreak if in_paragraph => text.push(' '),
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Description from the frontmatter, or the start of the first paragraph.
+fn description(entry: &Entry, options: &markdown::Options) -> String {
+    let text = match feed::field(&entry.metadata, "description") {
+        Some(description) => description.to_string(),
+        None => first_paragraph(&entry.md, options),
+    };
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.chars().count() <= MAX_DESCRIPTION {
+        return text;
+    }
+    let cut: String = text.chars().take(MAX_DESCRIPTION).collect();
+    let cut = cut
+        .rsplit_once(' ')
+        .map_or(cut.as_str(), |(start, _)| start);
+    format!("{}…", cut)
+}
+
+/// `llms.txt` index of the markdown files under `root`, with a section for
+/// each directory.
+pub fn llms_txt(
+    root: &Path,
+    options: &markdown::Options,
+    base: &str,
+    url: impl Fn(&Path) -> String,
+) -> String {
+    let mut entries = feed::entries(root, options);
+    let dir = |entry: &Entry| {
+        let path = entry.path.strip_prefix(root).unwrap_or(&entry.path);
+        path.parent()
+            .map(|dir| dir.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
+    entries.sort_by(|a, b| (dir(a), &a.path).cmp(&(dir(b), &b.path)));
+    let name = root
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "mdopen".to_string());
+    let mut text = format!("# {}\n", name);
+    let mut section = None;
+    for entry in &entries {
+        let dir = dir(entry);
+        if section.as_ref() != Some(&dir) {
+            let heading = if dir.is_empty() { "Docs" } else { &dir };
+            text.push_str(&format!("\n## {}\n\n", heading));
+            section = Some(dir);
+        }
+        let doc = markdown::render(&entry.md, options);
+        text.push_str(&format!(
+            "- [{}]({}{})",
+            entry.title(&doc).replace(['[', ']'], ""),
+            base,
+            url(&entry.path)
+        ));
+        let description = description(entry, options);
+        if !description.is_empty() {
+            text.push_str(": ");
+            text.push_str(&description);
+        }
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn documents_are_indexed() {
+        let dir = env::temp_dir().join("mdopen-test-sitemap");
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("guide")).unwrap();
+        fs::write(dir.join("README.md"), "# Project\n\nA *small*\ntool.\n").unwrap();
+        fs::write(
+            dir.join("guide/install.md"),
+            "---\ntitle: Installing\ndescription: How to install.\ndate: 2022-02-03\n---\nText\n",
+        )
+        .unwrap();
+        let options = markdown::Options::default();
+        let url = |path: &Path| {
+            let path = path.strip_prefix(&dir).unwrap().to_string_lossy();
+            path.replace(".md", ".html")
+        };
+
+        let xml = sitemap(&dir, &options, "https://x.org/", url);
+        assert!(xml.contains("<url><loc>https://x.org/guide/install.html</loc><lastmod>2022-02-03T00:00:00Z</lastmod></url>\n"));
+        assert!(xml.contains("<loc>https://x.org/README.html</loc>"));
+        assert_eq!(
+            llms_txt(&dir, &options, "", url),
+            "# mdopen-test-sitemap\n\n## Docs\n\n- [Project](README.html): A small tool.\n\n## guide\n\n- [Installing](guide/install.html): How to install.\n"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+//! Presentation of a markdown file as slides, for `--slides`.
+//!
+//! Slides are separated by `---` lines. A file without any is split before
+//! each level 1 and 2 heading instead. Everything after a `Note:` line of a
+//! slide is speaker notes, shown in a separate window.
+
+use crate::markdown;
+
+/// Whether `line` opens or closes a fenced code block.
+fn is_fence(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with("```") || line.starts_with("~~~")
+}
+
+/// Skip a YAML or TOML frontmatter block, which isn't part of any slide.
+fn strip_frontmatter(md: &str) -> &str {
+    for delimiter in ["---", "+++"] {
+        let Some(rest) = md.strip_prefix(delimiter) else {
+            continue;
+        };
+        let Some(rest) = rest.strip_prefix('\n').or(rest.strip_prefix("\r\n")) else {
+            continue;
+        };
+        let mut offset = 0;
+        for line in rest.split_inclusive('\n') {
+            offset += line.len();
+            if line.trim_end() == delimiter {
+                return &rest[offset..];
+            }
+        }
+    }
+    md
+}
+
+/// Split `md` into slides.
+pub fn split(md: &str) -> Vec<&str> {
+    let md = strip_frontmatter(md);
+    let mut rulers = Vec::new();
+    let mut headings = Vec::new();
+    let mut in_fence = false;
+    let mut offset = 0;
+    for line in md.split_inclusive('\n') {
+        if is_fence(line) {
+            in_fence = !in_fence;
+        } else if !in_fence && line.trim_end() == "---" {
+            rulers.push((offset, offset + line.len()));
+        } else if !in_fence && (line.starts_with("# ") || line.starts_with("## ")) {
+            headings.push((offset, offset));
+        }
+        offset += line.len();
+    }
+    let breaks = if rulers.is_empty() { headings } else { rulers };
+    let mut slides = Vec::new();
+    let mut start = 0;
+    for (end, next) in breaks {
+        slides.push(&md[start..end]);
+        start = next;
+    }
+    slides.push(&md[start..]);
+    slides.retain(|slide| !slide.trim().is_empty());
+    slides
+}
+
+/// Split a slide into its content and speaker notes.
+pub fn notes(slide: &str) -> (&str, Option<&str>) {
+    let mut offset = 0;
+    let mut in_fence = false;
+    for line in slide.split_inclusive('\n') {
+        if is_fence(line) {
+            in_fence = !in_fence;
+        }
+        let trimmed = line.trim();
+        if !in_fence && (trimmed == "Note:" || trimmed == "Notes:") {
+            return (&slide[..offset], Some(&slide[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+    (slide, None)
+}
+
+/// Render `md` as slides, each with its notes.
+pub fn to_html(md: &str, options: &markdown::Options) -> String {
+    let mut html = String::from("<div class=\"slides\">");
+    for (i, slide) in split(md).into_iter().enumerate() {
+        let (content, notes) = notes(slide);
+        html.push_str(&format!(
+            "<section class=\"slide\" id=\"slide-{}\"><div class=\"slide-content\">{}</div>",
+            i + 1,
+            markdown::to_html(content, options)
+        ));
+        if let Some(notes) = notes {
+            html.push_str(&format!(
+                "<aside class=\"slide-notes\">{}</aside>",
+                markdown::to_html(notes, options)
+            ));
+        }
+        html.push_str("</section>");
+    }
+    html.push_str("<div class=\"slide-counter\"></div></div>");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_are_split_into_slides() {
+        assert_eq!(
+            split("---\ntitle: Talk\n---\n# One\n\n---\n\n```\n---\n```\n---\nThree\n"),
+            ["# One\n\n", "\n```\n---\n```\n", "Three\n"]
+        );
+        assert_eq!(
+            split("intro\n# One\ntext\n## Two\n### Three\n"),
+            ["intro\n", "# One\ntext\n", "## Two\n### Three\n"]
+        );
+        assert_eq!(
+            notes("# One\nNote:\nsay hi\n"),
+            ("# One\n", Some("say hi\n"))
+        );
+        assert_eq!(notes("# One\n"), ("# One\n", None));
+
+        let html = to_html("# A\nNote:\nhi\n---\nB\n", &markdown::Options::default());
+        assert!(html.starts_with("<div class=\"slides\"><section class=\"slide\" id=\"slide-1\"><div class=\"slide-content\"><h1"));
+        assert!(html.contains("<aside class=\"slide-notes\"><p>hi</p>\n</aside></section><section class=\"slide\" id=\"slide-2\">"));
+    }
+}
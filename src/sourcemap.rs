@@ -0,0 +1,32 @@
+//! Offsets in preprocessed markdown, mapped back to the text it was made from.
+
+/// Where the pieces of a preprocessed text came from, in output order.
+#[derive(Default)]
+pub struct SourceMap {
+    /// The output offset each piece starts at, the source offset it maps to,
+    /// and whether it was copied from there rather than generated.
+    pieces: Vec<(usize, usize, bool)>,
+}
+
+impl SourceMap {
+    /// The output from `at` on is copied from the source at `from`.
+    pub fn copied(&mut self, at: usize, from: usize) {
+        self.pieces.push((at, from, true));
+    }
+
+    /// The output from `at` on was generated for the source at `from`.
+    pub fn generated(&mut self, at: usize, from: usize) {
+        self.pieces.push((at, from, false));
+    }
+
+    /// The source offset of `offset` in the output. Generated text maps to
+    /// where it was generated for, and an empty map leaves offsets as they are.
+    pub fn source(&self, offset: usize) -> usize {
+        let piece = self.pieces.partition_point(|&(at, _, _)| at <= offset);
+        match piece.checked_sub(1).map(|i| self.pieces[i]) {
+            Some((at, from, true)) => from + offset - at,
+            Some((_, from, false)) => from,
+            None => offset,
+        }
+    }
+}
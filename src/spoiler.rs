@@ -0,0 +1,148 @@
+//! Spoilers, hidden until clicked: `||inline||` as on Discord, and block
+//! quotes whose lines start with `>!`, as on Reddit.
+
+use pulldown_cmark::{CowStr, Event, Tag, TagEnd, TextMergeStream};
+
+const OPEN: &str = "<span class=\"spoiler\" tabindex=\"0\">";
+
+/// Whether `end` closes the block an inline spoiler must be closed in.
+fn ends_inline_block(end: &TagEnd) -> bool {
+    matches!(
+        end,
+        TagEnd::Paragraph
+            | TagEnd::Heading(_)
+            | TagEnd::Item
+            | TagEnd::TableCell
+            | TagEnd::DefinitionListTitle
+            | TagEnd::DefinitionListDefinition
+    )
+}
+
+/// Turn spoiler syntax in text events into elements blurred by the page.
+pub fn expand<'a>(events: impl Iterator<Item = Event<'a>>) -> Vec<Event<'a>> {
+    let mut out = Vec::new();
+    let mut in_code = false;
+    // Index in `out` of the opening tag of a `||` spoiler not closed yet.
+    let mut open = None;
+    // Whether each open block quote is a spoiler.
+    let mut quotes = Vec::<bool>::new();
+    // Index of a block quote start, until its first text tells if it is a spoiler.
+    let mut undecided_quote = None;
+    let mut line_start = false;
+    for event in TextMergeStream::new(events) {
+        let next_line_start = matches!(event, Event::Start(Tag::Paragraph) | Event::SoftBreak);
+        match &event {
+            Event::Start(Tag::CodeBlock(_)) => in_code = true,
+            Event::End(TagEnd::CodeBlock) => in_code = false,
+            Event::Start(Tag::BlockQuote(kind)) => {
+                quotes.push(false);
+                // GitHub alerts like `> [!NOTE]` are never spoilers.
+                if kind.is_none() {
+                    undecided_quote = Some(out.len());
+                    out.push(event);
+                    continue;
+                }
+            }
+            Event::End(TagEnd::BlockQuote(_)) => {
+                let spoiler = quotes.pop() == Some(true);
+                if spoiler {
+                    out.push(Event::Html("</div>\n".into()));
+                    continue;
+                }
+            }
+            Event::End(end) if ends_inline_block(end) => {
+                // An unclosed `||` is just text.
+                if let Some(i) = open.take() {
+                    out[i] = Event::Text("||".into());
+                }
+            }
+            Event::Text(text) if !in_code => {
+                let mut text: &str = text;
+                if let Some(i) = undecided_quote.take() {
+                    if let Some(rest) = text.strip_prefix('!') {
+                        out[i] = Event::Html("<div class=\"spoiler\" tabindex=\"0\">\n".into());
+                        *quotes.last_mut().unwrap() = true;
+                        text = rest.trim_start();
+                    }
+                } else if line_start && quotes.last() == Some(&true) {
+                    // Continuation lines start with `>!` too.
+                    text = text.strip_prefix('!').map_or(text, str::trim_start);
+                }
+                push_inline(&mut out, text, &mut open);
+                line_start = false;
+                continue;
+            }
+            _ => {}
+        }
+        if !matches!(event, Event::Start(Tag::Paragraph)) {
+            undecided_quote = None;
+        }
+        line_start = next_line_start;
+        out.push(event);
+    }
+    out
+}
+
+/// Push `text` with its `||` delimiters as spoiler tags.
+///
+/// Like emphasis, a spoiler cannot start before or end after a space.
+fn push_inline<'a>(out: &mut Vec<Event<'a>>, text: &str, open: &mut Option<usize>) {
+    let mut rest = text;
+    while let Some(i) = rest.find("||") {
+        let before = rest[..i].chars().last();
+        let after = rest[i + 2..].chars().next();
+        let delimiter = match open {
+            Some(_) if !before.is_some_and(char::is_whitespace) => Some("</span>"),
+            None if !after.is_some_and(char::is_whitespace) => Some(OPEN),
+            _ => None,
+        };
+        let Some(delimiter) = delimiter else {
+            out.push(Event::Text(CowStr::from(rest[..i + 2].to_string())));
+            rest = &rest[i + 2..];
+            continue;
+        };
+        if i > 0 {
+            out.push(Event::Text(CowStr::from(rest[..i].to_string())));
+        }
+        *open = match open {
+            Some(_) => None,
+            None => Some(out.len()),
+        };
+        out.push(Event::InlineHtml(delimiter.into()));
+        rest = &rest[i + 2..];
+    }
+    if !rest.is_empty() {
+        out.push(Event::Text(CowStr::from(rest.to_string())));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::markdown::{to_html, Options};
+
+    #[test]
+    fn inline_spoilers() {
+        let html = |md| to_html(md, &Options::default());
+        assert_eq!(
+            html("It was ||**Rosebud**||, not a || b || c.\n"),
+            "<p>It was <span class=\"spoiler\" tabindex=\"0\"><strong>Rosebud</strong></span>, not a || b || c.</p>\n"
+        );
+        assert_eq!(
+            html("||open\n\nclosed||\n"),
+            "<p>||open</p>\n<p>closed||</p>\n"
+        );
+        assert_eq!(html("`||x||`\n"), "<p><code>||x||</code></p>\n");
+    }
+
+    #[test]
+    fn block_spoilers() {
+        let html = to_html(
+            ">! The butler\n>! did it.\n\n> quote\n",
+            &Options::default(),
+        );
+        assert_eq!(
+            html,
+            "<div class=\"spoiler\" tabindex=\"0\">\n<p>The butler\ndid it.</p>\n</div>\n<blockquote>\n<p>quote</p>\n</blockquote>\n"
+        );
+    }
+}
@@ -0,0 +1,155 @@
+//! Server-sent events for live reload, for `--reload`.
+//!
+//! Pages open an `EventSource` on [`RELOAD_PREFIX`](crate::RELOAD_PREFIX)
+//! followed by their own path, and get a `change` event when their file or a
+//! file it depends on changes, so other tabs are left alone. Browsers
+//! reconnect by themselves after the `retry` time sent with the response, and
+//! comments sent while nothing happens keep proxies from closing the
+//! connection. A `shutdown` event tells pages that the server was stopped
+//! rather than lost.
+
+use log::debug;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use tiny_http::Request;
+
+/// Time between comments on an idle connection, which is also how soon
+/// closed pages are noticed.
+const HEARTBEAT: Duration = Duration::from_secs(5);
+
+/// How soon browsers reconnect after losing the connection.
+const RETRY: Duration = Duration::from_secs(1);
+
+/// Connected page.
+struct Client {
+    id: u64,
+    /// File shown by the page, if it comes from one.
+    page: Option<PathBuf>,
+    sender: Sender<String>,
+}
+
+static CLIENTS: Mutex<Vec<Client>> = Mutex::new(Vec::new());
+
+static NEXT_CLIENT: AtomicU64 = AtomicU64::new(0);
+
+/// Connections still being written to.
+static OPEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Longest wait for the `shutdown` event to be sent.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Text of an event named `name`, with a `data` field for each line of `data`.
+fn event(name: &str, data: &str) -> String {
+    let mut text = format!("event: {}\n", name);
+    for line in data.lines() {
+        text.push_str("data: ");
+        text.push_str(line);
+        text.push('\n');
+    }
+    if data.is_empty() {
+        text.push_str("data:\n");
+    }
+    text.push('\n');
+    text
+}
+
+/// Send `message` to the clients for which `to` is true, forgetting those
+/// that are gone. Returns the number of clients it was sent to.
+fn send(message: &str, to: impl Fn(&Client) -> bool) -> usize {
+    let mut sent = 0;
+    let mut clients = CLIENTS.lock().unwrap();
+    clients.retain(|client| {
+        if !to(client) {
+            return true;
+        }
+        let ok = client.sender.send(message.to_string()).is_ok();
+        sent += ok as usize;
+        ok
+    });
+    sent
+}
+
+/// Send an event to the connected pages showing a file for which `to` is
+/// true, returning how many there were.
+pub fn broadcast(name: &str, data: &str, to: impl Fn(&Path) -> bool) -> usize {
+    send(&event(name, data), |client| {
+        client.page.as_deref().is_some_and(&to)
+    })
+}
+
+/// Send an event to every connected page.
+pub fn broadcast_all(name: &str, data: &str) {
+    send(&event(name, data), |_| true);
+}
+
+/// Tell every connected page that the server is stopping, waiting a little
+/// for the event to be sent before the process exits.
+pub fn shutdown() {
+    let message = event("shutdown", "");
+    for client in CLIENTS.lock().unwrap().drain(..) {
+        _ = client.sender.send(message.clone());
+    }
+    let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+    while OPEN.load(Ordering::Relaxed) > 0 && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Number of connected pages.
+pub fn clients() -> usize {
+    CLIENTS.lock().unwrap().len()
+}
+
+/// Keep the connection of `request` open on a thread, sending it the events
+/// of the file `page`.
+pub fn serve(request: Request, page: Option<PathBuf>) {
+    let (sender, receiver) = mpsc::channel();
+    let id = NEXT_CLIENT.fetch_add(1, Ordering::Relaxed);
+    CLIENTS.lock().unwrap().push(Client { id, page, sender });
+    let mut writer = request.into_writer();
+    OPEN.fetch_add(1, Ordering::Relaxed);
+    thread::spawn(move || {
+        let head = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nX-Accel-Buffering: no\r\nConnection: close\r\n\r\nretry: {}\n\n",
+            RETRY.as_millis()
+        );
+        let mut message = head;
+        loop {
+            if writer
+                .write_all(message.as_bytes())
+                .and_then(|_| writer.flush())
+                .is_err()
+            {
+                break;
+            }
+            message = match receiver.recv_timeout(HEARTBEAT) {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => ": heartbeat\n\n".to_string(),
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+        }
+        debug!("reload client {} disconnected", id);
+        CLIENTS.lock().unwrap().retain(|client| client.id != id);
+        OPEN.fetch_sub(1, Ordering::Relaxed);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_have_a_data_line_per_line() {
+        assert_eq!(event("change", "/a.md"), "event: change\ndata: /a.md\n\n");
+        assert_eq!(
+            event("change", "/a.md\n/b.md"),
+            "event: change\ndata: /a.md\ndata: /b.md\n\n"
+        );
+        assert_eq!(event("change", ""), "event: change\ndata:\n\n");
+    }
+}
@@ -0,0 +1,167 @@
+//! Parsing of mdBook style `SUMMARY.md` files, which list the chapters of a
+//! book as nested links:
+//!
+//! ```md
+//! # My Book
+//!
+//! [Introduction](README.md)
+//!
+//! - [Usage](usage.md)
+//!   - [Options](options.md)
+//! ```
+
+use pulldown_cmark::{Event, Parser, Tag, TagEnd};
+use std::path::{Path, PathBuf};
+
+/// Chapter of a book.
+#[derive(Debug, PartialEq)]
+pub struct Chapter {
+    pub title: String,
+    /// Markdown file of the chapter, or none for a draft chapter.
+    pub path: Option<PathBuf>,
+    /// Nesting level, from 1 for top level chapters.
+    pub level: usize,
+}
+
+/// Table of contents of a book.
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    /// First heading, unless it is just "Summary".
+    pub title: Option<String>,
+    pub chapters: Vec<Chapter>,
+}
+
+/// Whether `path` is named like a summary file.
+pub fn is_summary(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.eq_ignore_ascii_case("SUMMARY.md"))
+}
+
+/// Parse a summary, resolving chapter paths against `dir`.
+pub fn parse(md: &str, dir: &Path) -> Summary {
+    let mut summary = Summary::default();
+    let mut depth = 0;
+    let mut heading = None::<String>;
+    let mut link = None::<(String, String)>;
+    for event in Parser::new(md) {
+        match event {
+            Event::Start(Tag::List(_)) => depth += 1,
+            Event::End(TagEnd::List(_)) => depth -= 1,
+            Event::Start(Tag::Heading { .. }) if summary.chapters.is_empty() => {
+                heading = Some(String::new());
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some(title) = heading.take().filter(|t| !t.trim().is_empty()) {
+                    let generic = ["summary", "table of contents", "contents"]
+                        .contains(&title.trim().to_lowercase().as_str());
+                    if summary.title.is_none() && !generic {
+                        summary.title = Some(title.trim().to_string());
+                    }
+                }
+            }
+            Event::Start(Tag::Link { dest_url, .. }) => {
+                link = Some((dest_url.to_string(), String::new()));
+            }
+            Event::End(TagEnd::Link) => {
+                if let Some((dest, title)) = link.take() {
+                    let path = dest.split('#').next().unwrap_or_default();
+                    summary.chapters.push(Chapter {
+                        title: title.trim().to_string(),
+                        path: (!path.is_empty()).then(|| dir.join(path)),
+                        level: depth.max(1),
+                    });
+                }
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, title)) = &mut link {
+                    title.push_str(&text);
+                } else if let Some(heading) = &mut heading {
+                    heading.push_str(&text);
+                }
+            }
+            _ => {}
+        }
+    }
+    summary
+}
+
+/// Chapters as nested `<ol>` lists, with `item` giving the content of each
+/// list item.
+pub fn list_html(chapters: &[Chapter], mut item: impl FnMut(usize, &Chapter) -> String) -> String {
+    let mut html = String::from("<ol>");
+    let mut level = 1;
+    let mut open_item = false;
+    for (i, chapter) in chapters.iter().enumerate() {
+        // Lists can only be nested one level at a time.
+        let target = chapter.level.clamp(1, level + 1);
+        while level < target {
+            html.push_str("<ol>");
+            level += 1;
+            open_item = false;
+        }
+        while level > target {
+            html.push_str("</li></ol>");
+            level -= 1;
+        }
+        if open_item {
+            html.push_str("</li>");
+        }
+        html.push_str("<li>");
+        html.push_str(&item(i, chapter));
+        open_item = true;
+    }
+    if open_item {
+        html.push_str("</li>");
+    }
+    while level > 1 {
+        html.push_str("</ol></li>");
+        level -= 1;
+    }
+    html.push_str("</ol>");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chapters_are_nested_links() {
+        let dir = Path::new("book");
+        let summary = parse(
+            "# Summary\n\n[Intro](README.md)\n\n- [Using `x`](use.md#top)\n  - [Draft]()\n- [End](end.md)\n",
+            dir,
+        );
+        assert_eq!(summary.title, None);
+        assert_eq!(
+            summary.chapters,
+            [
+                Chapter {
+                    title: "Intro".into(),
+                    path: Some(dir.join("README.md")),
+                    level: 1
+                },
+                Chapter {
+                    title: "Using x".into(),
+                    path: Some(dir.join("use.md")),
+                    level: 1
+                },
+                Chapter {
+                    title: "Draft".into(),
+                    path: None,
+                    level: 2
+                },
+                Chapter {
+                    title: "End".into(),
+                    path: Some(dir.join("end.md")),
+                    level: 1
+                },
+            ]
+        );
+        assert_eq!(
+            parse("# My Book\n- [A](a.md)\n", dir).title.as_deref(),
+            Some("My Book")
+        );
+    }
+}
@@ -0,0 +1,27 @@
+// Live reload of HTML files served with --reload, which reload on any change
+// of the files they may use. Markdown pages have their own client, which
+// updates them in place.
+(() => {
+	const prefix = document.currentScript.dataset.prefix;
+	let opened = false;
+	let retryDelay = 1000;
+	const connect = () => {
+		const events = new EventSource(prefix + location.pathname.slice(1));
+		events.addEventListener('change', () => location.reload());
+		events.addEventListener('reload', () => location.reload());
+		events.addEventListener('open', () => {
+			// Files may have changed while disconnected.
+			if (opened) location.reload();
+			opened = true;
+			retryDelay = 1000;
+		});
+		// Browsers give up when the server answers with something else,
+		// like when it was restarted without --reload.
+		events.addEventListener('error', () => {
+			if (events.readyState !== EventSource.CLOSED) return;
+			setTimeout(connect, retryDelay);
+			retryDelay = Math.min(retryDelay * 2, 30000);
+		});
+	};
+	connect();
+})();
@@ -0,0 +1,423 @@
+//! Rendering of markdown to wrapped plain text, or to text styled with ANSI
+//! escapes for terminals, for `mdopen render --format text|ansi`.
+
+use crate::markdown;
+use pulldown_cmark::{Event, HeadingLevel, Parser, Tag, TagEnd};
+
+/// Width used when the terminal width is unknown.
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// SGR codes of each style.
+const BOLD: &str = "1";
+const DIM: &str = "2";
+const ITALIC: &str = "3";
+const STRIKE: &str = "9";
+const HEADING: &str = "1;35";
+const CODE: &str = "36";
+const LINK: &str = "4;34";
+
+/// Word being wrapped, with the escapes of its styles.
+#[derive(Default)]
+struct Word {
+    text: String,
+    width: usize,
+    /// Style of the last character, to only switch styles when needed.
+    style: String,
+}
+
+struct Renderer {
+    color: bool,
+    width: usize,
+    out: String,
+    /// Prefixes of the enclosing block quotes and list items.
+    prefixes: Vec<String>,
+    /// Replaces the last prefix on the next line, like a list bullet.
+    marker: Option<String>,
+    blank: bool,
+    /// Styles of the enclosing inline elements.
+    styles: Vec<&'static str>,
+    words: Vec<(Word, bool)>,
+    word: Word,
+    space: bool,
+    lists: Vec<Option<u64>>,
+    links: Vec<String>,
+    code: Option<String>,
+    table: Option<Vec<Vec<String>>>,
+}
+
+impl Renderer {
+    fn new(width: usize, color: bool) -> Self {
+        Renderer {
+            color,
+            width: width.max(20),
+            out: String::new(),
+            prefixes: Vec::new(),
+            marker: None,
+            blank: false,
+            styles: Vec::new(),
+            words: Vec::new(),
+            word: Word::default(),
+            space: false,
+            lists: Vec::new(),
+            links: Vec::new(),
+            code: None,
+            table: None,
+        }
+    }
+
+    fn styled(&self, style: &str, text: &str) -> String {
+        match self.color && !style.is_empty() {
+            true => format!("\x1b[{}m{}\x1b[0m", style, text),
+            false => text.to_string(),
+        }
+    }
+
+    /// Prefix of the next line, using up the marker.
+    fn prefix(&mut self) -> String {
+        let mut prefix = String::new();
+        let last = self.prefixes.len().saturating_sub(1);
+        for (i, p) in self.prefixes.iter().enumerate() {
+            match (&self.marker, i == last) {
+                (Some(marker), true) => prefix.push_str(marker),
+                _ => prefix.push_str(p),
+            }
+        }
+        self.marker = None;
+        prefix
+    }
+
+    fn line(&mut self, line: &str) {
+        let prefix = self.prefix();
+        let line = format!("{}{}", prefix, line);
+        self.out.push_str(line.trim_end_matches(' '));
+        self.out.push('\n');
+    }
+
+    /// Start a block, after a blank line if the previous block needs one.
+    fn start_block(&mut self) {
+        self.flush();
+        if self.blank && !self.out.is_empty() {
+            let prefix: String = self.prefixes.concat();
+            self.out.push_str(prefix.trim_end());
+            self.out.push('\n');
+        }
+        self.blank = false;
+    }
+
+    fn end_word(&mut self) {
+        if self.word.width > 0 {
+            let mut word = std::mem::take(&mut self.word);
+            if self.color && !word.style.is_empty() {
+                word.text.push_str("\x1b[0m");
+            }
+            self.words.push((word, self.space));
+        }
+        self.space = false;
+    }
+
+    fn text(&mut self, text: &str) {
+        let style = self.styles.join(";");
+        for c in text.chars() {
+            if c.is_whitespace() {
+                self.end_word();
+                self.space = true;
+                continue;
+            }
+            if self.color && self.word.style != style {
+                let reset = if self.word.style.is_empty() {
+                    ""
+                } else {
+                    "\x1b[0m"
+                };
+                self.word.text.push_str(reset);
+                if !style.is_empty() {
+                    self.word.text.push_str(&format!("\x1b[{}m", style));
+                }
+                self.word.style = style.clone();
+            }
+            self.word.text.push(c);
+            self.word.width += 1;
+        }
+    }
+
+    /// Wrap the collected words into lines.
+    fn flush(&mut self) {
+        self.end_word();
+        if self.words.is_empty() {
+            return;
+        }
+        let words = std::mem::take(&mut self.words);
+        let available = self
+            .width
+            .saturating_sub(self.prefixes.concat().chars().count())
+            .max(10);
+        let mut line = String::new();
+        let mut width = 0;
+        for (word, space) in words {
+            if width > 0 && width + 1 + word.width > available {
+                self.line(&line);
+                line.clear();
+                width = 0;
+            } else if width > 0 && space {
+                line.push(' ');
+                width += 1;
+            }
+            line.push_str(&word.text);
+            width += word.width;
+        }
+        self.line(&line);
+    }
+
+    fn end_heading(&mut self, level: HeadingLevel) {
+        self.end_word();
+        let width: usize = self
+            .words
+            .iter()
+            .map(|(word, space)| word.width + *space as usize)
+            .sum();
+        self.flush();
+        if !self.color && level <= HeadingLevel::H2 {
+            let underline = if level == HeadingLevel::H1 { "=" } else { "-" };
+            self.line(&underline.repeat(width.min(self.width)));
+        }
+        self.blank = true;
+    }
+
+    fn end_code_block(&mut self, code: &str) {
+        for line in code.trim_end_matches('\n').lines() {
+            let line = format!("    {}", self.styled(CODE, line));
+            self.line(&line);
+        }
+        self.blank = true;
+    }
+
+    fn end_table(&mut self, rows: Vec<Vec<String>>) {
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        let widths: Vec<usize> = (0..columns)
+            .map(|i| {
+                rows.iter()
+                    .filter_map(|row| row.get(i))
+                    .map(|cell| cell.chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        for (i, row) in rows.iter().enumerate() {
+            let cells: Vec<String> = widths
+                .iter()
+                .enumerate()
+                .map(|(j, width)| {
+                    let cell = row.get(j).map_or("", String::as_str);
+                    let padded = format!("{:<1$}", cell, width);
+                    match i {
+                        0 => self.styled(BOLD, &padded),
+                        _ => padded,
+                    }
+                })
+                .collect();
+            self.line(&cells.join("  "));
+            if i == 0 {
+                let rule: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+                let rule = self.styled(DIM, &rule.join("  "));
+                self.line(&rule);
+            }
+        }
+        self.blank = true;
+    }
+
+    fn event(&mut self, event: Event) {
+        if let Some(code) = &mut self.code {
+            match event {
+                Event::Text(text) => code.push_str(&text),
+                Event::End(TagEnd::CodeBlock) => {
+                    let code = self.code.take().unwrap_or_default();
+                    self.end_code_block(&code);
+                }
+                _ => {}
+            }
+            return;
+        }
+        if let Some(rows) = &mut self.table {
+            match event {
+                Event::Start(Tag::TableHead | Tag::TableRow) => rows.push(Vec::new()),
+                Event::Start(Tag::TableCell) => {
+                    if let Some(row) = rows.last_mut() {
+                        row.push(String::new());
+                    }
+                }
+                Event::Text(text) | Event::Code(text) => {
+                    if let Some(cell) = rows.last_mut().and_then(|row| row.last_mut()) {
+                        cell.push_str(&text);
+                    }
+                }
+                Event::End(TagEnd::Table) => {
+                    let rows = self.table.take().unwrap_or_default();
+                    self.end_table(rows);
+                }
+                _ => {}
+            }
+            return;
+        }
+        match event {
+            Event::Start(Tag::Paragraph) => self.start_block(),
+            Event::End(TagEnd::Paragraph) => {
+                self.flush();
+                self.blank = true;
+            }
+            Event::Start(Tag::Heading { level, .. }) => {
+                self.start_block();
+                self.styles.push(if level <= HeadingLevel::H2 {
+                    HEADING
+                } else {
+                    BOLD
+                });
+            }
+            Event::End(TagEnd::Heading(level)) => {
+                self.styles.pop();
+                self.end_heading(level);
+            }
+            Event::Start(Tag::BlockQuote(_)) => {
+                self.start_block();
+                let bar = self.styled(DIM, "│");
+                self.prefixes.push(format!("{} ", bar));
+            }
+            Event::End(TagEnd::BlockQuote(_)) => {
+                self.flush();
+                self.prefixes.pop();
+                self.blank = true;
+            }
+            Event::Start(Tag::CodeBlock(_)) => {
+                self.start_block();
+                self.code = Some(String::new());
+            }
+            Event::Start(Tag::List(start)) => {
+                if self.lists.is_empty() {
+                    self.start_block();
+                } else {
+                    self.flush();
+                }
+                self.lists.push(start);
+            }
+            Event::End(TagEnd::List(_)) => {
+                self.flush();
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    self.blank = true;
+                }
+            }
+            Event::Start(Tag::Item) => {
+                self.flush();
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => "• ".to_string(),
+                };
+                self.prefixes.push(" ".repeat(marker.chars().count()));
+                self.marker = Some(marker);
+            }
+            Event::End(TagEnd::Item) => {
+                self.flush();
+                self.prefixes.pop();
+                self.marker = None;
+            }
+            Event::Start(Tag::FootnoteDefinition(label)) => {
+                self.start_block();
+                let marker = format!("[{}] ", label);
+                self.prefixes.push(" ".repeat(marker.chars().count()));
+                self.marker = Some(marker);
+            }
+            Event::End(TagEnd::FootnoteDefinition) => {
+                self.flush();
+                self.prefixes.pop();
+                self.blank = true;
+            }
+            Event::Start(Tag::Table(_)) => {
+                self.start_block();
+                self.table = Some(Vec::new());
+            }
+            Event::Rule => {
+                self.start_block();
+                let width = self
+                    .width
+                    .saturating_sub(self.prefixes.concat().chars().count());
+                let rule = self.styled(DIM, &"─".repeat(width));
+                self.line(&rule);
+                self.blank = true;
+            }
+            Event::Start(Tag::Emphasis) => self.styles.push(ITALIC),
+            Event::Start(Tag::Strong) => self.styles.push(BOLD),
+            Event::Start(Tag::Strikethrough) => self.styles.push(STRIKE),
+            Event::Start(Tag::Link { dest_url, .. }) => {
+                self.links.push(dest_url.to_string());
+                self.styles.push(LINK);
+            }
+            Event::End(TagEnd::Link) => {
+                self.styles.pop();
+                let url = self.links.pop().unwrap_or_default();
+                // Autolinks already show their address.
+                let shown = self.word.text.ends_with(url.as_str());
+                if !url.starts_with('#') && !shown {
+                    self.styles.push(DIM);
+                    self.text(&format!(" <{}>", url));
+                    self.styles.pop();
+                }
+            }
+            Event::Start(Tag::Image { .. }) => {
+                self.styles.push(DIM);
+                self.text("[image: ");
+            }
+            Event::End(TagEnd::Image) => {
+                self.text("]");
+                self.styles.pop();
+            }
+            Event::End(TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough) => {
+                self.styles.pop();
+            }
+            Event::Code(code) => {
+                self.styles.push(CODE);
+                self.text(&code);
+                self.styles.pop();
+            }
+            Event::Text(text) | Event::InlineMath(text) | Event::DisplayMath(text) => {
+                self.text(&text)
+            }
+            Event::FootnoteReference(label) => self.text(&format!("[{}]", label)),
+            Event::TaskListMarker(checked) => self.text(if checked { "[x] " } else { "[ ] " }),
+            Event::SoftBreak => self.space = true,
+            Event::HardBreak => self.flush(),
+            _ => {}
+        }
+    }
+}
+
+/// Render `md` as text wrapped at `width` columns, styled with ANSI escapes
+/// if `color` is set.
+pub fn render(md: &str, options: &markdown::Options, width: usize, color: bool) -> String {
+    let mut renderer = Renderer::new(width, color);
+    for event in Parser::new_ext(md, markdown::parser_options(options)) {
+        renderer.event(event);
+    }
+    renderer.flush();
+    renderer.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markdown_becomes_text() {
+        let options = markdown::Options::default();
+        let md = "# Title\n\nSome *long* text that needs to be wrapped at twenty columns.\n\n- one\n  - two\n1. [site](https://x.org)\n\n> quoted\n\n```\ncode\n```\n\n| a | bb |\n|---|----|\n| 1 | 2 |\n";
+        assert_eq!(
+            render(md, &options, 20, false),
+            "Title\n=====\n\nSome long text that\nneeds to be wrapped\nat twenty columns.\n\n• one\n  • two\n\n1. site\n   <https://x.org>\n\n│ quoted\n\n    code\n\na  bb\n-  --\n1  2\n"
+        );
+        assert_eq!(
+            render("Some **bold**, `code`\n", &options, 80, true),
+            "Some \x1b[1mbold\x1b[0m, \x1b[36mcode\x1b[0m\n"
+        );
+    }
+}
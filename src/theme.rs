@@ -0,0 +1,361 @@
+//! Conversion of TextMate `.tmTheme` color schemes to highlight.js
+//! stylesheets, so code can be colored like in the user's editor.
+
+use std::fs;
+use std::path::Path;
+
+/// Values of a property list, as far as themes use them.
+#[derive(Debug, PartialEq)]
+enum Plist {
+    Dict(Vec<(String, Plist)>),
+    Array(Vec<Plist>),
+    String(String),
+    Other,
+}
+
+impl Plist {
+    fn get(&self, key: &str) -> Option<&Plist> {
+        match self {
+            Plist::Dict(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Plist::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Parser of the XML property list format.
+struct PlistParser<'a> {
+    rest: &'a str,
+}
+
+impl<'a> PlistParser<'a> {
+    /// The next tag, skipping text, comments and declarations.
+    fn next_tag(&mut self) -> Option<&'a str> {
+        loop {
+            let start = self.rest.find('<')?;
+            self.rest = &self.rest[start..];
+            if let Some(comment) = self.rest.strip_prefix("<!--") {
+                self.rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+                continue;
+            }
+            let end = self.rest.find('>')?;
+            let tag = &self.rest[1..end];
+            self.rest = &self.rest[end + 1..];
+            if !tag.starts_with(['?', '!']) {
+                return Some(tag);
+            }
+        }
+    }
+
+    /// Text up to the closing tag of `name`.
+    fn text(&mut self, name: &str) -> Option<String> {
+        let end = self.rest.find(&format!("</{}>", name))?;
+        let text = unescape_xml(&self.rest[..end]);
+        self.rest = &self.rest[end + name.len() + 3..];
+        Some(text)
+    }
+
+    /// Parse the value starting with `tag`.
+    fn value(&mut self, tag: &str) -> Option<Plist> {
+        match tag {
+            "dict" => {
+                let mut entries = Vec::new();
+                loop {
+                    match self.next_tag()? {
+                        "/dict" => return Some(Plist::Dict(entries)),
+                        "key" => {
+                            let key = self.text("key")?;
+                            let tag = self.next_tag()?;
+                            entries.push((key, self.value(tag)?));
+                        }
+                        _ => return None,
+                    }
+                }
+            }
+            "array" => {
+                let mut items = Vec::new();
+                loop {
+                    match self.next_tag()? {
+                        "/array" => return Some(Plist::Array(items)),
+                        tag => items.push(self.value(tag)?),
+                    }
+                }
+            }
+            "string" => Some(Plist::String(self.text("string")?)),
+            tag if tag.ends_with('/') => Some(Plist::Other),
+            tag => {
+                self.text(tag)?;
+                Some(Plist::Other)
+            }
+        }
+    }
+}
+
+fn parse_plist(source: &str) -> Option<Plist> {
+    let mut parser = PlistParser { rest: source };
+    loop {
+        match parser.next_tag()? {
+            tag if tag.starts_with("plist") => continue,
+            tag => return parser.value(tag),
+        }
+    }
+}
+
+/// highlight.js classes and the TextMate scopes that color them, best first.
+const CLASSES: &[(&str, &[&str])] = &[
+    (".hljs-comment, .hljs-quote", &["comment"]),
+    (
+        ".hljs-keyword, .hljs-selector-tag, .hljs-doctag",
+        &["keyword", "storage"],
+    ),
+    (
+        ".hljs-meta",
+        &["meta.preprocessor", "keyword.control.import", "keyword"],
+    ),
+    (".hljs-string, .hljs-meta .hljs-string", &["string"]),
+    (".hljs-regexp", &["string.regexp", "string"]),
+    (".hljs-number", &["constant.numeric", "constant"]),
+    (".hljs-literal", &["constant.language", "constant"]),
+    (
+        ".hljs-symbol, .hljs-bullet, .hljs-link",
+        &[
+            "constant.other.symbol",
+            "constant.other",
+            "markup.list",
+            "constant",
+        ],
+    ),
+    (
+        ".hljs-built_in, .hljs-type",
+        &[
+            "support.type",
+            "entity.name.type",
+            "support.class",
+            "storage.type",
+        ],
+    ),
+    (
+        ".hljs-title, .hljs-title.function_",
+        &["entity.name.function", "entity.name"],
+    ),
+    (
+        ".hljs-title.class_, .hljs-title.class_.inherited__",
+        &["entity.name.class", "entity.name.type", "entity.name"],
+    ),
+    (
+        ".hljs-attr, .hljs-attribute, .hljs-selector-attr, .hljs-selector-pseudo",
+        &["entity.other.attribute-name"],
+    ),
+    (
+        ".hljs-name, .hljs-selector-id, .hljs-selector-class",
+        &["entity.name.tag", "entity.other.attribute-name"],
+    ),
+    (
+        ".hljs-variable, .hljs-template-variable",
+        &["variable.other", "variable"],
+    ),
+    (".hljs-params", &["variable.parameter", "variable"]),
+    (".hljs-operator", &["keyword.operator", "keyword"]),
+    (".hljs-punctuation", &["punctuation"]),
+    (".hljs-section", &["markup.heading", "entity.name.section"]),
+    (".hljs-addition", &["markup.inserted"]),
+    (".hljs-deletion", &["markup.deleted"]),
+    (".hljs-emphasis", &["markup.italic"]),
+    (".hljs-strong", &["markup.bold"]),
+];
+
+/// Whether `color` is a plain `#rgb` to `#rrggbbaa` hex color.
+fn is_color(color: &str) -> bool {
+    color.strip_prefix('#').is_some_and(|hex| {
+        matches!(hex.len(), 3 | 4 | 6 | 8) && hex.bytes().all(|b| b.is_ascii_hexdigit())
+    })
+}
+
+/// CSS declarations for the `settings` dict of a theme rule.
+fn declarations(settings: &Plist) -> String {
+    let mut css = String::new();
+    for (key, property) in [("foreground", "color"), ("background", "background")] {
+        if let Some(color) = settings.get(key).and_then(Plist::as_str) {
+            if is_color(color) {
+                css.push_str(&format!(" {}: {};", property, color));
+            }
+        }
+    }
+    let font_style = settings
+        .get("fontStyle")
+        .and_then(Plist::as_str)
+        .unwrap_or_default();
+    for (word, declaration) in [
+        ("italic", " font-style: italic;"),
+        ("bold", " font-weight: bold;"),
+        ("underline", " text-decoration: underline;"),
+    ] {
+        if font_style.split_whitespace().any(|w| w == word) {
+            css.push_str(declaration);
+        }
+    }
+    css
+}
+
+/// Index of the rule whose selector best matches `scope`: the longest one
+/// that is `scope` or a parent of it, or else one of its children.
+fn best_rule(selectors: &[(&str, usize)], scope: &str) -> Option<usize> {
+    let is_child = |child: &str, parent: &str| {
+        child
+            .strip_prefix(parent)
+            .is_some_and(|rest| rest.starts_with('.'))
+    };
+    selectors
+        .iter()
+        .filter(|(selector, _)| *selector == scope || is_child(scope, selector))
+        // Later rules win, like in CSS.
+        .max_by_key(|(selector, i)| (selector.len(), *i))
+        .or_else(|| {
+            selectors
+                .iter()
+                .find(|(selector, _)| is_child(selector, scope))
+        })
+        .map(|&(_, i)| i)
+}
+
+/// Convert a `.tmTheme` file to a highlight.js stylesheet.
+pub fn tm_theme_to_css(source: &str) -> Result<String, String> {
+    let plist = parse_plist(source).ok_or("not a property list")?;
+    let Some(Plist::Array(rules)) = plist.get("settings") else {
+        return Err("no settings in theme".to_string());
+    };
+
+    let mut css = String::new();
+    // Selectors of scoped rules, with the index of the rule.
+    let mut selectors = Vec::new();
+    for (i, rule) in rules.iter().enumerate() {
+        let Some(settings) = rule.get("settings") else {
+            continue;
+        };
+        match rule.get("scope").and_then(Plist::as_str) {
+            Some(scope) => {
+                for selector in scope.split(',') {
+                    // Only the innermost scope of `source.python string` counts.
+                    if let Some(last) = selector.split_whitespace().last() {
+                        selectors.push((last.trim_start_matches('-'), i));
+                    }
+                }
+            }
+            None => css.push_str(&format!(".hljs {{{} }}\n", declarations(settings))),
+        }
+    }
+
+    for (classes, scopes) in CLASSES {
+        let rule = scopes.iter().find_map(|scope| best_rule(&selectors, scope));
+        let Some(settings) = rule.and_then(|i| rules[i].get("settings")) else {
+            continue;
+        };
+        let declarations = declarations(settings);
+        if !declarations.is_empty() {
+            css.push_str(&format!("{} {{{} }}\n", classes, declarations));
+        }
+    }
+    Ok(css)
+}
+
+/// Read a highlight.js stylesheet, converting it from a `.tmTheme` if needed.
+pub fn load(path: &Path) -> Result<String, String> {
+    let source = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("css") => Ok(source),
+        _ => tm_theme_to_css(&source),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THEME: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>name</key>
+	<string>Test &amp; Co</string>
+	<key>settings</key>
+	<array>
+		<dict>
+			<key>settings</key>
+			<dict>
+				<key>background</key>
+				<string>#272822</string>
+				<key>foreground</key>
+				<string>#F8F8F2</string>
+			</dict>
+		</dict>
+		<!-- comments are ignored -->
+		<dict>
+			<key>scope</key>
+			<string>comment, punctuation.definition.comment</string>
+			<key>settings</key>
+			<dict>
+				<key>fontStyle</key>
+				<string>italic</string>
+				<key>foreground</key>
+				<string>#75715E</string>
+			</dict>
+		</dict>
+		<dict>
+			<key>scope</key>
+			<string>string</string>
+			<key>settings</key>
+			<dict><key>foreground</key><string>#E6DB74</string></dict>
+		</dict>
+		<dict>
+			<key>scope</key>
+			<string>string.regexp</string>
+			<key>settings</key>
+			<dict><key>foreground</key><string>red;}</string></dict>
+		</dict>
+		<dict>
+			<key>scope</key>
+			<string>constant.numeric.integer</string>
+			<key>settings</key>
+			<dict><key>foreground</key><string>#AE81FF</string></dict>
+		</dict>
+		<dict>
+			<key>scope</key>
+			<string>source.python entity.name.function</string>
+			<key>settings</key>
+			<dict><key>foreground</key><string>#A6E22E</string><key>fontStyle</key><string>bold underline</string></dict>
+		</dict>
+	</array>
+	<key>isDark</key>
+	<true/>
+</dict>
+</plist>
+"#;
+
+    #[test]
+    fn tm_themes_become_highlight_js_styles() {
+        let css = tm_theme_to_css(THEME).unwrap();
+        assert!(css.contains(".hljs { color: #F8F8F2; background: #272822; }"));
+        assert!(css.contains(".hljs-comment, .hljs-quote { color: #75715E; font-style: italic; }"));
+        assert!(css.contains(".hljs-string, .hljs-meta .hljs-string { color: #E6DB74; }"));
+        // A scope more specific than asked for is used when nothing else fits.
+        assert!(css.contains(".hljs-number { color: #AE81FF; }"));
+        assert!(css.contains(".hljs-title, .hljs-title.function_ { color: #A6E22E; font-weight: bold; text-decoration: underline; }"));
+        // Invalid colors are left out, so regexps are not colored like strings.
+        assert!(!css.contains("red"));
+        assert!(!css.contains(".hljs-regexp"));
+    }
+}
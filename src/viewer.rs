@@ -0,0 +1,308 @@
+//! Page bodies for viewing non-markdown files.
+
+use crate::json::{self, Value};
+use pulldown_cmark_escape::escape_html;
+use std::fmt::Write;
+
+/// Replace control characters that have no business in a text view.
+fn sanitize(text: &str) -> String {
+    text.chars()
+        .filter(|&c| c != '\r')
+        .map(|c| {
+            if c.is_control() && c != '\n' && c != '\t' {
+                char::REPLACEMENT_CHARACTER
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// Render text with line numbers and a line wrapping toggle.
+pub fn text(content: &[u8]) -> String {
+    let text = sanitize(&String::from_utf8_lossy(content));
+
+    let mut html = String::from(
+        "<input type='checkbox' id='wrap-lines'><label for='wrap-lines'> Wrap lines</label>\
+         <table class='text-view'>",
+    );
+    for (i, line) in text.lines().enumerate() {
+        let n = i + 1;
+        _ = write!(
+            html,
+            "<tr id='L{n}'><td class='line-number'><a href='#L{n}' data-line='{n}'></a></td><td class='line'>"
+        );
+        _ = escape_html(&mut html, line);
+        html.push_str("</td></tr>");
+    }
+    html.push_str("</table>");
+    html
+}
+
+fn push_json(html: &mut String, value: &Value) {
+    match value {
+        Value::Null => html.push_str("<span class='json-null'>null</span>"),
+        Value::Bool(b) => _ = write!(html, "<span class='json-bool'>{}</span>", b),
+        Value::Number(_) => _ = write!(html, "<span class='json-number'>{}</span>", value),
+        Value::String(_) => {
+            html.push_str("<span class='json-string'>");
+            _ = escape_html(&mut *html, &value.to_string());
+            html.push_str("</span>");
+        }
+        Value::Array(items) if items.is_empty() => html.push_str("[]"),
+        Value::Object(entries) if entries.is_empty() => html.push_str("{}"),
+        Value::Array(items) => {
+            _ = write!(
+                html,
+                "<details open><summary>[<span class='json-count'>{} items</span></summary>",
+                items.len()
+            );
+            for (i, item) in items.iter().enumerate() {
+                html.push_str("<div class='json-entry'>");
+                push_json(html, item);
+                if i + 1 < items.len() {
+                    html.push(',');
+                }
+                html.push_str("</div>");
+            }
+            html.push_str("</details>]");
+        }
+        Value::Object(entries) => {
+            _ = write!(
+                html,
+                "<details open><summary>{{<span class='json-count'>{} keys</span></summary>",
+                entries.len()
+            );
+            for (i, (key, item)) in entries.iter().enumerate() {
+                html.push_str("<div class='json-entry'><span class='json-key'>");
+                _ = escape_html(&mut *html, &Value::String(key.clone()).to_string());
+                html.push_str("</span>: ");
+                push_json(html, item);
+                if i + 1 < entries.len() {
+                    html.push(',');
+                }
+                html.push_str("</div>");
+            }
+            html.push_str("</details>}");
+        }
+    }
+}
+
+/// Render JSON as a collapsible tree, or as text if it does not parse.
+pub fn json(content: &[u8]) -> String {
+    let source = String::from_utf8_lossy(content);
+    match json::parse(&source) {
+        Ok(value) => {
+            let mut html = String::from("<div class='json-view'>");
+            push_json(&mut html, &value);
+            html.push_str("</div>");
+            html
+        }
+        Err(e) => {
+            let mut html = String::from("<p class='view-error'>");
+            _ = escape_html(&mut html, &e.to_string());
+            html.push_str("</p>");
+            html.push_str(&text(content));
+            html
+        }
+    }
+}
+
+fn indentation(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+fn push_yaml_line(html: &mut String, line: &str) {
+    let indent = &line[..indentation(line)];
+    let line = line.trim_start();
+    html.push_str(indent);
+    if line.starts_with('#') {
+        html.push_str("<span class='yaml-comment'>");
+        _ = escape_html(&mut *html, line);
+        html.push_str("</span>");
+        return;
+    }
+    let (item, rest) = match line.strip_prefix("- ") {
+        Some(rest) => ("- ", rest),
+        None => ("", line),
+    };
+    html.push_str(item);
+    match rest
+        .split_once(": ")
+        .or_else(|| rest.strip_suffix(':').map(|k| (k, "")))
+    {
+        Some((key, value)) if !key.starts_with(['"', '\'', '{', '[']) => {
+            html.push_str("<span class='json-key'>");
+            _ = escape_html(&mut *html, key);
+            html.push_str("</span>:");
+            if !value.is_empty() {
+                html.push_str(" <span class='json-string'>");
+                _ = escape_html(&mut *html, value);
+                html.push_str("</span>");
+            }
+        }
+        _ => {
+            html.push_str("<span class='json-string'>");
+            _ = escape_html(&mut *html, rest);
+            html.push_str("</span>");
+        }
+    }
+}
+
+/// Render YAML with highlighted keys, folding blocks by indentation.
+pub fn yaml(content: &[u8]) -> String {
+    let source = sanitize(&String::from_utf8_lossy(content));
+    let lines: Vec<&str> = source.lines().collect();
+
+    let mut html = String::from("<div class='yaml-view'>");
+    // Indentation levels of the currently open blocks.
+    let mut open = Vec::<usize>::new();
+    for (i, line) in lines.iter().enumerate() {
+        if line.trim().is_empty() {
+            html.push_str("<div class='yaml-line'>&nbsp;</div>");
+            continue;
+        }
+        let indent = indentation(line);
+        while open.last().is_some_and(|&level| indent <= level) {
+            open.pop();
+            html.push_str("</details>");
+        }
+        let next_indent = lines[i + 1..]
+            .iter()
+            .find(|l| !l.trim().is_empty())
+            .map(|l| indentation(l));
+        if next_indent.is_some_and(|next| next > indent) {
+            html.push_str("<details open><summary class='yaml-line'>");
+            push_yaml_line(&mut html, line);
+            html.push_str("</summary>");
+            open.push(indent);
+        } else {
+            html.push_str("<div class='yaml-line'>");
+            push_yaml_line(&mut html, line);
+            html.push_str("</div>");
+        }
+    }
+    for _ in open {
+        html.push_str("</details>");
+    }
+    html.push_str("</div>");
+    html
+}
+
+/// Get highlight.js language of a source file extension.
+pub fn source_language(ext: &str) -> Option<&'static str> {
+    let language = match ext.to_ascii_lowercase().as_str() {
+        "rs" => "rust",
+        "py" | "pyi" => "python",
+        "js" | "mjs" | "cjs" | "jsx" => "javascript",
+        "ts" | "tsx" => "typescript",
+        "go" => "go",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "cxx" | "hpp" | "hh" => "cpp",
+        "cs" => "csharp",
+        "java" => "java",
+        "kt" | "kts" => "kotlin",
+        "swift" => "swift",
+        "rb" => "ruby",
+        "php" => "php",
+        "pl" | "pm" => "perl",
+        "lua" => "lua",
+        "r" => "r",
+        "sh" | "bash" | "zsh" => "bash",
+        "ps1" => "powershell",
+        "sql" => "sql",
+        "css" => "css",
+        "scss" => "scss",
+        "less" => "less",
+        "xml" => "xml",
+        "toml" | "ini" | "cfg" => "ini",
+        "mk" => "makefile",
+        "diff" | "patch" => "diff",
+        "graphql" | "gql" => "graphql",
+        "vim" => "vim",
+        "hs" => "haskell",
+        "ex" | "exs" => "elixir",
+        "erl" => "erlang",
+        "clj" => "clojure",
+        "scala" => "scala",
+        "dart" => "dart",
+        "zig" => "zig",
+        "nim" => "nim",
+        "ml" | "mli" => "ocaml",
+        _ => return None,
+    };
+    Some(language)
+}
+
+/// Render source code with a line number gutter of `#L<n>` anchors.
+pub fn source(content: &[u8], language: &str) -> String {
+    let text = sanitize(&String::from_utf8_lossy(content));
+    let lines = text.lines().count().max(1);
+
+    let mut html = String::from("<table class='source-view'><tr><td class='line-numbers'>");
+    for n in 1..=lines {
+        _ = write!(html, "<a id='L{n}' href='#L{n}'>{n}</a>");
+    }
+    _ = write!(
+        html,
+        "</td><td class='source-code'><pre><code class='language-{}'>",
+        language
+    );
+    _ = escape_html(&mut html, &text);
+    html.push_str("</code></pre></td></tr></table>");
+    html
+}
+
+/// Number of bytes on one page of a hexdump.
+pub const HEXDUMP_PAGE_SIZE: u64 = 4096;
+
+/// Render one page of a binary file as offset, hex and ASCII columns.
+///
+/// `chunk` holds the bytes of page `page`, counting from zero.
+pub fn hexdump(chunk: &[u8], page: u64, file_size: u64) -> String {
+    let pages = file_size.div_ceil(HEXDUMP_PAGE_SIZE).max(1);
+    let start = page * HEXDUMP_PAGE_SIZE;
+
+    let mut nav = String::from("<p class='hexdump-nav'>");
+    if page > 0 {
+        _ = write!(nav, "<a href='?page={}'>Previous</a> ", page - 1);
+    }
+    _ = write!(nav, "Page {} of {} ({} bytes)", page + 1, pages, file_size);
+    if page + 1 < pages {
+        _ = write!(nav, " <a href='?page={}'>Next</a>", page + 1);
+    }
+    nav.push_str(" <a href='?download'>Download</a></p>");
+
+    let mut html = nav.clone();
+    html.push_str("<pre class='hexdump'>");
+    for (i, line) in chunk.chunks(16).enumerate() {
+        _ = write!(
+            html,
+            "<span class='hexdump-offset'>{:08x}</span>  ",
+            start + i as u64 * 16
+        );
+        for j in 0..16 {
+            match line.get(j) {
+                Some(b) => _ = write!(html, "{:02x} ", b),
+                None => html.push_str("   "),
+            }
+            if j == 7 {
+                html.push(' ');
+            }
+        }
+        html.push_str(" <span class='hexdump-ascii'>");
+        for &b in line {
+            match b {
+                b'<' => html.push_str("&lt;"),
+                b'>' => html.push_str("&gt;"),
+                b'&' => html.push_str("&amp;"),
+                0x20..=0x7e => html.push(b as char),
+                _ => html.push('.'),
+            }
+        }
+        html.push_str("</span>\n");
+    }
+    html.push_str("</pre>");
+    html.push_str(&nav);
+    html
+}
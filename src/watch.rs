@@ -0,0 +1,180 @@
+//! Polling of the served directory for changed files, for `--reload`.
+//!
+//! Modification times are compared on every poll, which works the same on
+//! every platform and file system, at the cost of walking the tree. Build
+//! directories and editor swap files are skipped, so builds and saves don't
+//! cause a storm of reloads.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// How often the directory is checked for changes by default.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Files and directories never watched, besides the `--watch-ignore` ones.
+pub const DEFAULT_IGNORE: &[&str] = &[
+    ".git",
+    "target",
+    "node_modules",
+    "*.swp",
+    "*.swx",
+    "*~",
+    ".#*",
+    "#*#",
+    // Vim checks that it can write to a directory with this file.
+    "4913",
+];
+
+/// Whether `text` matches `pattern`, where `*` matches anything but `/`, `**`
+/// matches anything and `?` matches a character other than `/`.
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        ['*', '*', rest @ ..] => (0..=text.len()).any(|i| glob_match(rest, &text[i..])),
+        ['*', rest @ ..] => (0..=text.len())
+            .take_while(|&i| i == 0 || text[i - 1] != '/')
+            .any(|i| glob_match(rest, &text[i..])),
+        ['?', rest @ ..] => text.first().is_some_and(|&c| c != '/') && glob_match(rest, &text[1..]),
+        [c, rest @ ..] => text.first() == Some(c) && glob_match(rest, &text[1..]),
+    }
+}
+
+/// Whether the file at `relative`, a path under the watched directory, is
+/// ignored. Patterns with a slash match the whole path, others its name.
+fn is_ignored(relative: &Path, ignore: &[String]) -> bool {
+    let path: Vec<char> = relative
+        .to_string_lossy()
+        .replace('\\', "/")
+        .chars()
+        .collect();
+    let name: Vec<char> = relative
+        .file_name()
+        .map(|name| name.to_string_lossy().chars().collect())
+        .unwrap_or_default();
+    ignore.iter().any(|pattern| {
+        let text = if pattern.contains('/') { &path } else { &name };
+        let pattern: Vec<char> = pattern.trim_start_matches('/').chars().collect();
+        glob_match(&pattern, text)
+    })
+}
+
+/// Modification times of the files under `dir`, skipping hidden and ignored
+/// ones.
+fn scan(root: &Path, dir: &Path, ignore: &[String], files: &mut HashMap<PathBuf, SystemTime>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+        if entry.file_name().to_string_lossy().starts_with('.') || is_ignored(relative, ignore) {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if metadata.is_dir() {
+            scan(root, &path, ignore, files);
+        } else if let Ok(modified) = metadata.modified() {
+            files.insert(path, modified);
+        }
+    }
+}
+
+/// Files added, modified or removed between two scans.
+fn changes(
+    before: &HashMap<PathBuf, SystemTime>,
+    after: &HashMap<PathBuf, SystemTime>,
+) -> Vec<PathBuf> {
+    let mut changed: Vec<PathBuf> = after
+        .iter()
+        .filter(|(path, modified)| before.get(*path) != Some(modified))
+        .map(|(path, _)| path.clone())
+        .chain(
+            before
+                .keys()
+                .filter(|path| !after.contains_key(*path))
+                .cloned(),
+        )
+        .collect();
+    changed.sort();
+    changed
+}
+
+/// Spawn a thread that checks for changes every `interval` and calls
+/// `on_change` with the files changed under `root`, except those matching a
+/// pattern of `ignore`.
+pub fn spawn(
+    root: PathBuf,
+    ignore: Vec<String>,
+    interval: Duration,
+    on_change: impl Fn(&[PathBuf]) + Send + 'static,
+) {
+    thread::spawn(move || {
+        let mut known = HashMap::new();
+        scan(&root, &root, &ignore, &mut known);
+        loop {
+            thread::sleep(interval);
+            let mut files = HashMap::new();
+            scan(&root, &root, &ignore, &mut files);
+            let changed = changes(&known, &files);
+            if !changed.is_empty() {
+                on_change(&changed);
+            }
+            known = files;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn changed_files_are_found() {
+        let dir = env::temp_dir().join("mdopen-test-watch");
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::write(dir.join("a.md"), "a").unwrap();
+        fs::write(dir.join("b.md"), "b").unwrap();
+        fs::write(dir.join(".git/HEAD"), "x").unwrap();
+        fs::create_dir_all(dir.join("sub/target")).unwrap();
+        fs::write(dir.join("sub/target/out.md"), "x").unwrap();
+        fs::write(dir.join("a.md~"), "x").unwrap();
+        fs::write(dir.join("sub/build.log"), "x").unwrap();
+
+        let ignore: Vec<String> = DEFAULT_IGNORE
+            .iter()
+            .map(|pattern| pattern.to_string())
+            .chain(["sub/*.log".to_string()])
+            .collect();
+        let mut before = HashMap::new();
+        scan(&dir, &dir, &ignore, &mut before);
+        assert_eq!(before.len(), 2);
+
+        let mut after = before.clone();
+        after.remove(&dir.join("b.md"));
+        after.insert(dir.join("sub/c.md"), SystemTime::now());
+        after.insert(dir.join("a.md"), SystemTime::UNIX_EPOCH);
+        assert_eq!(
+            changes(&before, &after),
+            [dir.join("a.md"), dir.join("b.md"), dir.join("sub/c.md")]
+        );
+        assert!(changes(&after, &after).is_empty());
+        assert!(is_ignored(
+            Path::new("docs/a/b.log"),
+            &["docs/**/*.log".to_string()]
+        ));
+        assert!(!is_ignored(
+            Path::new("docs/a/b.log"),
+            &["docs/*.log".to_string()]
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,205 @@
+//! Resolution of `[[Page]]` and `[[Page|label]]` links to markdown files.
+
+use crate::markdown;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use pulldown_cmark::{CowStr, Event, Tag, TagEnd, TextMergeStream};
+use pulldown_cmark_escape::{escape_href, escape_html};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Characters to escape in a URL path segment.
+pub const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Collect markdown files under `dir`, skipping hidden directories like `.git`.
+pub fn markdown_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        if path.is_dir() {
+            markdown_files(&path, files);
+        } else if path.extension().is_some_and(|ext| ext == "md") {
+            files.push(path);
+        }
+    }
+}
+
+pub fn url_path(root: &Path, file: &Path) -> String {
+    let relative = file.strip_prefix(root).unwrap_or(file);
+    relative
+        .components()
+        .map(|c| utf8_percent_encode(&c.as_os_str().to_string_lossy(), SEGMENT).to_string())
+        .fold(String::new(), |url, segment| url + "/" + &segment)
+}
+
+/// Find the page named `name`, like `Some Note` or `notes/Some Note`.
+///
+/// Names are matched case-insensitively against file names without the `.md`
+/// extension; a name with slashes must match the end of the path.
+fn find_page<'a>(files: &'a [PathBuf], root: &Path, name: &str) -> Option<&'a PathBuf> {
+    let name = name.trim().trim_end_matches(".md").to_lowercase();
+    let wanted = format!("/{}.md", name);
+    // Prefer the shallowest match, like Obsidian does.
+    files
+        .iter()
+        .filter(|file| {
+            let path = file.strip_prefix(root).unwrap_or(file);
+            let path = format!("/{}", path.to_string_lossy().replace('\\', "/"));
+            path.to_lowercase().ends_with(&wanted)
+        })
+        .min_by_key(|file| file.components().count())
+}
+
+/// Resolve wikilinks in text events into links to the pages under `root`.
+pub fn expand<'a>(events: impl Iterator<Item = Event<'a>>, root: &Path) -> Vec<Event<'a>> {
+    let mut files = None;
+    // Wikilinks are not recognized in code or inside other links.
+    let mut verbatim = 0;
+    let mut out = Vec::new();
+    // Text of a wikilink may be split into several events.
+    for event in TextMergeStream::new(events) {
+        match &event {
+            Event::Start(Tag::CodeBlock(_) | Tag::Link { .. } | Tag::Image { .. }) => verbatim += 1,
+            Event::End(TagEnd::CodeBlock | TagEnd::Link | TagEnd::Image) => verbatim -= 1,
+            Event::Text(text) if verbatim == 0 && text.contains("[[") => {
+                let files = files.get_or_insert_with(|| {
+                    let mut files = Vec::new();
+                    markdown_files(root, &mut files);
+                    files.sort();
+                    files
+                });
+                push_links(&mut out, text, files, root);
+                continue;
+            }
+            _ => {}
+        }
+        out.push(event);
+    }
+    out
+}
+
+fn push_links<'a>(out: &mut Vec<Event<'a>>, text: &str, files: &[PathBuf], root: &Path) {
+    let mut rest = text;
+    while let Some(start) = rest.find("[[") {
+        let Some(len) = rest[start + 2..].find("]]") else {
+            break;
+        };
+        let inner = &rest[start + 2..start + 2 + len];
+        // In `[[[Page]]]`, the link starts at the second bracket.
+        if inner.is_empty() || inner.contains('[') {
+            out.push(Event::Text(CowStr::from(rest[..start + 1].to_string())));
+            rest = &rest[start + 1..];
+            continue;
+        }
+        if start > 0 {
+            out.push(Event::Text(CowStr::from(rest[..start].to_string())));
+        }
+
+        let (target, label) = inner.split_once('|').unwrap_or((inner, inner));
+        let (page, heading) = target.split_once('#').unwrap_or((target, ""));
+        let mut html = String::new();
+        let url = if page.is_empty() {
+            Some(String::new())
+        } else {
+            find_page(files, root, page).map(|file| url_path(root, file))
+        };
+        match url {
+            Some(mut url) => {
+                if !heading.is_empty() {
+                    url.push('#');
+                    url.push_str(&markdown::slugify(heading.trim()));
+                }
+                html.push_str("<a class=\"wikilink\" href=\"");
+                _ = escape_href(&mut html, &url);
+                html.push_str("\">");
+            }
+            None => {
+                html.push_str("<a class=\"wikilink wikilink-missing\" title=\"Page not found\">")
+            }
+        }
+        _ = escape_html(&mut html, label.trim());
+        html.push_str("</a>");
+        out.push(Event::InlineHtml(html.into()));
+
+        rest = &rest[start + 2 + len + 2..];
+    }
+    if !rest.is_empty() {
+        out.push(Event::Text(CowStr::from(rest.to_string())));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn links_are_resolved_to_pages() {
+        let root = env::temp_dir().join("mdopen-test-wikilink");
+        _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("notes/old")).unwrap();
+        fs::create_dir_all(root.join(".trash")).unwrap();
+        for file in [
+            "notes/Some Note.md",
+            "notes/old/some note.md",
+            "Other.md",
+            ".trash/Gone.md",
+        ] {
+            fs::write(root.join(file), "").unwrap();
+        }
+        let mut files = Vec::new();
+        markdown_files(&root, &mut files);
+        files.sort();
+        assert_eq!(files.len(), 3);
+        assert_eq!(
+            find_page(&files, &root, "some note"),
+            Some(&root.join("notes/Some Note.md"))
+        );
+        assert_eq!(
+            find_page(&files, &root, "old/Some Note.md"),
+            Some(&root.join("notes/old/some note.md"))
+        );
+        assert_eq!(find_page(&files, &root, "Gone"), None);
+
+        let html = |text: &str| {
+            let mut out = Vec::new();
+            push_links(&mut out, text, &files, &root);
+            let mut html = String::new();
+            pulldown_cmark::html::push_html(&mut html, out.into_iter());
+            html
+        };
+        assert_eq!(
+            html("See [[Some Note|the note]]."),
+            "See <a class=\"wikilink\" href=\"/notes/Some%20Note.md\">the note</a>."
+        );
+        assert_eq!(
+            html("[[Other#Two Words]] [[#Top]]"),
+            "<a class=\"wikilink\" href=\"/Other.md#two-words\">Other#Two Words</a> <a class=\"wikilink\" href=\"#top\">#Top</a>"
+        );
+        assert_eq!(
+            html("[[Gone]]"),
+            "<a class=\"wikilink wikilink-missing\" title=\"Page not found\">Gone</a>"
+        );
+        assert_eq!(
+            html("[[[Other]]] [[]] [[open"),
+            "[<a class=\"wikilink\" href=\"/Other.md\">Other</a>] [[]] [[open"
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}